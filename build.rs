@@ -0,0 +1,17 @@
+/// Generates the `DiceRoll` gRPC service and its messages from `proto/dice_roll.proto`, using a
+/// vendored `protoc` so building doesn't need one installed.
+fn main() -> Result<(), Box<dyn std::error::Error>> {
+    println!("cargo:rerun-if-changed=proto/dice_roll.proto");
+
+    let mut config = tonic_prost_build::Config::new();
+    config.protoc_executable(protoc_bin_vendored::protoc_bin_path()?);
+    let includes = [
+        std::path::PathBuf::from("proto"),
+        protoc_bin_vendored::include_path()?,
+    ];
+    tonic_prost_build::configure()
+        .build_client(false)
+        .compile_with_config(config, &["proto/dice_roll.proto".into()], &includes)?;
+
+    return Ok(());
+}
@@ -0,0 +1,241 @@
+use axum::{
+    Json,
+    extract::{
+        Query, State,
+        rejection::{JsonRejection, QueryRejection},
+    },
+    http::StatusCode,
+    response::Response,
+};
+use dice_roll::{RollRequest, RollResponse};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use utoipa::ToSchema;
+
+use crate::{
+    AppState, ExpressionRequest,
+    history::{Client, HistoryTags, RollContext},
+    response::{Format, Negotiable, Problem},
+    roll_and_record, roll_notation,
+};
+
+const MAX_BATCH_SIZE: usize = 100;
+
+/// An item of a batch, either dice roll notation, on its own or as an `ExpressionRequest`, or a
+/// `RollRequest`.
+#[derive(Deserialize, ToSchema)]
+#[serde(untagged)]
+pub enum BatchItem {
+    #[schema(example = "1d20 + 2")]
+    Notation(String),
+    Expression(ExpressionRequest),
+    Dice(RollRequest),
+}
+
+/// Outcome of one item of a batch, either its result or the problem that kept it from being rolled.
+#[derive(Serialize, Debug, ToSchema)]
+pub struct BatchResult {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub result: Option<RollResponse>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<Problem>,
+}
+
+#[derive(Serialize, Debug, ToSchema)]
+#[serde(transparent)]
+pub struct BatchResults(pub Vec<BatchResult>);
+
+impl Negotiable for BatchResults {
+    fn into_text(self) -> String {
+        return self
+            .0
+            .into_iter()
+            .map(|item| match (item.result, item.error) {
+                (Some(result), _) => result.into_text(),
+                (None, Some(error)) => format!("error: {}", error.detail),
+                (None, None) => String::new(),
+            })
+            .collect::<Vec<_>>()
+            .join("\n");
+    }
+}
+
+async fn roll_item(
+    state: &AppState,
+    item: Value,
+    context: RollContext,
+) -> Result<RollResponse, Problem> {
+    let item = match serde_json::from_value::<BatchItem>(item) {
+        Ok(item) => item,
+        Err(_) => {
+            return Err(Problem::new(
+                StatusCode::BAD_REQUEST,
+                "INVALID_BATCH_ITEM",
+                "Items must be dice roll notation, an ExpressionRequest or a RollRequest."
+                    .to_string(),
+            ));
+        }
+    };
+    match item {
+        BatchItem::Notation(expression) => {
+            return roll_notation(state, "expression", expression, context).await;
+        }
+        BatchItem::Expression(request) => {
+            return roll_notation(state, "expression", request.expression, context).await;
+        }
+        BatchItem::Dice(roll_request) => {
+            return roll_and_record(state, roll_request, context).await;
+        }
+    }
+}
+
+/// Rolls each item of a batch in turn. Items succeed or fail on their own, the results are in the
+/// same order as the items.
+#[utoipa::path(
+    post,
+    tag = "dice-roll",
+    path = "/v1/rolls:batch",
+    params(HistoryTags),
+    request_body(content = Vec<BatchItem>, example = json!(["1d20 + 2", {"expression": "2d6"}, {"dice": [{"sides": 8}]}])),
+    responses(
+        (status = 200, description = "Results of the batch's items", content(
+            (BatchResults = "application/json"),
+            (String = "text/plain"),
+            (BatchResults = "application/cbor"),
+            (BatchResults = "application/msgpack"),
+        )),
+        (status = 400, description = "Request body is not an array or has too many items", content_type = "application/problem+json", body = Problem),
+        (status = 406, description = "None of the accepted media types are supported", content_type = "application/problem+json", body = Problem),
+    )
+)]
+pub async fn roll_batch(
+    State(state): State<AppState>,
+    format: Format,
+    client: Client,
+    tags: Result<Query<HistoryTags>, QueryRejection>,
+    payload: Result<Json<Vec<Value>>, JsonRejection>,
+) -> Response {
+    let tags = match HistoryTags::from_query(tags) {
+        Ok(tags) => tags,
+        Err(problem) => return format.problem(problem),
+    };
+    let items = match payload {
+        Ok(items) => items.0,
+        Err(e) => return format.problem(Problem::from_json_rejection(e)),
+    };
+    if items.len() > MAX_BATCH_SIZE {
+        return format.problem(Problem::new(
+            StatusCode::BAD_REQUEST,
+            "BATCH_TOO_LARGE",
+            format!(
+                "A batch can have at most {} items, {} provided.",
+                MAX_BATCH_SIZE,
+                items.len()
+            ),
+        ));
+    }
+
+    let mut results = Vec::with_capacity(items.len());
+    for item in items {
+        let context = RollContext {
+            session: tags.session.clone(),
+            campaign: tags.campaign.clone(),
+            ..client.context()
+        };
+        results.push(match roll_item(&state, item, context).await {
+            Ok(result) => BatchResult {
+                result: Some(result),
+                error: None,
+            },
+            Err(problem) => BatchResult {
+                result: None,
+                error: Some(problem),
+            },
+        });
+    }
+
+    return format.respond(StatusCode::OK, BatchResults(results));
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::http::{Method, header};
+    use serde_json::json;
+
+    use super::*;
+    use crate::tests::{request, respond, send, state, with_header};
+
+    fn batch(items: Value) -> axum::http::Request<axum::body::Body> {
+        return request(Method::POST, "/v1/rolls:batch?session=table", Some(items));
+    }
+
+    #[tokio::test]
+    async fn items_succeed_or_fail_on_their_own() {
+        let state = state();
+        let items = json!(["1d20 + 2", {"expression": "2d6"}, {"dice": [{"sides": 8}]}, "2d", {"dice": "many"}]);
+        let (status, body) = send(&state, batch(items)).await;
+        assert_eq!(status, StatusCode::OK);
+
+        let results = body.as_array().unwrap();
+        assert_eq!(results.len(), 5);
+        for result in &results[..3] {
+            assert!(result["result"]["total"].is_i64(), "{}", result);
+            assert!(result.get("error").is_none());
+        }
+        assert_eq!(
+            results[1]["result"]["rolls"][0]["rolls"]
+                .as_array()
+                .unwrap()
+                .len(),
+            2
+        );
+        for result in &results[3..] {
+            assert!(result.get("result").is_none());
+            assert_eq!(result["error"]["status"], 400, "{}", result);
+        }
+        assert_eq!(results[3]["error"]["code"], "INVALID_DICE_SIDES_TOKEN");
+        assert_eq!(results[4]["error"]["code"], "INVALID_BATCH_ITEM");
+
+        // Every item that was rolled is recorded, tagged with the batch's session.
+        let (_, history) = send(
+            &state,
+            request(Method::GET, "/v1/rolls?session=table", None),
+        )
+        .await;
+        assert_eq!(history["rolls"].as_array().unwrap().len(), 3);
+    }
+
+    #[tokio::test]
+    async fn batches_are_arrays_of_limited_size() {
+        let state = state();
+        let items = Value::Array(vec![json!("1d6"); MAX_BATCH_SIZE + 1]);
+        let (status, problem) = send(&state, batch(items)).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert_eq!(problem["code"], "BATCH_TOO_LARGE");
+
+        let (status, _) = send(&state, batch(json!({"expression": "1d6"}))).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+
+        let (status, body) = send(&state, batch(json!([]))).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body, json!([]));
+    }
+
+    #[tokio::test]
+    async fn results_can_be_read_as_text() {
+        let request = with_header(batch(json!(["1d1 + 1", "2d"])), "accept", "text/plain");
+        let (status, headers, body) = respond(&state(), request).await;
+        assert_eq!(status, StatusCode::OK);
+        assert!(
+            headers[header::CONTENT_TYPE]
+                .to_str()
+                .unwrap()
+                .starts_with("text/plain")
+        );
+        let text = String::from_utf8(body).unwrap();
+        let lines = text.lines().collect::<Vec<_>>();
+        assert_eq!(lines.len(), 2, "{}", text);
+        assert_eq!(lines[0], "(1 of 1) + 1 = 2");
+        assert!(lines[1].starts_with("error: "), "{}", text);
+    }
+}
@@ -0,0 +1,1455 @@
+use axum::{
+    Json,
+    extract::{
+        Path, Query, State,
+        rejection::{JsonRejection, QueryRejection},
+    },
+    http::{HeaderMap, StatusCode},
+    response::Response,
+};
+use chrono::{DateTime, SecondsFormat, Utc};
+use dice_roll::RollResponse;
+use rusqlite::{Connection, OptionalExtension, Row, params, params_from_iter, types::Value};
+use serde::{Deserialize, Serialize};
+use utoipa::{IntoParams, ToSchema};
+
+use crate::{
+    AppState, admit_roll,
+    history::{Client, RollContext},
+    parse_notation, record_roll_with,
+    response::{Format, Negotiable, Problem},
+    tenants::hash_key,
+    webhooks::random_hex,
+};
+
+pub const PARTICIPANT_TOKEN_HEADER: &str = "x-participant-token";
+const TOKEN_PREFIX: &str = "drp_";
+const MAX_NAME_LENGTH: usize = 64;
+const DEFAULT_PAGE_SIZE: u32 = 50;
+const MAX_PAGE_SIZE: u32 = 200;
+
+pub const SCHEMA: &str = "
+    CREATE TABLE IF NOT EXISTS campaigns (
+        id TEXT PRIMARY KEY,
+        tenant TEXT,
+        name TEXT NOT NULL,
+        created_at TEXT NOT NULL,
+        closed_at TEXT
+    );
+    CREATE TABLE IF NOT EXISTS campaign_participants (
+        id TEXT PRIMARY KEY,
+        campaign_id TEXT NOT NULL REFERENCES campaigns (id),
+        name TEXT NOT NULL,
+        role TEXT NOT NULL,
+        token_hash TEXT NOT NULL UNIQUE,
+        joined_at TEXT NOT NULL,
+        UNIQUE (campaign_id, name)
+    );
+    CREATE TABLE IF NOT EXISTS campaign_sessions (
+        id TEXT PRIMARY KEY,
+        campaign_id TEXT NOT NULL REFERENCES campaigns (id),
+        name TEXT NOT NULL,
+        started_at TEXT NOT NULL,
+        closed_at TEXT
+    );
+    CREATE TABLE IF NOT EXISTS campaign_rolls (
+        roll_id INTEGER PRIMARY KEY REFERENCES rolls (id),
+        campaign_id TEXT NOT NULL REFERENCES campaigns (id),
+        session_id TEXT REFERENCES campaign_sessions (id),
+        participant_id TEXT NOT NULL REFERENCES campaign_participants (id),
+        visibility TEXT NOT NULL
+    );
+    CREATE INDEX IF NOT EXISTS campaign_rolls_campaign ON campaign_rolls (campaign_id, roll_id);
+";
+
+/// A campaign, keeping the log of every roll made in it.
+#[derive(Serialize, Debug, ToSchema)]
+pub struct Campaign {
+    pub id: String,
+    #[schema(example = "Curse of Strahd")]
+    pub name: String,
+    pub created_at: DateTime<Utc>,
+    /// Set once the campaign is closed, nobody can join it or roll in it after that.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub closed_at: Option<DateTime<Utc>>,
+}
+
+impl Negotiable for Campaign {
+    fn into_text(self) -> String {
+        return match self.closed_at {
+            Some(_) => format!("{} {} closed", self.id, self.name),
+            None => format!("{} {}", self.id, self.name),
+        };
+    }
+}
+
+#[derive(Serialize, Debug, ToSchema)]
+pub struct CampaignList {
+    pub campaigns: Vec<Campaign>,
+}
+
+impl Negotiable for CampaignList {
+    fn into_text(self) -> String {
+        return self
+            .campaigns
+            .into_iter()
+            .map(|campaign| campaign.into_text())
+            .collect::<Vec<_>>()
+            .join("\n");
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum Role {
+    /// Game master, can start and close sessions, close the campaign and see every roll.
+    Gm,
+    #[default]
+    Player,
+}
+
+impl Role {
+    fn as_str(&self) -> &'static str {
+        return match self {
+            Role::Gm => "gm",
+            Role::Player => "player",
+        };
+    }
+
+    fn parse(value: &str) -> Role {
+        return match value {
+            "gm" => Role::Gm,
+            _ => Role::Player,
+        };
+    }
+}
+
+#[derive(Serialize, Clone, Debug, ToSchema)]
+pub struct Participant {
+    pub id: String,
+    #[schema(example = "Alice")]
+    pub name: String,
+    pub role: Role,
+    pub joined_at: DateTime<Utc>,
+}
+
+impl Negotiable for Participant {
+    fn into_text(self) -> String {
+        return format!("{} {} {}", self.id, self.name, self.role.as_str());
+    }
+}
+
+/// A participant who just joined, the token is only returned this once, only its hash is stored.
+#[derive(Serialize, Debug, ToSchema)]
+pub struct JoinedParticipant {
+    #[serde(flatten)]
+    pub participant: Participant,
+    /// Send as the `X-Participant-Token` header to act as this participant.
+    pub token: String,
+}
+
+impl Negotiable for JoinedParticipant {
+    fn into_text(self) -> String {
+        return format!("{} {}", self.participant.into_text(), self.token);
+    }
+}
+
+#[derive(Serialize, Debug, ToSchema)]
+pub struct ParticipantList {
+    pub participants: Vec<Participant>,
+}
+
+impl Negotiable for ParticipantList {
+    fn into_text(self) -> String {
+        return self
+            .participants
+            .into_iter()
+            .map(|participant| participant.into_text())
+            .collect::<Vec<_>>()
+            .join("\n");
+    }
+}
+
+#[derive(Serialize, Debug, ToSchema)]
+pub struct CreatedCampaign {
+    pub campaign: Campaign,
+    /// The campaign's creator, who joins it as its first GM.
+    pub gm: JoinedParticipant,
+}
+
+impl Negotiable for CreatedCampaign {
+    fn into_text(self) -> String {
+        return format!("{}\n{}", self.campaign.into_text(), self.gm.into_text());
+    }
+}
+
+/// A session of play within a campaign.
+#[derive(Serialize, Debug, ToSchema)]
+pub struct CampaignSession {
+    pub id: String,
+    #[schema(example = "Session 12: Into the Amber Temple")]
+    pub name: String,
+    pub started_at: DateTime<Utc>,
+    /// Set once the session is closed, no more rolls can be made in it after that.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub closed_at: Option<DateTime<Utc>>,
+}
+
+impl Negotiable for CampaignSession {
+    fn into_text(self) -> String {
+        return match self.closed_at {
+            Some(_) => format!("{} {} closed", self.id, self.name),
+            None => format!("{} {}", self.id, self.name),
+        };
+    }
+}
+
+#[derive(Serialize, Debug, ToSchema)]
+pub struct CampaignSessionList {
+    pub sessions: Vec<CampaignSession>,
+}
+
+impl Negotiable for CampaignSessionList {
+    fn into_text(self) -> String {
+        return self
+            .sessions
+            .into_iter()
+            .map(|session| session.into_text())
+            .collect::<Vec<_>>()
+            .join("\n");
+    }
+}
+
+/// Who can see what a campaign roll rolled.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum Visibility {
+    /// Everyone.
+    #[default]
+    Public,
+    /// The campaign's GMs and whoever made the roll.
+    Gm,
+    /// Only the campaign's GMs, not even whoever made the roll, e.g. for a hidden perception check.
+    Secret,
+}
+
+impl Visibility {
+    fn as_str(&self) -> &'static str {
+        return match self {
+            Visibility::Public => "public",
+            Visibility::Gm => "gm",
+            Visibility::Secret => "secret",
+        };
+    }
+
+    fn parse(value: &str) -> Visibility {
+        return match value {
+            "gm" => Visibility::Gm,
+            "secret" => Visibility::Secret,
+            _ => Visibility::Public,
+        };
+    }
+
+    /// Whether `viewer` can see what the participant `roller` rolled.
+    fn reveals(&self, roller: &str, viewer: &Participant) -> bool {
+        return match self {
+            Visibility::Public => true,
+            _ if viewer.role == Role::Gm => true,
+            Visibility::Gm => viewer.id == roller,
+            Visibility::Secret => false,
+        };
+    }
+}
+
+/// A roll in a campaign's log.
+#[derive(Serialize, Debug, ToSchema)]
+pub struct CampaignRoll {
+    /// Id of the roll in the history.
+    pub id: i64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub session: Option<String>,
+    pub participant_id: String,
+    #[schema(example = "Alice")]
+    pub participant: String,
+    pub visibility: Visibility,
+    /// Left out of rolls the caller isn't allowed to see.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub expression: Option<String>,
+    /// Left out of rolls the caller isn't allowed to see.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub result: Option<RollResponse>,
+    pub timestamp: DateTime<Utc>,
+}
+
+impl CampaignRoll {
+    /// Hides what was rolled from a viewer the roll's visibility doesn't reveal it to.
+    fn seen_by(mut self, viewer: &Participant) -> CampaignRoll {
+        if !self.visibility.reveals(&self.participant_id, viewer) {
+            self.expression = None;
+            self.result = None;
+        }
+        return self;
+    }
+}
+
+impl Negotiable for CampaignRoll {
+    fn into_text(self) -> String {
+        let timestamp = self.timestamp.to_rfc3339_opts(SecondsFormat::Secs, true);
+        return match (self.expression, self.result) {
+            (Some(expression), Some(result)) => format!(
+                "#{} {} {}: {} = {}",
+                self.id, timestamp, self.participant, expression, result.total
+            ),
+            _ => format!(
+                "#{} {} {} rolled in secret",
+                self.id, timestamp, self.participant
+            ),
+        };
+    }
+}
+
+#[derive(Serialize, Debug, ToSchema)]
+pub struct CampaignRollPage {
+    pub rolls: Vec<CampaignRoll>,
+    /// Pass as `before` to fetch the next page, absent on the last page.
+    pub next: Option<i64>,
+}
+
+impl Negotiable for CampaignRollPage {
+    fn into_text(self) -> String {
+        return self
+            .rolls
+            .into_iter()
+            .map(|roll| roll.into_text())
+            .collect::<Vec<_>>()
+            .join("\n");
+    }
+}
+
+#[derive(Deserialize, Debug, ToSchema)]
+#[serde(deny_unknown_fields)]
+pub struct CreateCampaignRequest {
+    #[schema(example = "Curse of Strahd")]
+    pub name: String,
+    /// Name the creator joins the campaign under, as its GM.
+    #[schema(example = "Dana")]
+    pub gm: String,
+}
+
+#[derive(Deserialize, Debug, ToSchema)]
+#[serde(deny_unknown_fields)]
+pub struct JoinCampaignRequest {
+    #[schema(example = "Alice")]
+    pub name: String,
+    /// Joining as a GM takes the token of one of the campaign's GMs.
+    #[serde(default)]
+    pub role: Role,
+}
+
+#[derive(Deserialize, Debug, ToSchema)]
+#[serde(deny_unknown_fields)]
+pub struct CreateSessionRequest {
+    #[schema(example = "Session 12: Into the Amber Temple")]
+    pub name: String,
+}
+
+#[derive(Deserialize, Debug, ToSchema)]
+#[serde(deny_unknown_fields)]
+pub struct CampaignRollRequest {
+    #[schema(example = "1d20 + 5")]
+    pub expression: String,
+    /// Open session of the campaign to make the roll in.
+    #[serde(default)]
+    pub session: Option<String>,
+    #[serde(default)]
+    pub visibility: Visibility,
+}
+
+#[derive(Deserialize, Debug, Default, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct CampaignRollFilter {
+    /// Only return rolls made in this session.
+    pub session: Option<String>,
+    /// Only return rolls made by this participant.
+    pub participant: Option<String>,
+    /// Only return rolls with an id lower than this one, used for pagination.
+    pub before: Option<i64>,
+    /// Maximum number of rolls to return, defaults to 50 and is capped at 200.
+    pub limit: Option<u32>,
+}
+
+fn now() -> String {
+    return Utc::now().to_rfc3339_opts(SecondsFormat::Micros, true);
+}
+
+fn timestamp(value: Option<String>) -> Option<DateTime<Utc>> {
+    return value
+        .and_then(|value| DateTime::parse_from_rfc3339(&value).ok())
+        .map(|value| value.with_timezone(&Utc));
+}
+
+fn validate_name(field: &str, name: &str) -> Result<(), Problem> {
+    if name.trim().is_empty() || name.len() > MAX_NAME_LENGTH {
+        return Err(Problem::new(
+            StatusCode::BAD_REQUEST,
+            "INVALID_NAME",
+            format!(
+                "{} must be between 1 and {} characters.",
+                field, MAX_NAME_LENGTH
+            ),
+        )
+        .with_field(field.to_string()));
+    }
+
+    return Ok(());
+}
+
+fn campaign_not_found(id: &str) -> Problem {
+    return Problem::new(
+        StatusCode::NOT_FOUND,
+        "CAMPAIGN_NOT_FOUND",
+        format!("No campaign with id {} exists.", id),
+    );
+}
+
+fn session_not_found(id: &str) -> Problem {
+    return Problem::new(
+        StatusCode::NOT_FOUND,
+        "CAMPAIGN_SESSION_NOT_FOUND",
+        format!("No session with id {} exists in this campaign.", id),
+    );
+}
+
+fn campaign(row: &Row) -> Result<Campaign, rusqlite::Error> {
+    return Ok(Campaign {
+        id: row.get(0)?,
+        name: row.get(1)?,
+        created_at: timestamp(row.get(2)?).unwrap_or_default(),
+        closed_at: timestamp(row.get(3)?),
+    });
+}
+
+fn participant(row: &Row) -> Result<Participant, rusqlite::Error> {
+    let role: String = row.get(2)?;
+    return Ok(Participant {
+        id: row.get(0)?,
+        name: row.get(1)?,
+        role: Role::parse(&role),
+        joined_at: timestamp(row.get(3)?).unwrap_or_default(),
+    });
+}
+
+fn campaign_session(row: &Row) -> Result<CampaignSession, rusqlite::Error> {
+    return Ok(CampaignSession {
+        id: row.get(0)?,
+        name: row.get(1)?,
+        started_at: timestamp(row.get(2)?).unwrap_or_default(),
+        closed_at: timestamp(row.get(3)?),
+    });
+}
+
+fn logged_roll(row: &Row) -> Result<CampaignRoll, rusqlite::Error> {
+    let response: String = row.get(3)?;
+    let visibility: String = row.get(7)?;
+    return Ok(CampaignRoll {
+        id: row.get(0)?,
+        timestamp: timestamp(row.get(1)?).unwrap_or_default(),
+        expression: row.get(2)?,
+        result: serde_json::from_str(&response).ok(),
+        session: row.get(4)?,
+        participant_id: row.get(5)?,
+        participant: row.get(6)?,
+        visibility: Visibility::parse(&visibility),
+    });
+}
+
+fn load_campaign(
+    connection: &Connection,
+    id: &str,
+    tenant: Option<&str>,
+) -> Result<Option<Campaign>, rusqlite::Error> {
+    return connection
+        .query_row(
+            "SELECT id, name, created_at, closed_at FROM campaigns WHERE id = ?1 AND tenant IS ?2",
+            params![id, tenant],
+            campaign,
+        )
+        .optional();
+}
+
+fn load_session(
+    connection: &Connection,
+    campaign_id: &str,
+    id: &str,
+) -> Result<Option<CampaignSession>, rusqlite::Error> {
+    return connection
+        .query_row(
+            "SELECT id, name, started_at, closed_at FROM campaign_sessions
+             WHERE id = ?1 AND campaign_id = ?2",
+            params![id, campaign_id],
+            campaign_session,
+        )
+        .optional();
+}
+
+fn participant_token_required() -> Problem {
+    return Problem::new(
+        StatusCode::UNAUTHORIZED,
+        "PARTICIPANT_TOKEN_REQUIRED",
+        "Only the campaign's participants can do this, send the token they joined with as the X-Participant-Token header."
+            .to_string(),
+    );
+}
+
+/// A campaign, along with the participant making the request if they sent their token.
+struct Access {
+    campaign: Campaign,
+    caller: Option<Participant>,
+}
+
+impl Access {
+    /// The participant making the request.
+    fn participant(&self) -> Result<&Participant, Problem> {
+        return self.caller.as_ref().ok_or_else(participant_token_required);
+    }
+
+    /// The participant making the request, who must be one of the campaign's GMs.
+    fn gm(&self) -> Result<&Participant, Problem> {
+        let participant = self.participant()?;
+        if participant.role != Role::Gm {
+            return Err(Problem::new(
+                StatusCode::FORBIDDEN,
+                "GM_REQUIRED",
+                "Only the campaign's GMs can do this.".to_string(),
+            ));
+        }
+
+        return Ok(participant);
+    }
+
+    fn require_open(&self) -> Result<(), Problem> {
+        if self.campaign.closed_at.is_some() {
+            return Err(Problem::new(
+                StatusCode::CONFLICT,
+                "CAMPAIGN_CLOSED",
+                format!("Campaign {} has been closed.", self.campaign.id),
+            ));
+        }
+
+        return Ok(());
+    }
+}
+
+/// Looks up a campaign of the client's tenant, and the participant whose token the request
+/// carries.
+async fn access(
+    state: &AppState,
+    client: &Client,
+    headers: &HeaderMap,
+    id: &str,
+) -> Result<Access, Problem> {
+    let tenant = client.tenant.as_ref().map(|tenant| tenant.id.clone());
+    let token = headers
+        .get(PARTICIPANT_TOKEN_HEADER)
+        .map(|token| hash_key(token.to_str().unwrap_or_default()));
+    let id = id.to_string();
+    let result = state
+        .history
+        .with_connection(move |connection| {
+            let campaign = match load_campaign(connection, &id, tenant.as_deref())? {
+                Some(campaign) => campaign,
+                None => return Ok(Err(campaign_not_found(&id))),
+            };
+            let caller = match token {
+                Some(token_hash) => connection
+                    .query_row(
+                        "SELECT id, name, role, joined_at FROM campaign_participants
+                         WHERE campaign_id = ?1 AND token_hash = ?2",
+                        params![id, token_hash],
+                        participant,
+                    )
+                    .optional()?,
+                None => {
+                    return Ok(Ok(Access {
+                        campaign,
+                        caller: None,
+                    }));
+                }
+            };
+            if caller.is_none() {
+                return Ok(Err(Problem::new(
+                    StatusCode::UNAUTHORIZED,
+                    "INVALID_PARTICIPANT_TOKEN",
+                    "The participant token doesn't belong to anyone in this campaign.".to_string(),
+                )));
+            }
+            return Ok(Ok(Access { campaign, caller }));
+        })
+        .await;
+
+    return result.and_then(|access| access);
+}
+
+/// Like [`access`], for what only the campaign's participants can do.
+async fn participant_access(
+    state: &AppState,
+    client: &Client,
+    headers: &HeaderMap,
+    id: &str,
+) -> Result<(Access, Participant), Problem> {
+    let access = access(state, client, headers, id).await?;
+    let participant = access.participant()?.clone();
+    return Ok((access, participant));
+}
+
+/// Adds a participant to a campaign, returning them along with their token.
+fn insert_participant(
+    connection: &Connection,
+    campaign_id: &str,
+    name: &str,
+    role: Role,
+) -> Result<JoinedParticipant, rusqlite::Error> {
+    let token = format!("{}{}", TOKEN_PREFIX, random_hex(32));
+    let participant = Participant {
+        id: random_hex(8),
+        name: name.to_string(),
+        role,
+        joined_at: Utc::now(),
+    };
+    connection.execute(
+        "INSERT INTO campaign_participants (id, campaign_id, name, role, token_hash, joined_at)
+         VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+        params![
+            participant.id,
+            campaign_id,
+            participant.name,
+            role.as_str(),
+            hash_key(&token),
+            participant
+                .joined_at
+                .to_rfc3339_opts(SecondsFormat::Micros, true),
+        ],
+    )?;
+
+    return Ok(JoinedParticipant { participant, token });
+}
+
+/// Creates a campaign, its creator joins it as its first GM.
+#[utoipa::path(
+    post,
+    tag = "campaigns",
+    path = "/v1/campaigns",
+    request_body = CreateCampaignRequest,
+    responses(
+        (status = 201, description = "Campaign was created", body = CreatedCampaign),
+        (status = 400, description = "Invalid campaign", content_type = "application/problem+json", body = Problem),
+    )
+)]
+pub async fn create_campaign(
+    State(state): State<AppState>,
+    format: Format,
+    client: Client,
+    payload: Result<Json<CreateCampaignRequest>, JsonRejection>,
+) -> Response {
+    let request = match payload {
+        Ok(request) => request.0,
+        Err(e) => return format.problem(Problem::from_json_rejection(e)),
+    };
+    if let Err(problem) =
+        validate_name("name", &request.name).and_then(|_| validate_name("gm", &request.gm))
+    {
+        return format.problem(problem);
+    }
+    let campaign = Campaign {
+        id: random_hex(8),
+        name: request.name,
+        created_at: Utc::now(),
+        closed_at: None,
+    };
+
+    let tenant = client.tenant.as_ref().map(|tenant| tenant.id.clone());
+    let (id, name) = (campaign.id.clone(), campaign.name.clone());
+    let created_at = campaign
+        .created_at
+        .to_rfc3339_opts(SecondsFormat::Micros, true);
+    let result = state
+        .history
+        .with_connection(move |connection| {
+            let transaction = connection.unchecked_transaction()?;
+            transaction.execute(
+                "INSERT INTO campaigns (id, tenant, name, created_at) VALUES (?1, ?2, ?3, ?4)",
+                params![id, tenant, name, created_at],
+            )?;
+            let gm = insert_participant(&transaction, &id, &request.gm, Role::Gm)?;
+            transaction.commit()?;
+            return Ok(gm);
+        })
+        .await;
+    let created = result.map(|gm| CreatedCampaign { campaign, gm });
+    return format.result(StatusCode::CREATED, created);
+}
+
+/// Lists the campaigns the participant tokens belong to, newest first. Tokens of several
+/// campaigns can be sent as separate `X-Participant-Token` headers, or separated by commas.
+#[utoipa::path(
+    get,
+    tag = "campaigns",
+    path = "/v1/campaigns",
+    security(("participant_token" = [])),
+    responses(
+        (status = 200, description = "Campaigns", body = CampaignList),
+        (status = 401, description = "Missing participant token", content_type = "application/problem+json", body = Problem),
+    )
+)]
+pub async fn list_campaigns(
+    State(state): State<AppState>,
+    format: Format,
+    client: Client,
+    headers: HeaderMap,
+) -> Response {
+    let token_hashes = headers
+        .get_all(PARTICIPANT_TOKEN_HEADER)
+        .iter()
+        .flat_map(|tokens| tokens.to_str().unwrap_or_default().split(','))
+        .map(|token| token.trim())
+        .filter(|token| !token.is_empty())
+        .map(|token| Value::Text(hash_key(token)))
+        .collect::<Vec<_>>();
+    if token_hashes.is_empty() {
+        return format.problem(participant_token_required());
+    }
+
+    let tenant = client.tenant.as_ref().map(|tenant| tenant.id.clone());
+    let sql = format!(
+        "SELECT id, name, created_at, closed_at FROM campaigns
+         WHERE tenant IS ? AND id IN (
+             SELECT campaign_id FROM campaign_participants WHERE token_hash IN ({})
+         )
+         ORDER BY created_at DESC",
+        vec!["?"; token_hashes.len()].join(", ")
+    );
+    let values = [vec![tenant.map_or(Value::Null, Value::Text)], token_hashes].concat();
+    let result = state
+        .history
+        .with_connection(move |connection| {
+            let mut statement = connection.prepare(&sql)?;
+            return statement
+                .query_map(params_from_iter(values), campaign)?
+                .collect::<Result<Vec<_>, _>>();
+        })
+        .await;
+
+    let campaigns = result.map(|campaigns| CampaignList { campaigns });
+    return format.result(StatusCode::OK, campaigns);
+}
+
+/// Returns a campaign to one of its participants.
+#[utoipa::path(
+    get,
+    tag = "campaigns",
+    path = "/v1/campaigns/{id}",
+    params(("id" = String, Path, description = "Id of the campaign.")),
+    security(("participant_token" = [])),
+    responses(
+        (status = 200, description = "The campaign", body = Campaign),
+        (status = 401, description = "Missing or invalid participant token", content_type = "application/problem+json", body = Problem),
+        (status = 404, description = "Unknown campaign", content_type = "application/problem+json", body = Problem),
+    )
+)]
+pub async fn get_campaign(
+    State(state): State<AppState>,
+    format: Format,
+    client: Client,
+    headers: HeaderMap,
+    Path(id): Path<String>,
+) -> Response {
+    let campaign = participant_access(&state, &client, &headers, &id).await;
+    return format.result(StatusCode::OK, campaign.map(|(access, _)| access.campaign));
+}
+
+/// Closes a campaign along with its open sessions. Its roll log is kept, but nobody can join it
+/// or roll in it any more.
+#[utoipa::path(
+    post,
+    tag = "campaigns",
+    path = "/v1/campaigns/{id}/close",
+    params(("id" = String, Path, description = "Id of the campaign.")),
+    security(("participant_token" = [])),
+    responses(
+        (status = 200, description = "The closed campaign", body = Campaign),
+        (status = 401, description = "Missing or invalid participant token", content_type = "application/problem+json", body = Problem),
+        (status = 403, description = "Participant is not a GM", content_type = "application/problem+json", body = Problem),
+        (status = 404, description = "Unknown campaign", content_type = "application/problem+json", body = Problem),
+    )
+)]
+pub async fn close_campaign(
+    State(state): State<AppState>,
+    format: Format,
+    client: Client,
+    headers: HeaderMap,
+    Path(id): Path<String>,
+) -> Response {
+    let access = match access(&state, &client, &headers, &id).await {
+        Ok(access) => access,
+        Err(problem) => return format.problem(problem),
+    };
+    if let Err(problem) = access.gm() {
+        return format.problem(problem);
+    }
+
+    let closed_at = now();
+    let campaign_id = id.clone();
+    let result = state
+        .history
+        .with_connection(move |connection| {
+            connection.execute(
+                "UPDATE campaign_sessions SET closed_at = ?2 WHERE campaign_id = ?1 AND closed_at IS NULL",
+                params![campaign_id, closed_at],
+            )?;
+            connection.execute(
+                "UPDATE campaigns SET closed_at = ?2 WHERE id = ?1 AND closed_at IS NULL",
+                params![campaign_id, closed_at],
+            )?;
+            return connection.query_row(
+                "SELECT id, name, created_at, closed_at FROM campaigns WHERE id = ?1",
+                params![campaign_id],
+                campaign,
+            );
+        })
+        .await;
+    return format.result(StatusCode::OK, result);
+}
+
+/// Joins an open campaign, returning the token to act as the new participant with.
+#[utoipa::path(
+    post,
+    tag = "campaigns",
+    path = "/v1/campaigns/{id}/participants",
+    params(("id" = String, Path, description = "Id of the campaign.")),
+    request_body = JoinCampaignRequest,
+    security((), ("participant_token" = [])),
+    responses(
+        (status = 201, description = "Participant joined the campaign", body = JoinedParticipant),
+        (status = 400, description = "Invalid participant", content_type = "application/problem+json", body = Problem),
+        (status = 401, description = "Invalid participant token", content_type = "application/problem+json", body = Problem),
+        (status = 403, description = "Joining as a GM without a GM's token", content_type = "application/problem+json", body = Problem),
+        (status = 404, description = "Unknown campaign", content_type = "application/problem+json", body = Problem),
+        (status = 409, description = "Campaign is closed or the name is taken", content_type = "application/problem+json", body = Problem),
+    )
+)]
+pub async fn join_campaign(
+    State(state): State<AppState>,
+    format: Format,
+    client: Client,
+    headers: HeaderMap,
+    Path(id): Path<String>,
+    payload: Result<Json<JoinCampaignRequest>, JsonRejection>,
+) -> Response {
+    let request = match payload {
+        Ok(request) => request.0,
+        Err(e) => return format.problem(Problem::from_json_rejection(e)),
+    };
+    if let Err(problem) = validate_name("name", &request.name) {
+        return format.problem(problem);
+    }
+    let access = match access(&state, &client, &headers, &id).await {
+        Ok(access) => access,
+        Err(problem) => return format.problem(problem),
+    };
+    if let Err(problem) = access.require_open() {
+        return format.problem(problem);
+    }
+    if request.role == Role::Gm
+        && let Err(problem) = access.gm()
+    {
+        return format.problem(problem);
+    }
+
+    let result = state
+        .history
+        .with_connection(move |connection| {
+            let taken = connection
+                .query_row(
+                    "SELECT 1 FROM campaign_participants WHERE campaign_id = ?1 AND name = ?2",
+                    params![id, request.name],
+                    |_| Ok(()),
+                )
+                .optional()?;
+            if taken.is_some() {
+                return Ok(Err(Problem::new(
+                    StatusCode::CONFLICT,
+                    "PARTICIPANT_NAME_TAKEN",
+                    format!("{} has already joined this campaign.", request.name),
+                )
+                .with_field("name".to_string())));
+            }
+            return Ok(Ok(insert_participant(
+                connection,
+                &id,
+                &request.name,
+                request.role,
+            )?));
+        })
+        .await;
+    return format.result(StatusCode::CREATED, result.and_then(|joined| joined));
+}
+
+/// Lists the participants of a campaign to one of them, in the order they joined.
+#[utoipa::path(
+    get,
+    tag = "campaigns",
+    path = "/v1/campaigns/{id}/participants",
+    params(("id" = String, Path, description = "Id of the campaign.")),
+    security(("participant_token" = [])),
+    responses(
+        (status = 200, description = "Participants", body = ParticipantList),
+        (status = 401, description = "Missing or invalid participant token", content_type = "application/problem+json", body = Problem),
+        (status = 404, description = "Unknown campaign", content_type = "application/problem+json", body = Problem),
+    )
+)]
+pub async fn list_participants(
+    State(state): State<AppState>,
+    format: Format,
+    client: Client,
+    headers: HeaderMap,
+    Path(id): Path<String>,
+) -> Response {
+    if let Err(problem) = participant_access(&state, &client, &headers, &id).await {
+        return format.problem(problem);
+    }
+    let result = state
+        .history
+        .with_connection(move |connection| {
+            let mut statement = connection.prepare(
+                "SELECT id, name, role, joined_at FROM campaign_participants
+                 WHERE campaign_id = ?1 ORDER BY joined_at",
+            )?;
+            return statement
+                .query_map(params![id], participant)?
+                .collect::<Result<Vec<_>, _>>();
+        })
+        .await;
+
+    let participants = result.map(|participants| ParticipantList { participants });
+    return format.result(StatusCode::OK, participants);
+}
+
+/// Starts a session of play in an open campaign.
+#[utoipa::path(
+    post,
+    tag = "campaigns",
+    path = "/v1/campaigns/{id}/sessions",
+    params(("id" = String, Path, description = "Id of the campaign.")),
+    request_body = CreateSessionRequest,
+    security(("participant_token" = [])),
+    responses(
+        (status = 201, description = "Session was started", body = CampaignSession),
+        (status = 400, description = "Invalid session", content_type = "application/problem+json", body = Problem),
+        (status = 401, description = "Missing or invalid participant token", content_type = "application/problem+json", body = Problem),
+        (status = 403, description = "Participant is not a GM", content_type = "application/problem+json", body = Problem),
+        (status = 404, description = "Unknown campaign", content_type = "application/problem+json", body = Problem),
+        (status = 409, description = "Campaign is closed", content_type = "application/problem+json", body = Problem),
+    )
+)]
+pub async fn create_session(
+    State(state): State<AppState>,
+    format: Format,
+    client: Client,
+    headers: HeaderMap,
+    Path(id): Path<String>,
+    payload: Result<Json<CreateSessionRequest>, JsonRejection>,
+) -> Response {
+    let request = match payload {
+        Ok(request) => request.0,
+        Err(e) => return format.problem(Problem::from_json_rejection(e)),
+    };
+    if let Err(problem) = validate_name("name", &request.name) {
+        return format.problem(problem);
+    }
+    let access = match access(&state, &client, &headers, &id).await {
+        Ok(access) => access,
+        Err(problem) => return format.problem(problem),
+    };
+    if let Err(problem) = access.require_open().and_then(|_| access.gm().map(|_| ())) {
+        return format.problem(problem);
+    }
+    let session = CampaignSession {
+        id: random_hex(8),
+        name: request.name,
+        started_at: Utc::now(),
+        closed_at: None,
+    };
+
+    let (session_id, name) = (session.id.clone(), session.name.clone());
+    let started_at = session
+        .started_at
+        .to_rfc3339_opts(SecondsFormat::Micros, true);
+    let result = state
+        .history
+        .with_connection(move |connection| {
+            return connection.execute(
+                "INSERT INTO campaign_sessions (id, campaign_id, name, started_at) VALUES (?1, ?2, ?3, ?4)",
+                params![session_id, id, name, started_at],
+            );
+        })
+        .await;
+    return format.result(StatusCode::CREATED, result.map(|_| session));
+}
+
+/// Lists the sessions of a campaign to one of its participants, in the order they were started.
+#[utoipa::path(
+    get,
+    tag = "campaigns",
+    path = "/v1/campaigns/{id}/sessions",
+    params(("id" = String, Path, description = "Id of the campaign.")),
+    security(("participant_token" = [])),
+    responses(
+        (status = 200, description = "Sessions", body = CampaignSessionList),
+        (status = 401, description = "Missing or invalid participant token", content_type = "application/problem+json", body = Problem),
+        (status = 404, description = "Unknown campaign", content_type = "application/problem+json", body = Problem),
+    )
+)]
+pub async fn list_sessions(
+    State(state): State<AppState>,
+    format: Format,
+    client: Client,
+    headers: HeaderMap,
+    Path(id): Path<String>,
+) -> Response {
+    if let Err(problem) = participant_access(&state, &client, &headers, &id).await {
+        return format.problem(problem);
+    }
+    let result = state
+        .history
+        .with_connection(move |connection| {
+            let mut statement = connection.prepare(
+                "SELECT id, name, started_at, closed_at FROM campaign_sessions
+                 WHERE campaign_id = ?1 ORDER BY started_at",
+            )?;
+            return statement
+                .query_map(params![id], campaign_session)?
+                .collect::<Result<Vec<_>, _>>();
+        })
+        .await;
+
+    let sessions = result.map(|sessions| CampaignSessionList { sessions });
+    return format.result(StatusCode::OK, sessions);
+}
+
+/// Closes a session of a campaign, no more rolls can be made in it.
+#[utoipa::path(
+    post,
+    tag = "campaigns",
+    path = "/v1/campaigns/{id}/sessions/{session}/close",
+    params(
+        ("id" = String, Path, description = "Id of the campaign."),
+        ("session" = String, Path, description = "Id of the session."),
+    ),
+    security(("participant_token" = [])),
+    responses(
+        (status = 200, description = "The closed session", body = CampaignSession),
+        (status = 401, description = "Missing or invalid participant token", content_type = "application/problem+json", body = Problem),
+        (status = 403, description = "Participant is not a GM", content_type = "application/problem+json", body = Problem),
+        (status = 404, description = "Unknown campaign or session", content_type = "application/problem+json", body = Problem),
+    )
+)]
+pub async fn close_session(
+    State(state): State<AppState>,
+    format: Format,
+    client: Client,
+    headers: HeaderMap,
+    Path((id, session_id)): Path<(String, String)>,
+) -> Response {
+    let access = match access(&state, &client, &headers, &id).await {
+        Ok(access) => access,
+        Err(problem) => return format.problem(problem),
+    };
+    if let Err(problem) = access.gm() {
+        return format.problem(problem);
+    }
+
+    let result = state
+        .history
+        .with_connection(move |connection| {
+            connection.execute(
+                "UPDATE campaign_sessions SET closed_at = ?3
+                 WHERE id = ?1 AND campaign_id = ?2 AND closed_at IS NULL",
+                params![session_id, id, now()],
+            )?;
+            let session = load_session(connection, &id, &session_id)?;
+            return Ok(session.ok_or_else(|| session_not_found(&session_id)));
+        })
+        .await;
+    return format.result(StatusCode::OK, result.and_then(|session| session));
+}
+
+/// Rolls dice notation as a participant of an open campaign, adding it to the campaign's roll log.
+///
+/// GM-only and secret rolls are kept out of the roll history listing and webhooks, see the
+/// `visibility` of the roll for who can see them in the campaign's log.
+#[utoipa::path(
+    post,
+    tag = "campaigns",
+    path = "/v1/campaigns/{id}/rolls",
+    params(("id" = String, Path, description = "Id of the campaign.")),
+    request_body = CampaignRollRequest,
+    security(("participant_token" = [])),
+    responses(
+        (status = 200, description = "Dice were rolled and logged, what was rolled is left out of secret rolls", body = CampaignRoll),
+        (status = 400, description = "Invalid expression", content_type = "application/problem+json", body = Problem),
+        (status = 401, description = "Missing or invalid participant token", content_type = "application/problem+json", body = Problem),
+        (status = 404, description = "Unknown campaign or session", content_type = "application/problem+json", body = Problem),
+        (status = 409, description = "Campaign or session is closed", content_type = "application/problem+json", body = Problem),
+    )
+)]
+pub async fn campaign_roll(
+    State(state): State<AppState>,
+    format: Format,
+    client: Client,
+    headers: HeaderMap,
+    Path(id): Path<String>,
+    payload: Result<Json<CampaignRollRequest>, JsonRejection>,
+) -> Response {
+    let request = match payload {
+        Ok(request) => request.0,
+        Err(e) => return format.problem(Problem::from_json_rejection(e)),
+    };
+    let roll_request = match parse_notation("expression", &request.expression) {
+        Ok(roll_request) => roll_request,
+        Err(problem) => return format.problem(problem),
+    };
+    let roller = match participant_access(&state, &client, &headers, &id).await {
+        Ok((access, roller)) => match access.require_open() {
+            Ok(()) => roller,
+            Err(problem) => return format.problem(problem),
+        },
+        Err(problem) => return format.problem(problem),
+    };
+    if let Some(session_id) = request.session.clone() {
+        let campaign_id = id.clone();
+        let session = state
+            .history
+            .with_connection(move |connection| load_session(connection, &campaign_id, &session_id))
+            .await;
+        let problem = match session {
+            Ok(Some(session)) if session.closed_at.is_none() => None,
+            Ok(Some(session)) => Some(Problem::new(
+                StatusCode::CONFLICT,
+                "CAMPAIGN_SESSION_CLOSED",
+                format!("Session {} has been closed.", session.id),
+            )),
+            Ok(None) => Some(session_not_found(
+                request.session.as_deref().unwrap_or_default(),
+            )),
+            Err(problem) => Some(problem),
+        };
+        if let Some(problem) = problem {
+            return format.problem(problem.with_field("session".to_string()));
+        }
+    }
+
+    let context = RollContext {
+        session: request.session.clone(),
+        campaign: Some(id.clone()),
+        player: Some(roller.name.clone()),
+        expression: Some(request.expression.clone()),
+        hidden: request.visibility != Visibility::Public,
+        ..client.context()
+    };
+    if let Err(problem) = admit_roll(&state, &context, &roll_request) {
+        return format.problem(problem);
+    }
+    let result = match roll_request.roll_dice() {
+        Ok(result) => result,
+        Err(e) => return format.problem(Problem::from(e)),
+    };
+    let (session, participant_id) = (request.session.clone(), roller.id.clone());
+    let visibility = request.visibility;
+    let log = move |connection: &Connection, roll_id: i64| {
+        connection.execute(
+            "INSERT INTO campaign_rolls (roll_id, campaign_id, session_id, participant_id, visibility)
+             VALUES (?1, ?2, ?3, ?4, ?5)",
+            params![roll_id, id, session, participant_id, visibility.as_str()],
+        )?;
+        return Ok(());
+    };
+    let recorded = match record_roll_with(&state, context, &roll_request, &result, log).await {
+        Ok(recorded) => recorded,
+        Err(problem) => return format.problem(problem),
+    };
+
+    let roll = CampaignRoll {
+        id: recorded.id,
+        session: request.session,
+        participant_id: roller.id.clone(),
+        participant: roller.name.clone(),
+        visibility,
+        expression: Some(request.expression),
+        result: Some(result),
+        timestamp: recorded.timestamp,
+    };
+    return format.respond(StatusCode::OK, roll.seen_by(&roller));
+}
+
+/// Lists the rolls made in a campaign to one of its participants, newest first.
+///
+/// Every roll is listed, but what was rolled is left out of GM-only and secret rolls the
+/// participant isn't allowed to see.
+#[utoipa::path(
+    get,
+    tag = "campaigns",
+    path = "/v1/campaigns/{id}/rolls",
+    params(("id" = String, Path, description = "Id of the campaign."), CampaignRollFilter),
+    security(("participant_token" = [])),
+    responses(
+        (status = 200, description = "A page of the campaign's roll log", body = CampaignRollPage),
+        (status = 400, description = "Invalid filter", content_type = "application/problem+json", body = Problem),
+        (status = 401, description = "Missing or invalid participant token", content_type = "application/problem+json", body = Problem),
+        (status = 404, description = "Unknown campaign", content_type = "application/problem+json", body = Problem),
+    )
+)]
+pub async fn list_campaign_rolls(
+    State(state): State<AppState>,
+    format: Format,
+    client: Client,
+    headers: HeaderMap,
+    Path(id): Path<String>,
+    filter: Result<Query<CampaignRollFilter>, QueryRejection>,
+) -> Response {
+    let filter = match filter {
+        Ok(filter) => filter.0,
+        Err(e) => return format.problem(Problem::from_query_rejection(e)),
+    };
+    let viewer = match participant_access(&state, &client, &headers, &id).await {
+        Ok((_, viewer)) => viewer,
+        Err(problem) => return format.problem(problem),
+    };
+
+    let limit = filter
+        .limit
+        .unwrap_or(DEFAULT_PAGE_SIZE)
+        .clamp(1, MAX_PAGE_SIZE);
+    let mut sql = "SELECT r.id, r.created_at, r.expression, r.response, c.session_id, c.participant_id, p.name, c.visibility
+         FROM campaign_rolls c
+         JOIN rolls r ON r.id = c.roll_id
+         JOIN campaign_participants p ON p.id = c.participant_id
+         WHERE c.campaign_id = ?"
+        .to_string();
+    let mut values = vec![Value::Text(id)];
+    let text_filters = [
+        ("c.session_id", filter.session),
+        ("c.participant_id", filter.participant),
+    ];
+    for (column, value) in text_filters {
+        if let Some(value) = value {
+            sql.push_str(&format!(" AND {} = ?", column));
+            values.push(Value::Text(value));
+        }
+    }
+    if let Some(before) = filter.before {
+        sql.push_str(" AND c.roll_id < ?");
+        values.push(Value::Integer(before));
+    }
+    // Fetch one extra row to find out whether there is another page.
+    sql.push_str(&format!(" ORDER BY c.roll_id DESC LIMIT {}", limit + 1));
+
+    let rolls = state
+        .history
+        .with_connection(move |connection| {
+            let mut statement = connection.prepare(&sql)?;
+            let rows = statement.query_map(params_from_iter(values), logged_roll)?;
+            return rows.collect::<Result<Vec<_>, _>>();
+        })
+        .await;
+    let mut rolls = match rolls {
+        Ok(rolls) => rolls,
+        Err(problem) => return format.problem(problem),
+    };
+
+    let next = if rolls.len() > limit as usize {
+        rolls.truncate(limit as usize);
+        rolls.last().map(|roll| roll.id)
+    } else {
+        None
+    };
+    let rolls = rolls
+        .into_iter()
+        .map(|roll| roll.seen_by(&viewer))
+        .collect();
+    return format.respond(StatusCode::OK, CampaignRollPage { rolls, next });
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::http::Method;
+    use serde_json::{Value, json};
+
+    use super::*;
+    use crate::tests::{request, send, state, with_header};
+
+    fn as_participant(
+        method: Method,
+        uri: &str,
+        token: &str,
+        body: Option<Value>,
+    ) -> axum::http::Request<axum::body::Body> {
+        return with_header(request(method, uri, body), PARTICIPANT_TOKEN_HEADER, token);
+    }
+
+    /// Creates a campaign with a GM and two players, returning its id and their tokens.
+    async fn campaign(state: &AppState) -> (String, [String; 3]) {
+        let (status, created) = send(
+            state,
+            request(
+                Method::POST,
+                "/v1/campaigns",
+                Some(json!({"name": "Curse of Strahd", "gm": "Dana"})),
+            ),
+        )
+        .await;
+        assert_eq!(status, StatusCode::CREATED);
+        let id = created["campaign"]["id"].as_str().unwrap().to_string();
+        let mut tokens = vec![created["gm"]["token"].as_str().unwrap().to_string()];
+        for name in ["Alice", "Bob"] {
+            let uri = format!("/v1/campaigns/{}/participants", id);
+            let join = request(Method::POST, &uri, Some(json!({"name": name})));
+            let (status, joined) = send(state, join).await;
+            assert_eq!(status, StatusCode::CREATED);
+            tokens.push(joined["token"].as_str().unwrap().to_string());
+        }
+
+        return (id, tokens.try_into().unwrap());
+    }
+
+    fn participant(id: &str, role: Role) -> Participant {
+        return Participant {
+            id: id.to_string(),
+            name: id.to_string(),
+            role,
+            joined_at: Utc::now(),
+        };
+    }
+
+    #[test]
+    fn visibility_decides_who_sees_a_roll() {
+        let gm = participant("dana", Role::Gm);
+        let roller = participant("alice", Role::Player);
+        let other = participant("bob", Role::Player);
+        let seen = [
+            (Visibility::Public, [true, true, true]),
+            (Visibility::Gm, [true, true, false]),
+            (Visibility::Secret, [true, false, false]),
+        ];
+        for (visibility, expected) in seen {
+            for (viewer, expected) in [&gm, &roller, &other].into_iter().zip(expected) {
+                let roll = CampaignRoll {
+                    id: 1,
+                    session: None,
+                    participant_id: roller.id.clone(),
+                    participant: roller.name.clone(),
+                    visibility,
+                    expression: Some("1d20".to_string()),
+                    result: None,
+                    timestamp: Utc::now(),
+                };
+                let roll = roll.seen_by(viewer);
+                assert_eq!(roll.expression.is_some(), expected, "{:?}", visibility);
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn rolls_are_logged_with_what_each_participant_can_see() {
+        let state = state();
+        let (id, [gm, alice, bob]) = campaign(&state).await;
+        let rolls = format!("/v1/campaigns/{}/rolls", id);
+        let mut logged = Vec::new();
+        for visibility in ["public", "gm", "secret"] {
+            let roll = json!({"expression": "1d20", "visibility": visibility});
+            let (status, roll) = send(
+                &state,
+                as_participant(Method::POST, &rolls, &alice, Some(roll)),
+            )
+            .await;
+            assert_eq!(status, StatusCode::OK);
+            assert_eq!(roll["visibility"], visibility);
+            assert_eq!(roll.get("result").is_some(), visibility != "secret");
+            logged.push(roll);
+        }
+
+        let seen = [
+            (&gm, [true, true, true]),
+            (&alice, [true, true, false]),
+            (&bob, [true, false, false]),
+        ];
+        for (token, expected) in seen {
+            let (status, page) =
+                send(&state, as_participant(Method::GET, &rolls, token, None)).await;
+            assert_eq!(status, StatusCode::OK);
+            // Newest first.
+            let listed = page["rolls"].as_array().unwrap().iter().rev();
+            for ((roll, logged), expected) in listed.zip(&logged).zip(expected) {
+                assert_eq!(roll["id"], logged["id"]);
+                assert_eq!(roll["timestamp"], logged["timestamp"]);
+                assert_eq!(roll.get("expression").is_some(), expected);
+                assert_eq!(roll.get("result").is_some(), expected);
+            }
+        }
+
+        let (status, history) = send(&state, request(Method::GET, "/v1/rolls", None)).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(history["rolls"].as_array().unwrap().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn campaigns_are_only_shown_to_their_participants() {
+        let state = state();
+        let (id, [gm, alice, _]) = campaign(&state).await;
+        let (other, [other_gm, _, _]) = campaign(&state).await;
+        for uri in [
+            format!("/v1/campaigns/{}", id),
+            format!("/v1/campaigns/{}/participants", id),
+            format!("/v1/campaigns/{}/sessions", id),
+            format!("/v1/campaigns/{}/rolls", id),
+        ] {
+            let (status, problem) = send(&state, request(Method::GET, &uri, None)).await;
+            assert_eq!(status, StatusCode::UNAUTHORIZED, "{}", uri);
+            assert_eq!(problem["code"], "PARTICIPANT_TOKEN_REQUIRED");
+            let (status, problem) =
+                send(&state, as_participant(Method::GET, &uri, &other_gm, None)).await;
+            assert_eq!(status, StatusCode::UNAUTHORIZED, "{}", uri);
+            assert_eq!(problem["code"], "INVALID_PARTICIPANT_TOKEN");
+            let (status, _) = send(&state, as_participant(Method::GET, &uri, &alice, None)).await;
+            assert_eq!(status, StatusCode::OK, "{}", uri);
+        }
+
+        let (status, problem) = send(&state, request(Method::GET, "/v1/campaigns", None)).await;
+        assert_eq!(status, StatusCode::UNAUTHORIZED);
+        assert_eq!(problem["code"], "PARTICIPANT_TOKEN_REQUIRED");
+        let listed = |body: Value| {
+            return body["campaigns"]
+                .as_array()
+                .unwrap()
+                .iter()
+                .map(|campaign| campaign["id"].as_str().unwrap().to_string())
+                .collect::<Vec<_>>();
+        };
+        let (_, campaigns) = send(
+            &state,
+            as_participant(Method::GET, "/v1/campaigns", &alice, None),
+        )
+        .await;
+        assert_eq!(listed(campaigns), [id.as_str()]);
+        let tokens = format!("{}, {}", gm, other_gm);
+        let (_, campaigns) = send(
+            &state,
+            as_participant(Method::GET, "/v1/campaigns", &tokens, None),
+        )
+        .await;
+        let mut campaigns = listed(campaigns);
+        campaigns.sort();
+        let mut expected = vec![id, other];
+        expected.sort();
+        assert_eq!(campaigns, expected);
+    }
+}
@@ -0,0 +1,340 @@
+use std::collections::BTreeMap;
+
+use clap::{ArgMatches, parser::ValueSource};
+use serde::Deserialize;
+
+use crate::tenants::Feature;
+
+/// Settings read from a TOML file. Each setting stands in for the default value of a command
+/// line argument, so arguments, and their `DICE_ROLL_*` environment variables, override it.
+#[derive(Deserialize, Debug, Default)]
+#[serde(deny_unknown_fields)]
+pub struct Config {
+    #[serde(default)]
+    server: ServerConfig,
+    #[serde(default)]
+    limits: LimitsConfig,
+    #[serde(default)]
+    cors: CorsConfig,
+    #[serde(default)]
+    logging: LoggingConfig,
+    #[serde(default)]
+    receipts: ReceiptsConfig,
+    #[serde(default)]
+    discord: DiscordConfig,
+    #[serde(default)]
+    slack: SlackConfig,
+    #[serde(default)]
+    mattermost: MattermostConfig,
+    #[serde(default)]
+    webhooks: WebhooksConfig,
+    /// Features turned on, or off, for everyone.
+    #[serde(default)]
+    features: BTreeMap<Feature, bool>,
+}
+
+#[derive(Deserialize, Debug, Default)]
+#[serde(deny_unknown_fields)]
+struct ServerConfig {
+    host: Option<String>,
+    port: Option<u16>,
+    grpc_port: Option<u16>,
+    unix_socket: Option<String>,
+    tls_cert: Option<String>,
+    tls_key: Option<String>,
+    shutdown_timeout: Option<u64>,
+    database: Option<String>,
+    admin_token: Option<String>,
+    #[serde(default)]
+    trusted_proxies: Vec<String>,
+}
+
+#[derive(Deserialize, Debug, Default)]
+#[serde(deny_unknown_fields)]
+struct LimitsConfig {
+    rate_limit: Option<u32>,
+    api_key_rate_limit: Option<u32>,
+    dice_budget: Option<u32>,
+    max_body_size: Option<usize>,
+}
+
+#[derive(Deserialize, Debug, Default)]
+#[serde(deny_unknown_fields)]
+struct CorsConfig {
+    #[serde(default)]
+    origins: Vec<String>,
+}
+
+#[derive(Deserialize, Debug, Default)]
+#[serde(deny_unknown_fields)]
+struct LoggingConfig {
+    level: Option<String>,
+    format: Option<String>,
+}
+
+#[derive(Deserialize, Debug, Default)]
+#[serde(deny_unknown_fields)]
+struct ReceiptsConfig {
+    signing_key: Option<String>,
+}
+
+#[derive(Deserialize, Debug, Default)]
+#[serde(deny_unknown_fields)]
+struct DiscordConfig {
+    public_key: Option<String>,
+}
+
+#[derive(Deserialize, Debug, Default)]
+#[serde(deny_unknown_fields)]
+struct SlackConfig {
+    signing_secret: Option<String>,
+}
+
+#[derive(Deserialize, Debug, Default)]
+#[serde(deny_unknown_fields)]
+struct MattermostConfig {
+    token: Option<String>,
+}
+
+#[derive(Deserialize, Debug, Default)]
+#[serde(deny_unknown_fields)]
+struct WebhooksConfig {
+    #[serde(default)]
+    urls: Vec<String>,
+    secret: Option<String>,
+    filter: Option<String>,
+}
+
+impl Config {
+    pub fn load(path: &str) -> Result<Config, String> {
+        let contents = std::fs::read_to_string(path).map_err(|e| e.to_string())?;
+        return Config::parse(&contents);
+    }
+
+    fn parse(contents: &str) -> Result<Config, String> {
+        return toml::from_str(contents).map_err(|e| e.to_string().trim_end().to_string());
+    }
+
+    /// Whether the argument was given a value, on the command line, in the environment or in the
+    /// file, rather than falling back to its default.
+    fn is_set(&self, matches: &ArgMatches, id: &str) -> bool {
+        let passed = matches
+            .value_source(id)
+            .is_some_and(|source| source != ValueSource::DefaultValue);
+        return passed || self.settings().iter().any(|(setting, _)| *setting == id);
+    }
+
+    /// Checks the arguments that can't be combined, once the file's settings have been merged
+    /// with them. Clap can't check these itself, as it ignores default values, which the file's
+    /// settings are passed as.
+    pub fn check(&self, matches: &ArgMatches) -> Result<(), String> {
+        let is_set = |id: &str| self.is_set(matches, id);
+        for id in ["host", "port", "tls_cert", "tls_key"] {
+            if is_set("unix_socket") && is_set(id) {
+                return Err(format!(
+                    "--unix-socket can't be used with --{}",
+                    id.replace('_', "-")
+                ));
+            }
+        }
+        if is_set("tls_cert") != is_set("tls_key") {
+            return Err("--tls-cert and --tls-key must be set together".to_string());
+        }
+        if is_set("webhook") && !is_set("webhook_secret") {
+            return Err("--webhook requires --webhook-secret to be set".to_string());
+        }
+
+        return Ok(());
+    }
+
+    /// Settings in the file, by the id of the command line argument they stand in for.
+    fn settings(&self) -> Vec<(&'static str, Vec<String>)> {
+        let server = &self.server;
+        let limits = &self.limits;
+        let settings = [
+            ("host", server.host.clone()),
+            ("port", server.port.map(|port| port.to_string())),
+            ("grpc_port", server.grpc_port.map(|port| port.to_string())),
+            ("unix_socket", server.unix_socket.clone()),
+            ("tls_cert", server.tls_cert.clone()),
+            ("tls_key", server.tls_key.clone()),
+            (
+                "shutdown_timeout",
+                server.shutdown_timeout.map(|timeout| timeout.to_string()),
+            ),
+            ("database", server.database.clone()),
+            ("admin_token", server.admin_token.clone()),
+            (
+                "rate_limit",
+                limits.rate_limit.map(|limit| limit.to_string()),
+            ),
+            (
+                "api_key_rate_limit",
+                limits.api_key_rate_limit.map(|limit| limit.to_string()),
+            ),
+            (
+                "dice_budget",
+                limits.dice_budget.map(|budget| budget.to_string()),
+            ),
+            (
+                "max_body_size",
+                limits.max_body_size.map(|size| size.to_string()),
+            ),
+            ("log_level", self.logging.level.clone()),
+            ("log_format", self.logging.format.clone()),
+            ("signing_key", self.receipts.signing_key.clone()),
+            ("discord_public_key", self.discord.public_key.clone()),
+            ("slack_signing_secret", self.slack.signing_secret.clone()),
+            ("mattermost_token", self.mattermost.token.clone()),
+            ("webhook_secret", self.webhooks.secret.clone()),
+            ("webhook_filter", self.webhooks.filter.clone()),
+        ];
+        let mut settings = settings
+            .into_iter()
+            .filter_map(|(id, value)| value.map(|value| (id, vec![value])))
+            .collect::<Vec<_>>();
+        if !self.server.trusted_proxies.is_empty() {
+            settings.push(("trusted_proxy", self.server.trusted_proxies.clone()));
+        }
+        if !self.cors.origins.is_empty() {
+            settings.push(("cors_origin", self.cors.origins.clone()));
+        }
+        if !self.webhooks.urls.is_empty() {
+            settings.push(("webhook", self.webhooks.urls.clone()));
+        }
+        let disabled = self
+            .features
+            .iter()
+            .filter(|(_, enabled)| !**enabled)
+            .map(|(feature, _)| feature.as_str().to_string())
+            .collect::<Vec<_>>();
+        if !disabled.is_empty() {
+            settings.push(("disable_feature", disabled));
+        }
+
+        return settings;
+    }
+
+    /// Makes the file's settings the default values of the command's arguments.
+    pub fn apply(&self, command: clap::Command) -> clap::Command {
+        return self
+            .settings()
+            .into_iter()
+            .fold(command, |command, (id, values)| {
+                return command.mut_arg(id, |arg| arg.default_values(values));
+            });
+    }
+}
+
+/// Lets every argument be set with a `DICE_ROLL_*` environment variable, e.g. `DICE_ROLL_PORT`.
+pub fn with_env_overrides(command: clap::Command) -> clap::Command {
+    let ids = command
+        .get_arguments()
+        .map(|arg| arg.get_id().to_string())
+        .filter(|id| id != "help")
+        .collect::<Vec<_>>();
+
+    return ids.into_iter().fold(command, |command, id| {
+        let variable = format!("DICE_ROLL_{}", id.to_uppercase());
+        // Values are hidden from --help as several of them are secrets.
+        return command.mut_arg(id, |arg| arg.env(variable).hide_env_values(true));
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::command;
+
+    fn merge(file: &str, args: &[&str]) -> Result<ArgMatches, String> {
+        let config = Config::parse(file)?;
+        let matches = config
+            .apply(command())
+            .try_get_matches_from([&["dice-roll-api"], args].concat())
+            .map_err(|e| e.to_string())?;
+        config.check(&matches)?;
+        return Ok(matches);
+    }
+
+    #[test]
+    fn settings_fill_in_for_missing_arguments() {
+        let file = "
+            [server]
+            port = 8080
+            trusted_proxies = [\"10.0.0.0/8\"]
+
+            [limits]
+            rate_limit = 30
+        ";
+        let matches = merge(file, &["--rate-limit", "60"]).unwrap();
+        assert_eq!(matches.get_one::<String>("port").unwrap(), "8080");
+        assert_eq!(matches.get_one::<String>("host").unwrap(), "0.0.0.0");
+        assert_eq!(*matches.get_one::<u32>("rate_limit").unwrap(), 60);
+        assert_eq!(
+            matches
+                .get_many::<String>("trusted_proxy")
+                .unwrap()
+                .collect::<Vec<_>>(),
+            ["10.0.0.0/8"]
+        );
+    }
+
+    #[test]
+    fn rejects_unknown_settings() {
+        let error = Config::parse("[server]\nprot = 8080").unwrap_err();
+        assert!(error.contains("unknown field `prot`"), "{}", error);
+        assert!(Config::parse("[features]\ndice = false").is_err());
+    }
+
+    #[test]
+    fn conflicting_settings_are_rejected_wherever_they_come_from() {
+        let unix_socket = "[server]\nunix_socket = \"/run/dice-roll/api.sock\"";
+        assert!(merge(unix_socket, &[]).is_ok());
+
+        let with_tls = format!(
+            "{}\ntls_cert = \"cert.pem\"\ntls_key = \"key.pem\"",
+            unix_socket
+        );
+        assert_eq!(
+            merge(&with_tls, &[]).unwrap_err(),
+            "--unix-socket can't be used with --tls-cert"
+        );
+        assert_eq!(
+            merge(unix_socket, &["--port", "8080"]).unwrap_err(),
+            "--unix-socket can't be used with --port"
+        );
+        assert_eq!(
+            merge("[server]\ntls_cert = \"cert.pem\"", &[]).unwrap_err(),
+            "--tls-cert and --tls-key must be set together"
+        );
+        assert!(
+            merge(
+                "[server]\ntls_cert = \"cert.pem\"",
+                &["--tls-key", "key.pem"]
+            )
+            .is_ok()
+        );
+        assert_eq!(
+            merge("[webhooks]\nurls = [\"https://example.com\"]", &[]).unwrap_err(),
+            "--webhook requires --webhook-secret to be set"
+        );
+    }
+
+    #[test]
+    fn features_can_be_turned_off() {
+        let file = "
+            [features]
+            webhooks = false
+            rooms = true
+            fair = false
+        ";
+        let matches = merge(file, &[]).unwrap();
+        assert_eq!(
+            matches
+                .get_many::<String>("disable_feature")
+                .unwrap()
+                .collect::<Vec<_>>(),
+            ["fair", "webhooks"]
+        );
+    }
+}
@@ -0,0 +1,357 @@
+use axum::{
+    Json,
+    body::Bytes,
+    extract::State,
+    http::{HeaderMap, StatusCode},
+    response::{IntoResponse, Response},
+};
+use chrono::Utc;
+use dice_roll::parser;
+use ed25519_dalek::{Signature, VerifyingKey};
+use serde::Deserialize;
+use serde_json::{Value, json};
+
+use crate::{AppState, history::RollContext, response::Problem, roll_and_record};
+
+const PING: u8 = 1;
+const APPLICATION_COMMAND: u8 = 2;
+const PONG: u8 = 1;
+const CHANNEL_MESSAGE_WITH_SOURCE: u8 = 4;
+/// Message flag hiding a response from everyone but the user who ran the command.
+const EPHEMERAL: u32 = 1 << 6;
+
+/// Requests signed longer ago than this are rejected to prevent replays.
+pub const MAX_REQUEST_AGE_SECONDS: i64 = 60 * 5;
+
+#[derive(Deserialize, Debug)]
+struct Interaction {
+    #[serde(rename = "type")]
+    interaction_type: u8,
+    #[serde(default)]
+    data: Option<CommandData>,
+    /// Set when the command is run in a guild.
+    #[serde(default)]
+    member: Option<Member>,
+    /// Set when the command is run in a direct message.
+    #[serde(default)]
+    user: Option<User>,
+}
+
+#[derive(Deserialize, Debug)]
+struct CommandData {
+    name: String,
+    #[serde(default)]
+    options: Vec<CommandOption>,
+}
+
+#[derive(Deserialize, Debug)]
+struct CommandOption {
+    name: String,
+    #[serde(default)]
+    value: Option<Value>,
+}
+
+#[derive(Deserialize, Debug)]
+struct Member {
+    user: User,
+}
+
+#[derive(Deserialize, Debug)]
+struct User {
+    id: String,
+    username: String,
+}
+
+/// Parses the application's hex encoded public key from the Discord developer portal.
+pub fn parse_public_key(public_key: &str) -> Result<VerifyingKey, String> {
+    let bytes: [u8; 32] = match hex::decode(public_key.trim()) {
+        Ok(bytes) => match bytes.try_into() {
+            Ok(bytes) => bytes,
+            Err(_) => return Err("public key must be 32 bytes".to_string()),
+        },
+        Err(e) => return Err(e.to_string()),
+    };
+
+    return VerifyingKey::from_bytes(&bytes).map_err(|e| e.to_string());
+}
+
+/// Checks Discord's signature over the request's timestamp followed by its raw body, and that
+/// the request was signed recently enough not to be a replay.
+pub fn verify_signature(public_key: &VerifyingKey, headers: &HeaderMap, body: &[u8]) -> bool {
+    let header = |name: &str| headers.get(name).and_then(|value| value.to_str().ok());
+    let (signature, timestamp) = match (
+        header("x-signature-ed25519"),
+        header("x-signature-timestamp"),
+    ) {
+        (Some(signature), Some(timestamp)) => (signature, timestamp),
+        _ => return false,
+    };
+    match timestamp.parse::<i64>() {
+        Ok(signed_at) if (Utc::now().timestamp() - signed_at).abs() <= MAX_REQUEST_AGE_SECONDS => {}
+        _ => return false,
+    }
+    let signature: [u8; 64] = match hex::decode(signature).map(|bytes| bytes.try_into()) {
+        Ok(Ok(signature)) => signature,
+        _ => return false,
+    };
+    let mut message = timestamp.as_bytes().to_vec();
+    message.extend_from_slice(body);
+
+    return public_key
+        .verify_strict(&message, &Signature::from_bytes(&signature))
+        .is_ok();
+}
+
+fn message(content: String, flags: u32) -> Response {
+    return Json(json!({
+        "type": CHANNEL_MESSAGE_WITH_SOURCE,
+        "data": {
+            "content": content,
+            "flags": flags,
+            "allowed_mentions": { "parse": [] },
+        },
+    }))
+    .into_response();
+}
+
+/// Receives Discord interactions, answering PINGs and `/roll` application commands.
+///
+/// Requests must be signed by Discord, see the README for registering the `/roll` command.
+#[utoipa::path(
+    post,
+    tag = "discord",
+    path = "/discord/interactions",
+    request_body(content = Object, description = "A Discord interaction"),
+    params(
+        ("X-Signature-Ed25519" = String, Header, description = "Discord's signature of the request."),
+        ("X-Signature-Timestamp" = String, Header, description = "Timestamp the signature covers."),
+    ),
+    responses(
+        (status = 200, description = "Interaction response", body = Object),
+        (status = 401, description = "Invalid request signature", content_type = "application/problem+json", body = Problem),
+        (status = 404, description = "Discord interactions are not enabled", content_type = "application/problem+json", body = Problem),
+    )
+)]
+pub async fn interactions(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    body: Bytes,
+) -> Response {
+    let public_key = match &state.discord_public_key {
+        Some(public_key) => public_key,
+        None => {
+            return Problem::new(
+                StatusCode::NOT_FOUND,
+                "DISCORD_DISABLED",
+                "This server has not been configured with a Discord public key.".to_string(),
+            )
+            .into_response();
+        }
+    };
+    if !verify_signature(public_key, &headers, &body) {
+        return Problem::new(
+            StatusCode::UNAUTHORIZED,
+            "INVALID_SIGNATURE",
+            "Request signature could not be verified.".to_string(),
+        )
+        .into_response();
+    }
+    let interaction = match serde_json::from_slice::<Interaction>(&body) {
+        Ok(interaction) => interaction,
+        Err(e) => {
+            return Problem::new(
+                StatusCode::BAD_REQUEST,
+                "INVALID_INTERACTION",
+                format!("Request body is not a valid interaction, {}.", e),
+            )
+            .into_response();
+        }
+    };
+
+    match interaction.interaction_type {
+        PING => return Json(json!({ "type": PONG })).into_response(),
+        APPLICATION_COMMAND => return roll_command(&state, interaction).await,
+        _ => {
+            return Problem::new(
+                StatusCode::BAD_REQUEST,
+                "UNSUPPORTED_INTERACTION",
+                format!(
+                    "Interactions of type {} are not supported.",
+                    interaction.interaction_type
+                ),
+            )
+            .into_response();
+        }
+    }
+}
+
+async fn roll_command(state: &AppState, interaction: Interaction) -> Response {
+    let data = match interaction.data {
+        Some(data) if data.name == "roll" => data,
+        Some(data) => return message(format!("Unknown command `/{}`.", data.name), EPHEMERAL),
+        None => return message("Missing command data.".to_string(), EPHEMERAL),
+    };
+    let expression = data
+        .options
+        .into_iter()
+        .find(|option| option.name == "expression")
+        .and_then(|option| option.value)
+        .and_then(|value| value.as_str().map(|value| value.trim().to_string()))
+        .unwrap_or_default();
+    if expression.is_empty() {
+        return message("Usage: `/roll expression:1d20+5`".to_string(), EPHEMERAL);
+    }
+    let user = interaction
+        .member
+        .map(|member| member.user)
+        .or(interaction.user);
+
+    let roll_request = match parser::parse(expression.clone()) {
+        Ok(roll_request) => roll_request,
+        Err(e) => return message(e.to_string(), EPHEMERAL),
+    };
+    let context = RollContext {
+        // Users relaying rolls through Discord have a dice budget of their own.
+        client: user.as_ref().map(|user| format!("discord:{}", user.id)),
+        player: user.as_ref().map(|user| user.username.clone()),
+        expression: Some(expression.clone()),
+        ..RollContext::default()
+    };
+    match roll_and_record(state, roll_request, context).await {
+        Ok(result) => {
+            let roller = match &user {
+                Some(user) => format!("<@{}>", user.id),
+                None => "Someone".to_string(),
+            };
+            return message(format!("{} rolled `{}`\n{}", roller, expression, result), 0);
+        }
+        Err(problem) => return message(problem.detail, EPHEMERAL),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::http::{HeaderValue, Method};
+    use ed25519_dalek::{Signer, SigningKey};
+    use rand::RngCore;
+
+    use super::*;
+    use crate::tests::{request, send, state};
+
+    const PING_FIXTURE: &[u8] = include_bytes!("../../../fixtures/discord/ping.json");
+    const ROLL_FIXTURE: &[u8] = include_bytes!("../../../fixtures/discord/roll.json");
+
+    fn signing_key() -> SigningKey {
+        let mut bytes = [0u8; 32];
+        rand::rng().fill_bytes(&mut bytes);
+        return SigningKey::from_bytes(&bytes);
+    }
+
+    fn signed_headers(key: &SigningKey, timestamp: i64, body: &[u8]) -> HeaderMap {
+        let timestamp = timestamp.to_string();
+        let mut message = timestamp.as_bytes().to_vec();
+        message.extend_from_slice(body);
+        let signature = hex::encode(key.sign(&message).to_bytes());
+
+        let mut headers = HeaderMap::new();
+        headers.insert(
+            "x-signature-ed25519",
+            HeaderValue::from_str(&signature).unwrap(),
+        );
+        headers.insert(
+            "x-signature-timestamp",
+            HeaderValue::from_str(&timestamp).unwrap(),
+        );
+        return headers;
+    }
+
+    #[test]
+    fn signed_fixtures_verify() {
+        let key = signing_key();
+        let now = Utc::now().timestamp();
+        for fixture in [PING_FIXTURE, ROLL_FIXTURE] {
+            let headers = signed_headers(&key, now, fixture);
+            assert!(verify_signature(&key.verifying_key(), &headers, fixture));
+        }
+    }
+
+    #[test]
+    fn tampered_payloads_are_rejected() {
+        let key = signing_key();
+        let headers = signed_headers(&key, Utc::now().timestamp(), ROLL_FIXTURE);
+        let tampered = String::from_utf8(ROLL_FIXTURE.to_vec())
+            .unwrap()
+            .replace("1d20+5", "1d20+50");
+
+        assert!(!verify_signature(
+            &key.verifying_key(),
+            &headers,
+            tampered.as_bytes()
+        ));
+        assert!(!verify_signature(
+            &signing_key().verifying_key(),
+            &headers,
+            ROLL_FIXTURE
+        ));
+        assert!(!verify_signature(
+            &key.verifying_key(),
+            &HeaderMap::new(),
+            ROLL_FIXTURE
+        ));
+    }
+
+    #[test]
+    fn stale_signatures_are_rejected() {
+        let key = signing_key();
+        let signed_at = Utc::now().timestamp() - MAX_REQUEST_AGE_SECONDS - 60;
+        let headers = signed_headers(&key, signed_at, ROLL_FIXTURE);
+
+        assert!(!verify_signature(
+            &key.verifying_key(),
+            &headers,
+            ROLL_FIXTURE
+        ));
+    }
+
+    #[tokio::test]
+    async fn answers_signed_interactions() {
+        let key = signing_key();
+        let state = AppState {
+            discord_public_key: Some(key.verifying_key()),
+            ..state()
+        };
+        let interaction = |body: &[u8], headers: HeaderMap| {
+            let mut request = request(Method::POST, "/discord/interactions", None);
+            *request.body_mut() = axum::body::Body::from(body.to_vec());
+            request.headers_mut().extend(headers);
+            return request;
+        };
+        let now = Utc::now().timestamp();
+
+        let (status, body) = send(
+            &state,
+            interaction(PING_FIXTURE, signed_headers(&key, now, PING_FIXTURE)),
+        )
+        .await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body["type"], PONG);
+
+        let (status, body) = send(
+            &state,
+            interaction(ROLL_FIXTURE, signed_headers(&key, now, ROLL_FIXTURE)),
+        )
+        .await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body["type"], CHANNEL_MESSAGE_WITH_SOURCE);
+        let content = body["data"]["content"].as_str().unwrap();
+        assert!(content.starts_with("<@300> rolled `1d20+5`"), "{}", content);
+
+        let (status, body) = send(
+            &state,
+            interaction(ROLL_FIXTURE, signed_headers(&key, now, PING_FIXTURE)),
+        )
+        .await;
+        assert_eq!(status, StatusCode::UNAUTHORIZED);
+        assert_eq!(body["code"], "INVALID_SIGNATURE");
+    }
+}
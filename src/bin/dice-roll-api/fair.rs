@@ -0,0 +1,582 @@
+use axum::{
+    Json,
+    extract::{Path, State, rejection::JsonRejection},
+    http::{HeaderMap, StatusCode},
+    response::Response,
+};
+use chrono::{DateTime, SecondsFormat, Utc};
+use dice_roll::{RollResponse, fair};
+use rand::RngCore;
+use rusqlite::{OptionalExtension, params};
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+
+use crate::{
+    AppState, admit_roll,
+    history::{Client, RollContext},
+    parse_notation, record_roll,
+    response::{Format, Negotiable, Problem},
+    tenants::hash_key,
+    webhooks::random_hex,
+};
+
+pub const SEED_TOKEN_HEADER: &str = "x-seed-token";
+const TOKEN_PREFIX: &str = "drs_";
+const MAX_CLIENT_SEED_LENGTH: usize = 128;
+
+pub const SCHEMA: &str = "
+    CREATE TABLE IF NOT EXISTS fair_seeds (
+        id TEXT PRIMARY KEY,
+        server_seed TEXT NOT NULL,
+        commitment TEXT NOT NULL,
+        owner_hash TEXT,
+        tenant TEXT,
+        created_at TEXT NOT NULL,
+        revealed_at TEXT
+    );
+    CREATE TABLE IF NOT EXISTS fair_rolls (
+        seed_id TEXT NOT NULL REFERENCES fair_seeds (id),
+        nonce INTEGER NOT NULL,
+        client_seed TEXT NOT NULL,
+        roll_id INTEGER,
+        PRIMARY KEY (seed_id, nonce)
+    );
+";
+
+/// A committed server seed. The seed itself is only included once it has been revealed.
+#[derive(Serialize, Debug, ToSchema)]
+pub struct Seed {
+    pub id: String,
+    /// Hex encoded SHA-256 hash of the server seed's bytes.
+    pub commitment: String,
+    pub created_at: DateTime<Utc>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub revealed_at: Option<DateTime<Utc>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub server_seed: Option<String>,
+}
+
+impl Negotiable for Seed {
+    fn into_text(self) -> String {
+        return match self.server_seed {
+            Some(server_seed) => format!("{} {} {}", self.id, self.commitment, server_seed),
+            None => format!("{} {}", self.id, self.commitment),
+        };
+    }
+}
+
+/// A newly committed server seed, along with the token needed to reveal it.
+#[derive(Serialize, Debug, ToSchema)]
+pub struct CreatedSeed {
+    #[serde(flatten)]
+    pub seed: Seed,
+    /// Send as the `X-Seed-Token` header to reveal the seed. Only returned this once.
+    pub owner_token: String,
+}
+
+impl Negotiable for CreatedSeed {
+    fn into_text(self) -> String {
+        return format!(
+            "{} {} {}",
+            self.seed.id, self.seed.commitment, self.owner_token
+        );
+    }
+}
+
+#[derive(Deserialize, Debug, ToSchema)]
+#[serde(deny_unknown_fields)]
+pub struct FairRollRequest {
+    #[schema(example = "1d20 + 5")]
+    pub expression: String,
+    #[schema(example = "my-lucky-seed")]
+    pub client_seed: String,
+    /// Must be unique for each roll made with the same server seed.
+    #[schema(example = 1)]
+    pub nonce: u64,
+}
+
+/// A roll along with everything needed to reproduce it once the server seed is revealed.
+#[derive(Serialize, Debug, ToSchema)]
+pub struct FairRoll {
+    pub seed_id: String,
+    pub commitment: String,
+    pub client_seed: String,
+    pub nonce: u64,
+    pub expression: String,
+    pub result: RollResponse,
+}
+
+impl Negotiable for FairRoll {
+    fn into_text(self) -> String {
+        return self.result.to_string();
+    }
+}
+
+fn timestamp(value: Option<String>) -> Option<DateTime<Utc>> {
+    let value = value?;
+    return DateTime::parse_from_rfc3339(&value)
+        .map(|timestamp| timestamp.with_timezone(&Utc))
+        .ok();
+}
+
+fn seed_not_found(id: &str) -> Problem {
+    return Problem::new(
+        StatusCode::NOT_FOUND,
+        "SEED_NOT_FOUND",
+        format!("No server seed with id {} exists.", id),
+    );
+}
+
+/// Loads one of the tenant's seeds, `None` being the seeds created without an API key.
+async fn load_seed(
+    state: &AppState,
+    id: String,
+    tenant: Option<String>,
+) -> Result<Option<Seed>, Problem> {
+    return state
+        .history
+        .with_connection(move |connection| {
+            return connection
+                .query_row(
+                    "SELECT id, server_seed, commitment, created_at, revealed_at
+                     FROM fair_seeds WHERE id = ?1 AND tenant IS ?2",
+                    params![id, tenant],
+                    |row| {
+                        let server_seed: String = row.get(1)?;
+                        let revealed_at = timestamp(row.get(4)?);
+                        let seed = Seed {
+                            id: row.get(0)?,
+                            commitment: row.get(2)?,
+                            created_at: timestamp(row.get(3)?).unwrap_or_default(),
+                            server_seed: revealed_at.map(|_| server_seed),
+                            revealed_at,
+                        };
+                        return Ok(seed);
+                    },
+                )
+                .optional();
+        })
+        .await;
+}
+
+/// Generates a new secret server seed and publishes its commitment. The returned owner token is
+/// needed to reveal the seed later. Seeds are only visible to the tenant whose API key created
+/// them, or to requests without one when none was sent.
+#[utoipa::path(
+    post,
+    tag = "fair",
+    path = "/v1/fair/seeds",
+    responses(
+        (status = 201, description = "Server seed was committed to", body = CreatedSeed),
+    )
+)]
+pub async fn create_seed(
+    State(state): State<AppState>,
+    format: Format,
+    client: Client,
+) -> Response {
+    let server_seed = fair::generate_server_seed();
+    let commitment = match fair::commitment(&server_seed) {
+        Ok(commitment) => commitment,
+        Err(e) => return format.problem(Problem::from(e)),
+    };
+    let mut id = [0u8; 8];
+    rand::rng().fill_bytes(&mut id);
+    let seed = Seed {
+        id: hex::encode(id),
+        commitment,
+        created_at: Utc::now(),
+        revealed_at: None,
+        server_seed: None,
+    };
+    let owner_token = format!("{}{}", TOKEN_PREFIX, random_hex(32));
+
+    let (id, commitment) = (seed.id.clone(), seed.commitment.clone());
+    let owner_hash = hash_key(&owner_token);
+    let created_at = seed.created_at.to_rfc3339_opts(SecondsFormat::Micros, true);
+    let tenant = client.tenant_id();
+    let result = state
+        .history
+        .with_connection(move |connection| {
+            return connection.execute(
+                "INSERT INTO fair_seeds (id, server_seed, commitment, owner_hash, tenant, created_at) VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+                params![id, server_seed, commitment, owner_hash, tenant, created_at],
+            );
+        })
+        .await;
+    match result {
+        Ok(_) => {
+            return format.respond(StatusCode::CREATED, CreatedSeed { seed, owner_token });
+        }
+        Err(problem) => return format.problem(problem),
+    }
+}
+
+/// Returns a server seed's commitment, and the seed itself once revealed.
+#[utoipa::path(
+    get,
+    tag = "fair",
+    path = "/v1/fair/seeds/{id}",
+    params(("id" = String, Path, description = "Id of the server seed.")),
+    responses(
+        (status = 200, description = "The server seed", body = Seed),
+        (status = 404, description = "Unknown server seed", content_type = "application/problem+json", body = Problem),
+    )
+)]
+pub async fn get_seed(
+    State(state): State<AppState>,
+    format: Format,
+    client: Client,
+    Path(id): Path<String>,
+) -> Response {
+    match load_seed(&state, id.clone(), client.tenant_id()).await {
+        Ok(Some(seed)) => return format.respond(StatusCode::OK, seed),
+        Ok(None) => return format.problem(seed_not_found(&id)),
+        Err(problem) => return format.problem(problem),
+    }
+}
+
+/// Reveals a server seed so its rolls can be verified. No further rolls can be made with it.
+/// Only whoever created the seed, holding its owner token, can reveal it.
+#[utoipa::path(
+    post,
+    tag = "fair",
+    path = "/v1/fair/seeds/{id}/reveal",
+    params(("id" = String, Path, description = "Id of the server seed.")),
+    security(("seed_token" = [])),
+    responses(
+        (status = 200, description = "The revealed server seed", body = Seed),
+        (status = 401, description = "Missing or invalid owner token", content_type = "application/problem+json", body = Problem),
+        (status = 404, description = "Unknown server seed", content_type = "application/problem+json", body = Problem),
+    )
+)]
+pub async fn reveal_seed(
+    State(state): State<AppState>,
+    format: Format,
+    client: Client,
+    headers: HeaderMap,
+    Path(id): Path<String>,
+) -> Response {
+    let token_hash = match headers.get(SEED_TOKEN_HEADER) {
+        Some(token) => hash_key(token.to_str().unwrap_or_default()),
+        None => {
+            return format.problem(Problem::new(
+                StatusCode::UNAUTHORIZED,
+                "SEED_TOKEN_REQUIRED",
+                "Revealing a server seed requires the owner token it was created with in the X-Seed-Token header."
+                    .to_string(),
+            ));
+        }
+    };
+    let revealed_at = Utc::now().to_rfc3339_opts(SecondsFormat::Micros, true);
+    let (seed_id, tenant) = (id.clone(), client.tenant_id());
+    let result = state
+        .history
+        .with_connection(move |connection| {
+            let owner_hash = connection
+                .query_row(
+                    "SELECT owner_hash FROM fair_seeds WHERE id = ?1 AND tenant IS ?2",
+                    params![seed_id, tenant],
+                    |row| row.get::<_, Option<String>>(0),
+                )
+                .optional()?;
+            match owner_hash {
+                Some(Some(owner_hash)) if owner_hash == token_hash => {}
+                Some(_) => {
+                    return Ok(Err(Problem::new(
+                        StatusCode::UNAUTHORIZED,
+                        "INVALID_SEED_TOKEN",
+                        "The owner token does not belong to this server seed.".to_string(),
+                    )));
+                }
+                None => return Ok(Err(seed_not_found(&seed_id))),
+            }
+            connection.execute(
+                "UPDATE fair_seeds SET revealed_at = ?2 WHERE id = ?1 AND revealed_at IS NULL",
+                params![seed_id, revealed_at],
+            )?;
+            return Ok(Ok(()));
+        })
+        .await;
+    match result {
+        Ok(Ok(())) => {}
+        Ok(Err(problem)) | Err(problem) => return format.problem(problem),
+    }
+
+    return get_seed(State(state), format, client, Path(id)).await;
+}
+
+/// Makes a provably fair roll using a committed server seed and the client's seed and nonce.
+#[utoipa::path(
+    post,
+    tag = "fair",
+    path = "/v1/fair/seeds/{id}/rolls",
+    params(("id" = String, Path, description = "Id of the server seed.")),
+    request_body = FairRollRequest,
+    responses(
+        (status = 200, description = "Dice were rolled", body = FairRoll),
+        (status = 400, description = "Invalid roll request", content_type = "application/problem+json", body = Problem),
+        (status = 404, description = "Unknown server seed", content_type = "application/problem+json", body = Problem),
+        (status = 409, description = "Seed already revealed or nonce already used", content_type = "application/problem+json", body = Problem),
+    )
+)]
+pub async fn fair_roll(
+    State(state): State<AppState>,
+    format: Format,
+    client: Client,
+    Path(id): Path<String>,
+    payload: Result<Json<FairRollRequest>, JsonRejection>,
+) -> Response {
+    let request = match payload {
+        Ok(request) => request.0,
+        Err(e) => return format.problem(Problem::from_json_rejection(e)),
+    };
+    if request.client_seed.is_empty() || request.client_seed.len() > MAX_CLIENT_SEED_LENGTH {
+        return format.problem(
+            Problem::new(
+                StatusCode::BAD_REQUEST,
+                "INVALID_CLIENT_SEED",
+                format!(
+                    "Client seed must be between 1 and {} characters.",
+                    MAX_CLIENT_SEED_LENGTH
+                ),
+            )
+            .with_field("client_seed".to_string()),
+        );
+    }
+    let nonce = match i64::try_from(request.nonce) {
+        Ok(nonce) => nonce,
+        Err(_) => {
+            return format.problem(
+                Problem::new(
+                    StatusCode::BAD_REQUEST,
+                    "INVALID_NONCE",
+                    format!("Nonce can not exceed {}.", i64::MAX),
+                )
+                .with_field("nonce".to_string()),
+            );
+        }
+    };
+    let roll_request = match parse_notation("expression", &request.expression) {
+        Ok(roll_request) => roll_request,
+        Err(problem) => return format.problem(problem),
+    };
+
+    let context = RollContext {
+        expression: Some(request.expression.clone()),
+        ..client.context()
+    };
+    if let Err(problem) = admit_roll(&state, &context, &roll_request) {
+        return format.problem(problem);
+    }
+
+    // Reserve the nonce before rolling so it can only ever be used once per seed.
+    let (seed_id, client_seed, tenant) =
+        (id.clone(), request.client_seed.clone(), client.tenant_id());
+    let reserved = state
+        .history
+        .with_connection(move |connection| {
+            let seed = connection
+                .query_row(
+                    "SELECT server_seed, commitment, revealed_at FROM fair_seeds WHERE id = ?1 AND tenant IS ?2",
+                    params![seed_id, tenant],
+                    |row| {
+                        let revealed_at: Option<String> = row.get(2)?;
+                        return Ok((row.get::<_, String>(0)?, row.get::<_, String>(1)?, revealed_at));
+                    },
+                )
+                .optional()?;
+            let (server_seed, commitment) = match seed {
+                Some((_, _, Some(_))) => {
+                    return Ok(Err(Problem::new(
+                        StatusCode::CONFLICT,
+                        "SEED_REVEALED",
+                        "This server seed has already been revealed, commit to a new one."
+                            .to_string(),
+                    )));
+                }
+                Some((server_seed, commitment, None)) => (server_seed, commitment),
+                None => return Ok(Err(seed_not_found(&seed_id))),
+            };
+            let inserted = connection.execute(
+                "INSERT OR IGNORE INTO fair_rolls (seed_id, nonce, client_seed) VALUES (?1, ?2, ?3)",
+                params![seed_id, nonce, client_seed],
+            )?;
+            if inserted == 0 {
+                return Ok(Err(Problem::new(
+                    StatusCode::CONFLICT,
+                    "NONCE_ALREADY_USED",
+                    format!("Nonce {} has already been used with this server seed.", nonce),
+                )
+                .with_field("nonce".to_string())));
+            }
+            return Ok(Ok((server_seed, commitment)));
+        })
+        .await;
+    let (server_seed, commitment) = match reserved {
+        Ok(Ok(reserved)) => reserved,
+        Ok(Err(problem)) | Err(problem) => return format.problem(problem),
+    };
+
+    let mut rng = match fair::FairRng::new(&server_seed, &request.client_seed, request.nonce) {
+        Ok(rng) => rng,
+        Err(e) => return format.problem(Problem::from(e)),
+    };
+    let result = match roll_request.roll_dice_fair(&mut rng) {
+        Ok(result) => result,
+        Err(e) => return format.problem(Problem::from(e)),
+    };
+    let roll_id = match record_roll(&state, context, &roll_request, &result).await {
+        Ok(recorded) => recorded.id,
+        Err(problem) => return format.problem(problem),
+    };
+    let seed_id = id.clone();
+    let linked = state
+        .history
+        .with_connection(move |connection| {
+            return connection.execute(
+                "UPDATE fair_rolls SET roll_id = ?3 WHERE seed_id = ?1 AND nonce = ?2",
+                params![seed_id, nonce, roll_id],
+            );
+        })
+        .await;
+    if let Err(problem) = linked {
+        return format.problem(problem);
+    }
+
+    return format.respond(
+        StatusCode::OK,
+        FairRoll {
+            seed_id: id,
+            commitment,
+            client_seed: request.client_seed,
+            nonce: request.nonce,
+            expression: request.expression,
+            result,
+        },
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::http::{HeaderValue, Method};
+    use serde_json::json;
+
+    use super::*;
+    use crate::{
+        tenants::API_KEY_HEADER,
+        tests::{api_key, request, send, state, with_header},
+    };
+
+    fn reveal(id: &str, token: Option<&str>) -> axum::http::Request<axum::body::Body> {
+        let mut request = request(Method::POST, &format!("/v1/fair/seeds/{}/reveal", id), None);
+        if let Some(token) = token {
+            request
+                .headers_mut()
+                .insert(SEED_TOKEN_HEADER, HeaderValue::from_str(token).unwrap());
+        }
+
+        return request;
+    }
+
+    #[tokio::test]
+    async fn only_the_owner_can_reveal_a_seed() {
+        let state = state();
+        let (status, seed) = send(&state, request(Method::POST, "/v1/fair/seeds", None)).await;
+        assert_eq!(status, StatusCode::CREATED);
+        let id = seed["id"].as_str().unwrap();
+        let owner_token = seed["owner_token"].as_str().unwrap();
+        assert!(seed.get("server_seed").is_none());
+
+        let (status, problem) = send(&state, reveal(id, None)).await;
+        assert_eq!(status, StatusCode::UNAUTHORIZED);
+        assert_eq!(problem["code"], "SEED_TOKEN_REQUIRED");
+        let (status, problem) = send(&state, reveal(id, Some("drs_guess"))).await;
+        assert_eq!(status, StatusCode::UNAUTHORIZED);
+        assert_eq!(problem["code"], "INVALID_SEED_TOKEN");
+        let (status, problem) = send(&state, reveal("unknown", Some(owner_token))).await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+        assert_eq!(problem["code"], "SEED_NOT_FOUND");
+
+        let (status, revealed) = send(&state, reveal(id, Some(owner_token))).await;
+        assert_eq!(status, StatusCode::OK);
+        let server_seed = revealed["server_seed"].as_str().unwrap();
+        assert_eq!(fair::commitment(server_seed).unwrap(), seed["commitment"]);
+    }
+
+    #[tokio::test]
+    async fn rolls_are_reproducible_and_stop_once_revealed() {
+        let state = state();
+        let (_, seed) = send(&state, request(Method::POST, "/v1/fair/seeds", None)).await;
+        let id = seed["id"].as_str().unwrap();
+        let roll = || {
+            return request(
+                Method::POST,
+                &format!("/v1/fair/seeds/{}/rolls", id),
+                Some(json!({"expression": "3d20 + 2", "client_seed": "lucky", "nonce": 1})),
+            );
+        };
+
+        let (status, fair_roll) = send(&state, roll()).await;
+        assert_eq!(status, StatusCode::OK);
+        let (status, problem) = send(&state, roll()).await;
+        assert_eq!(status, StatusCode::CONFLICT);
+        assert_eq!(problem["code"], "NONCE_ALREADY_USED");
+
+        let (_, revealed) = send(&state, reveal(id, seed["owner_token"].as_str())).await;
+        let mut rng =
+            fair::FairRng::new(revealed["server_seed"].as_str().unwrap(), "lucky", 1).unwrap();
+        let reproduced = parse_notation("expression", "3d20 + 2")
+            .unwrap()
+            .roll_dice_fair(&mut rng)
+            .unwrap();
+        assert_eq!(
+            fair_roll["result"],
+            serde_json::to_value(reproduced).unwrap()
+        );
+
+        let (status, problem) = send(&state, roll()).await;
+        assert_eq!(status, StatusCode::CONFLICT);
+        assert_eq!(problem["code"], "SEED_REVEALED");
+    }
+
+    #[tokio::test]
+    async fn seeds_are_only_visible_to_their_tenant() {
+        let state = state();
+        let acme = api_key(&state, "acme", json!({})).await;
+        let globex = api_key(&state, "globex", json!({})).await;
+        let create = request(Method::POST, "/v1/fair/seeds", None);
+        let (_, seed) = send(&state, with_header(create, API_KEY_HEADER, &acme)).await;
+        let id = seed["id"].as_str().unwrap();
+        let path = format!("/v1/fair/seeds/{}", id);
+
+        let get = |key: Option<&str>| {
+            let request = request(Method::GET, &path, None);
+            return match key {
+                Some(key) => with_header(request, API_KEY_HEADER, key),
+                None => request,
+            };
+        };
+        assert_eq!(send(&state, get(Some(&acme))).await.0, StatusCode::OK);
+        assert_eq!(
+            send(&state, get(Some(&globex))).await.0,
+            StatusCode::NOT_FOUND
+        );
+        assert_eq!(send(&state, get(None)).await.0, StatusCode::NOT_FOUND);
+
+        let roll = request(
+            Method::POST,
+            &format!("{}/rolls", path),
+            Some(json!({"expression": "1d20", "client_seed": "lucky", "nonce": 1})),
+        );
+        let (status, problem) = send(&state, with_header(roll, API_KEY_HEADER, &globex)).await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+        assert_eq!(problem["code"], "SEED_NOT_FOUND");
+        let reveal = with_header(
+            reveal(id, seed["owner_token"].as_str()),
+            API_KEY_HEADER,
+            &globex,
+        );
+        assert_eq!(send(&state, reveal).await.0, StatusCode::NOT_FOUND);
+    }
+}
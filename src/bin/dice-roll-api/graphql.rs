@@ -0,0 +1,485 @@
+use async_graphql::{
+    Context, Data, ErrorExtensions, Object, Schema, Subscription,
+    http::{ALL_WEBSOCKET_PROTOCOLS, WebSocketProtocols, WsMessage},
+};
+use axum::{
+    Json,
+    extract::{
+        State, WebSocketUpgrade,
+        rejection::JsonRejection,
+        ws::{CloseFrame, Message, WebSocket, rejection::WebSocketUpgradeRejection},
+    },
+    http::{HeaderMap, StatusCode, header},
+    response::{IntoResponse, Response},
+};
+use dice_roll::{Distribution, RollResponse};
+use tokio::sync::mpsc;
+use tokio_stream::{Stream, StreamExt, wrappers::UnboundedReceiverStream};
+
+use crate::{
+    AppState,
+    history::{Client, HistoryFilter, HistoryPage, RollContext},
+    parse_notation,
+    response::Problem,
+    roll_notation,
+    sessions::{self, SessionRoll},
+    tenants::Feature,
+};
+
+/// Deepest a query can nest selections, the schema has no recursive types so this is generous.
+const MAX_QUERY_DEPTH: usize = 8;
+/// Most a query can cost, each field costing 1 apart from those doing more work.
+const MAX_QUERY_COMPLEXITY: usize = 100;
+/// Cost of working out a distribution, which takes far longer than resolving anything else, so
+/// a query can't alias it more than a few times.
+const DISTRIBUTION_COMPLEXITY: usize = 25;
+
+pub type DiceRollSchema = Schema<Query, Mutation, Subscription>;
+
+pub fn schema() -> DiceRollSchema {
+    return Schema::build(Query, Mutation, Subscription)
+        .limit_depth(MAX_QUERY_DEPTH)
+        .limit_complexity(MAX_QUERY_COMPLEXITY)
+        .finish();
+}
+
+/// Problems become GraphQL errors carrying the problem's `code`, `status` and `field` as
+/// extensions.
+impl ErrorExtensions for Problem {
+    fn extend(&self) -> async_graphql::Error {
+        return async_graphql::Error::new(self.detail.clone()).extend_with(|_, extensions| {
+            extensions.set("code", self.code);
+            extensions.set("status", self.status);
+            if let Some(field) = &self.field {
+                extensions.set("field", field.as_str());
+            }
+        });
+    }
+}
+
+/// The caller of a resolver, after checking their tenant, or the anonymous policy when they have
+/// none, allows `feature`.
+fn client<'a>(
+    context: &Context<'a>,
+    feature: Option<Feature>,
+) -> async_graphql::Result<&'a Client> {
+    let client = context.data::<Client>()?;
+    if let Some(feature) = feature {
+        let state = context.data::<AppState>()?;
+        state
+            .tenants
+            .require(client.tenant.as_deref(), feature)
+            .map_err(|e| e.extend())?;
+    }
+
+    return Ok(client);
+}
+
+fn validate_session(session: &str) -> async_graphql::Result<()> {
+    return sessions::validate_session_id(session).map_err(|problem| {
+        return Problem {
+            field: Some("session".to_string()),
+            ..problem
+        }
+        .extend();
+    });
+}
+
+pub struct Query;
+
+#[Object]
+impl Query {
+    /// Every total dice roll notation can roll and how likely each is, along with their
+    /// lowest, highest and average. The dice are charged against the dice budget as if rolled.
+    #[graphql(complexity = "DISTRIBUTION_COMPLEXITY + child_complexity")]
+    async fn distribution(
+        &self,
+        context: &Context<'_>,
+        expression: String,
+    ) -> async_graphql::Result<Distribution> {
+        let client = client(context, None)?;
+        let state = context.data::<AppState>()?;
+        let roll_request = parse_notation("expression", &expression).map_err(|e| e.extend())?;
+        roll_request
+            .validate(&state.tenants.limits(client.tenant.as_deref()))
+            .map_err(|e| Problem::from(e).extend())?;
+        state
+            .limits
+            .charge_dice(&client.context(), &roll_request)
+            .map_err(|e| e.extend())?;
+        // Working out the chances of a hundred thousand sided roll takes a while.
+        let distribution = tokio::task::spawn_blocking(move || roll_request.distribution()).await?;
+
+        return distribution.map_err(|e| Problem::from(e).extend());
+    }
+
+    /// Rolls recorded in a session, newest first. Pass a page's `next` as `before` to fetch the
+    /// page after it.
+    async fn history(
+        &self,
+        context: &Context<'_>,
+        session: String,
+        before: Option<i64>,
+        #[graphql(
+            desc = "Maximum number of rolls to return, defaults to 50 and is capped at 200."
+        )]
+        limit: Option<u32>,
+    ) -> async_graphql::Result<HistoryPage> {
+        let client = client(context, Some(Feature::History))?;
+        let state = context.data::<AppState>()?;
+        let filter = HistoryFilter {
+            session: Some(session),
+            before,
+            limit,
+            ..HistoryFilter::default()
+        };
+
+        return state
+            .history
+            .query(filter, client.tenant_id())
+            .await
+            .map_err(|e| e.extend());
+    }
+}
+
+pub struct Mutation;
+
+#[Object]
+impl Mutation {
+    /// Rolls dice roll notation and records the result in the roll history. Rolls made in a
+    /// session are also sent to its `rolls` subscribers.
+    async fn roll(
+        &self,
+        context: &Context<'_>,
+        expression: String,
+        session: Option<String>,
+        player: Option<String>,
+    ) -> async_graphql::Result<RollResponse> {
+        let feature = session.as_ref().map(|_| Feature::Sessions);
+        let client = client(context, feature)?;
+        let state = context.data::<AppState>()?;
+        if let Some(session) = &session {
+            validate_session(session)?;
+        }
+        let roll_context = RollContext {
+            session,
+            player,
+            ..client.context()
+        };
+        let result = roll_notation(state, "expression", expression, roll_context)
+            .await
+            .map_err(|e| e.extend())?;
+
+        return Ok(result);
+    }
+}
+
+pub struct Subscription;
+
+#[Subscription]
+impl Subscription {
+    /// Rolls made in a session from now on, as they're made.
+    async fn rolls(
+        &self,
+        context: &Context<'_>,
+        session: String,
+    ) -> async_graphql::Result<impl Stream<Item = SessionRoll>> {
+        let client = client(context, Some(Feature::Sessions))?;
+        let state = context.data::<AppState>()?;
+        validate_session(&session)?;
+
+        return Ok(state
+            .sessions
+            .stream(client.tenant_id().as_deref(), &session, None));
+    }
+}
+
+/// Executes a GraphQL query or mutation sent as JSON.
+#[utoipa::path(
+    post,
+    tag = "graphql",
+    path = "/v1/graphql",
+    request_body(content = Object, example = json!({"query": "mutation { roll(expression: \"1d20 + 2\") { total } }"})),
+    responses(
+        (status = 200, description = "Result of the operation, along with any errors it ran into", body = Object),
+        (status = 400, description = "Request body is not a GraphQL request", content_type = "application/problem+json", body = Problem),
+    )
+)]
+pub async fn graphql(
+    State(state): State<AppState>,
+    client: Client,
+    payload: Result<Json<async_graphql::Request>, JsonRejection>,
+) -> Response {
+    let request = match payload {
+        Ok(request) => request.0,
+        Err(e) => return Problem::from_json_rejection(e).into_response(),
+    };
+    let schema = state.graphql.clone();
+    let response = schema.execute(request.data(state).data(client)).await;
+
+    return Json(response).into_response();
+}
+
+/// Returns the GraphQL schema in SDL, for generating clients.
+#[utoipa::path(
+    get,
+    tag = "graphql",
+    path = "/v1/graphql/schema",
+    responses(
+        (status = 200, description = "The GraphQL schema", content_type = "text/plain", body = String),
+    )
+)]
+pub async fn graphql_schema(State(state): State<AppState>) -> Response {
+    return (
+        [(header::CONTENT_TYPE, "text/plain; charset=utf-8")],
+        state.graphql.sdl(),
+    )
+        .into_response();
+}
+
+/// Serves GraphQL subscriptions over a WebSocket.
+///
+/// Speaks the `graphql-transport-ws` protocol, or the older `graphql-ws`, picked with the
+/// `Sec-WebSocket-Protocol` header. Browsers, which can't set headers on WebSockets, can send
+/// their API key as `apiKey` in the `connection_init` payload.
+#[utoipa::path(
+    get,
+    tag = "graphql",
+    path = "/v1/graphql",
+    responses(
+        (status = 101, description = "Switching to the WebSocket protocol"),
+        (status = 400, description = "No supported GraphQL over WebSocket protocol was asked for", content_type = "application/problem+json", body = Problem),
+        (status = 426, description = "Request was not a WebSocket upgrade", content_type = "application/problem+json", body = Problem),
+    )
+)]
+pub async fn graphql_socket(
+    State(state): State<AppState>,
+    client: Client,
+    headers: HeaderMap,
+    upgrade: Result<WebSocketUpgrade, WebSocketUpgradeRejection>,
+) -> Response {
+    let upgrade = match upgrade {
+        Ok(upgrade) => upgrade,
+        Err(e) => {
+            return Problem::new(
+                StatusCode::UPGRADE_REQUIRED,
+                "WEBSOCKET_UPGRADE_REQUIRED",
+                format!(
+                    "Subscriptions are only served over a WebSocket, queries and mutations can be POSTed, {}.",
+                    e.body_text()
+                ),
+            )
+            .into_response();
+        }
+    };
+    let protocol = headers
+        .get(header::SEC_WEBSOCKET_PROTOCOL)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|protocols| {
+            return protocols
+                .split(',')
+                .find_map(|protocol| protocol.trim().parse::<WebSocketProtocols>().ok());
+        });
+    let protocol = match protocol {
+        Some(protocol) => protocol,
+        None => {
+            return Problem::new(
+                StatusCode::BAD_REQUEST,
+                "UNSUPPORTED_WEBSOCKET_PROTOCOL",
+                format!(
+                    "The Sec-WebSocket-Protocol header must ask for one of {}.",
+                    ALL_WEBSOCKET_PROTOCOLS.join(", ")
+                ),
+            )
+            .into_response();
+        }
+    };
+
+    return upgrade
+        .max_message_size(state.limits.max_body_size)
+        .protocols(ALL_WEBSOCKET_PROTOCOLS)
+        .on_upgrade(move |socket| handle_socket(socket, state, client, protocol));
+}
+
+async fn handle_socket(
+    mut socket: WebSocket,
+    state: AppState,
+    client: Client,
+    protocol: WebSocketProtocols,
+) {
+    let (incoming, received) = mpsc::unbounded_channel::<Vec<u8>>();
+    let initial_state = state.clone();
+    let mut outgoing = async_graphql::http::WebSocket::new(
+        state.graphql.clone(),
+        UnboundedReceiverStream::new(received),
+        protocol,
+    )
+    .on_connection_init(move |payload| async move {
+        let client = match payload.get("apiKey").and_then(|key| key.as_str()) {
+            Some(key) => Client {
+                tenant: Some(
+                    initial_state
+                        .tenants
+                        .authorize(key, None)
+                        .map_err(|e| e.extend())?,
+                ),
+                ..client
+            },
+            None => client,
+        };
+        let mut data = Data::default();
+        data.insert(initial_state);
+        data.insert(client);
+        return Ok(data);
+    });
+
+    loop {
+        tokio::select! {
+            message = socket.recv() => {
+                let message = match message {
+                    Some(Ok(Message::Text(text))) => text.as_bytes().to_vec(),
+                    Some(Ok(Message::Binary(bytes))) => bytes.to_vec(),
+                    Some(Ok(Message::Close(_))) | Some(Err(_)) | None => break,
+                    Some(Ok(_)) => continue,
+                };
+                if incoming.send(message).is_err() {
+                    break;
+                }
+            }
+            message = outgoing.next() => {
+                match message {
+                    Some(WsMessage::Text(text)) => {
+                        if socket.send(Message::Text(text.into())).await.is_err() {
+                            break;
+                        }
+                    }
+                    Some(WsMessage::Close(code, reason)) => {
+                        let frame = CloseFrame {
+                            code,
+                            reason: reason.into(),
+                        };
+                        let _ = socket.send(Message::Close(Some(frame))).await;
+                        break;
+                    }
+                    None => break,
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::http::Method;
+    use serde_json::{Value, json};
+
+    use super::*;
+    use crate::{
+        idempotency::{IDEMPOTENCY_KEY_HEADER, REPLAYED_HEADER},
+        limits::{Limits, TokenBuckets},
+        tenants::API_KEY_HEADER,
+        tests::{api_key, request, respond, send, state, with_header},
+    };
+
+    fn graphql(query: &str) -> axum::http::Request<axum::body::Body> {
+        return request(Method::POST, "/v1/graphql", Some(json!({ "query": query })));
+    }
+
+    #[tokio::test]
+    async fn distributions_cover_every_total() {
+        let query = "{ distribution(expression: \"2d6 + 1\") { minimum maximum mean outcomes { total probability } } }";
+        let (status, body) = send(&state(), graphql(query)).await;
+        assert_eq!(status, StatusCode::OK);
+
+        let distribution = &body["data"]["distribution"];
+        assert_eq!(distribution["minimum"], 3);
+        assert_eq!(distribution["maximum"], 13);
+        assert_eq!(distribution["mean"], 8.0);
+        let outcomes = distribution["outcomes"].as_array().unwrap();
+        assert_eq!(outcomes.len(), 11);
+        let probability = |outcome: &Value| outcome["probability"].as_f64().unwrap();
+        assert!((probability(&outcomes[5]) - 6.0 / 36.0).abs() < 1e-9);
+        assert!((outcomes.iter().map(probability).sum::<f64>() - 1.0).abs() < 1e-9);
+    }
+
+    #[tokio::test]
+    async fn queries_can_only_ask_for_a_few_distributions() {
+        let distribution = |alias: &str| {
+            return format!("{}: distribution(expression: \"1d6\") {{ mean }}", alias);
+        };
+        let query = format!("{{ {} {} }}", distribution("a"), distribution("b"));
+        let (_, body) = send(&state(), graphql(&query)).await;
+        assert!(body.get("errors").is_none(), "{}", body);
+
+        let aliases = ["a", "b", "c", "d"].map(distribution).join(" ");
+        let (_, body) = send(&state(), graphql(&format!("{{ {} }}", aliases))).await;
+        let message = body["errors"][0]["message"].as_str().unwrap();
+        assert!(message.contains("too complex"), "{}", message);
+    }
+
+    #[tokio::test]
+    async fn distributions_are_charged_to_the_dice_budget() {
+        let state = state();
+        let state = AppState {
+            limits: Limits {
+                dice_budget: TokenBuckets::per_minute(10),
+                ..state.limits.clone()
+            },
+            ..state
+        };
+        let query = "{ distribution(expression: \"6d6\") { mean } }";
+
+        let (_, body) = send(&state, graphql(query)).await;
+        assert_eq!(body["data"]["distribution"]["mean"], 21.0);
+        let (_, body) = send(&state, graphql(query)).await;
+        assert_eq!(
+            body["errors"][0]["extensions"]["code"],
+            "DICE_BUDGET_EXCEEDED"
+        );
+    }
+
+    #[tokio::test]
+    async fn retried_roll_mutations_are_replayed() {
+        let state = state();
+        let roll = || {
+            let mutation = graphql("mutation { roll(expression: \"1d1000\") { total } }");
+            return with_header(mutation, IDEMPOTENCY_KEY_HEADER, "mutation-1");
+        };
+
+        let (_, headers, first) = respond(&state, roll()).await;
+        assert!(headers.get(REPLAYED_HEADER).is_none());
+        let (_, headers, retried) = respond(&state, roll()).await;
+        assert_eq!(headers[REPLAYED_HEADER], "true");
+        assert_eq!(first, retried);
+
+        let (_, history) = send(&state, request(Method::GET, "/v1/rolls", None)).await;
+        assert_eq!(history["rolls"].as_array().unwrap().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn history_is_scoped_to_the_tenant() {
+        let state = state();
+        let acme = api_key(&state, "acme", json!({})).await;
+        let globex = api_key(&state, "globex", json!({})).await;
+        let roll = graphql("mutation { roll(expression: \"1d6\", session: \"table\") { total } }");
+        let (_, body) = send(&state, with_header(roll, API_KEY_HEADER, &acme)).await;
+        assert!(body.get("errors").is_none(), "{}", body);
+
+        let history = |key: &str| {
+            let query = graphql("{ history(session: \"table\") { rolls { id } } }");
+            return with_header(query, API_KEY_HEADER, key);
+        };
+        let (_, body) = send(&state, history(&acme)).await;
+        assert_eq!(
+            body["data"]["history"]["rolls"].as_array().unwrap().len(),
+            1
+        );
+        let (_, body) = send(&state, history(&globex)).await;
+        assert!(
+            body["data"]["history"]["rolls"]
+                .as_array()
+                .unwrap()
+                .is_empty()
+        );
+    }
+}
@@ -0,0 +1,383 @@
+use std::{pin::Pin, time::Duration};
+
+use tokio::net::TcpListener;
+use tokio_stream::{Stream, StreamExt, wrappers::TcpListenerStream};
+use tonic::{Code, Request, Response, Status, metadata::MetadataValue, transport::Server};
+use tower_http::trace::TraceLayer;
+
+use crate::{
+    AppState,
+    history::Client,
+    parse_notation,
+    proxies::{FORWARDED_FOR_HEADER, Peer, REAL_IP_HEADER},
+    response::Problem,
+    roll_and_record, server, sessions,
+    tenants::{API_KEY_HEADER, Feature},
+};
+
+mod generated {
+    tonic::include_proto!("dice_roll.v1");
+}
+
+use generated::{
+    Dice, ParseRequest, RollRequest, RollResponse, RollStats, RollStreamRequest, Rolls,
+    SessionRoll,
+    dice_roll_server::{DiceRoll, DiceRollServer},
+};
+
+impl From<RollRequest> for dice_roll::RollRequest {
+    fn from(request: RollRequest) -> dice_roll::RollRequest {
+        let dice = request
+            .dice
+            .into_iter()
+            .map(|dice| dice_roll::Dice {
+                count: dice.count.unwrap_or(1),
+                sides: dice.sides,
+                modifier: dice.modifier,
+            })
+            .collect();
+
+        return dice_roll::RollRequest { dice };
+    }
+}
+
+impl From<dice_roll::RollRequest> for RollRequest {
+    fn from(request: dice_roll::RollRequest) -> RollRequest {
+        let dice = request
+            .dice
+            .into_iter()
+            .map(|dice| Dice {
+                count: Some(dice.count),
+                sides: dice.sides,
+                modifier: dice.modifier,
+            })
+            .collect();
+
+        return RollRequest { dice };
+    }
+}
+
+impl From<dice_roll::RollResponse> for RollResponse {
+    fn from(response: dice_roll::RollResponse) -> RollResponse {
+        let rolls = response
+            .rolls
+            .into_iter()
+            .map(|rolls| Rolls {
+                count: rolls.count,
+                sides: rolls.sides,
+                modifier: rolls.modifier,
+                rolls: rolls.rolls,
+                total: rolls.total,
+            })
+            .collect();
+
+        return RollResponse {
+            rolls,
+            total: response.total,
+        };
+    }
+}
+
+impl From<dice_roll::RollStats> for RollStats {
+    fn from(stats: dice_roll::RollStats) -> RollStats {
+        return RollStats {
+            minimum: stats.minimum,
+            maximum: stats.maximum,
+            mean: stats.mean,
+            standard_deviation: stats.standard_deviation,
+        };
+    }
+}
+
+impl From<sessions::SessionRoll> for SessionRoll {
+    fn from(roll: sessions::SessionRoll) -> SessionRoll {
+        return SessionRoll {
+            id: roll.id,
+            player: roll.player,
+            expression: roll.expression,
+            result: Some(roll.result.into()),
+            timestamp: Some(prost_types::Timestamp {
+                seconds: roll.timestamp.timestamp(),
+                nanos: roll.timestamp.timestamp_subsec_nanos() as i32,
+            }),
+        };
+    }
+}
+
+/// Problems become statuses with the closest code, the problem's own code, field and retry delay
+/// are sent along as metadata.
+impl From<Problem> for Status {
+    fn from(problem: Problem) -> Status {
+        let code = match problem.status {
+            400 | 413 => Code::InvalidArgument,
+            401 => Code::Unauthenticated,
+            403 => Code::PermissionDenied,
+            404 => Code::NotFound,
+            429 => Code::ResourceExhausted,
+            503 => Code::Unavailable,
+            500..=599 => Code::Internal,
+            _ => Code::Unknown,
+        };
+        let mut status = Status::new(code, problem.detail);
+        let metadata = status.metadata_mut();
+        metadata.insert("problem-code", MetadataValue::from_static(problem.code));
+        if let Some(field) = problem.field.and_then(|field| field.parse().ok()) {
+            metadata.insert("problem-field", field);
+        }
+        if let Some(retry_after) = problem.retry_after {
+            metadata.insert("retry-after", retry_after.into());
+        }
+
+        return status;
+    }
+}
+
+pub struct DiceRollService {
+    state: AppState,
+}
+
+impl DiceRollService {
+    /// Identifies the caller the way the HTTP API does, by their address, looking through trusted
+    /// proxies, and the tenant of the `x-api-key` metadata they sent, and charges the call against
+    /// their request rate.
+    fn client<T>(&self, request: &Request<T>, feature: Option<Feature>) -> Result<Client, Status> {
+        let header = |name: &str| {
+            return request
+                .metadata()
+                .get(name)
+                .and_then(|value| value.to_str().ok());
+        };
+        let address = self.state.proxies.client_address(
+            request.remote_addr().map(|address| Peer::Ip(address.ip())),
+            header(FORWARDED_FOR_HEADER),
+            header(REAL_IP_HEADER),
+        );
+        // Calls are charged before their key is checked, like requests over HTTP.
+        let key = request
+            .metadata()
+            .get(API_KEY_HEADER)
+            .map(|key| key.to_str().unwrap_or_default());
+        let resolved = key.and_then(|key| self.state.tenants.resolve(key));
+        self.state
+            .limits
+            .charge_request(resolved.as_ref(), &address)?;
+        let tenant = match key {
+            Some(key) => Some(self.state.tenants.authorize(key, feature)?),
+            None => {
+                if let Some(feature) = feature {
+                    self.state.tenants.require(None, feature)?;
+                }
+                None
+            }
+        };
+
+        return Ok(Client {
+            address: Some(address),
+            tenant,
+        });
+    }
+}
+
+type SessionRollStream = Pin<Box<dyn Stream<Item = Result<SessionRoll, Status>> + Send>>;
+
+#[tonic::async_trait]
+impl DiceRoll for DiceRollService {
+    async fn roll(&self, request: Request<RollRequest>) -> Result<Response<RollResponse>, Status> {
+        let client = self.client(&request, None)?;
+        let roll_request = request.into_inner().into();
+        let roll_response = roll_and_record(&self.state, roll_request, client.context()).await?;
+
+        return Ok(Response::new(roll_response.into()));
+    }
+
+    async fn parse(&self, request: Request<ParseRequest>) -> Result<Response<RollRequest>, Status> {
+        self.client(&request, None)?;
+        let roll_request = parse_notation("expression", &request.into_inner().expression)?;
+
+        return Ok(Response::new(roll_request.into()));
+    }
+
+    async fn stats(&self, request: Request<RollRequest>) -> Result<Response<RollStats>, Status> {
+        let client = self.client(&request, None)?;
+        let roll_request = dice_roll::RollRequest::from(request.into_inner());
+        let limits = self.state.tenants.limits(client.tenant.as_deref());
+        if let Err(e) = roll_request.validate(&limits) {
+            return Err(Problem::from(e).into());
+        }
+        let stats = roll_request.stats().map_err(Problem::from)?;
+
+        return Ok(Response::new(stats.into()));
+    }
+
+    type RollStreamStream = SessionRollStream;
+
+    async fn roll_stream(
+        &self,
+        request: Request<RollStreamRequest>,
+    ) -> Result<Response<SessionRollStream>, Status> {
+        let client = self.client(&request, Some(Feature::Sessions))?;
+        let request = request.into_inner();
+        sessions::validate_session_id(&request.session).map_err(|problem| Problem {
+            field: Some("session".to_string()),
+            ..problem
+        })?;
+        let rolls = self
+            .state
+            .sessions
+            .stream(
+                client.tenant_id().as_deref(),
+                &request.session,
+                request.last_event_id,
+            )
+            .map(|roll| Ok(roll.into()));
+
+        return Ok(Response::new(Box::pin(rolls)));
+    }
+}
+
+/// Serves the gRPC service until asked to stop, giving in-flight calls up to `drain_timeout` to
+/// finish like the HTTP server does.
+pub async fn serve(
+    listener: TcpListener,
+    state: AppState,
+    drain_timeout: Duration,
+) -> Result<(), tonic::transport::Error> {
+    match listener.local_addr() {
+        Ok(address) => tracing::info!("gRPC server running on {}", address),
+        Err(e) => {
+            tracing::warn!(error = %e, "Failed to find local address gRPC server is running on")
+        }
+    }
+    let (shutdown, drain_deadline) = server::shutdown("grpc", drain_timeout);
+    let server = Server::builder()
+        .layer(TraceLayer::new_for_grpc())
+        .add_service(DiceRollServer::new(DiceRollService { state }))
+        .serve_with_incoming_shutdown(TcpListenerStream::new(listener), shutdown);
+
+    tokio::select! {
+        result = server => return result,
+        _ = drain_deadline => return Ok(()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+    use crate::tests::{api_key, state};
+
+    fn dice(count: i32, sides: i32, modifier: i32) -> RollRequest {
+        return RollRequest {
+            dice: vec![Dice {
+                count: Some(count),
+                sides,
+                modifier,
+            }],
+        };
+    }
+
+    fn problem_code(status: &Status) -> &str {
+        return status
+            .metadata()
+            .get("problem-code")
+            .unwrap()
+            .to_str()
+            .unwrap();
+    }
+
+    #[tokio::test]
+    async fn rolls_and_parses_dice() {
+        let service = DiceRollService { state: state() };
+
+        let response = service.roll(Request::new(dice(3, 6, 2))).await.unwrap();
+        let response = response.into_inner();
+        assert_eq!(response.rolls[0].rolls.len(), 3);
+        assert!((5..=20).contains(&response.total));
+
+        let parsed = service
+            .parse(Request::new(ParseRequest {
+                expression: "2d8 - 1".to_string(),
+            }))
+            .await
+            .unwrap();
+        assert_eq!(parsed.into_inner(), dice(2, 8, -1));
+
+        let status = service.roll(Request::new(dice(1, 0, 0))).await.unwrap_err();
+        assert_eq!(status.code(), Code::InvalidArgument);
+        assert_eq!(problem_code(&status), "INVALID_DICE_SIDES");
+    }
+
+    #[tokio::test]
+    async fn stats_match_the_dice() {
+        let service = DiceRollService { state: state() };
+        let stats = service
+            .stats(Request::new(dice(2, 6, 1)))
+            .await
+            .unwrap()
+            .into_inner();
+
+        assert_eq!(stats.minimum, 3);
+        assert_eq!(stats.maximum, 13);
+        assert!((stats.mean - 8.0).abs() < 1e-9);
+        assert!((stats.standard_deviation - (35.0_f64 / 6.0).sqrt()).abs() < 1e-9);
+    }
+
+    #[tokio::test]
+    async fn stats_are_held_to_the_tenants_limits() {
+        let state = state();
+        let key = api_key(&state, "acme", json!({"max_sides": 20})).await;
+        let service = DiceRollService { state };
+        let mut request = Request::new(dice(1, 100, 0));
+        request
+            .metadata_mut()
+            .insert(API_KEY_HEADER, key.parse().unwrap());
+
+        let status = service.stats(request).await.unwrap_err();
+        assert_eq!(status.code(), Code::InvalidArgument);
+        assert_eq!(problem_code(&status), "INVALID_DICE_SIDES");
+
+        let mut request = Request::new(dice(1, 6, 0));
+        request
+            .metadata_mut()
+            .insert(API_KEY_HEADER, "drk_guess".parse().unwrap());
+        let status = service.stats(request).await.unwrap_err();
+        assert_eq!(status.code(), Code::Unauthenticated);
+        assert_eq!(problem_code(&status), "INVALID_API_KEY");
+    }
+
+    #[tokio::test]
+    async fn streams_session_rolls() {
+        let service = DiceRollService { state: state() };
+        let roll = dice_roll::parser::parse("1d6".to_string())
+            .unwrap()
+            .roll_dice()
+            .unwrap();
+        service
+            .state
+            .sessions
+            .record(None, "table", None, "1d6".to_string(), roll);
+
+        let stream = service
+            .roll_stream(Request::new(RollStreamRequest {
+                session: "table".to_string(),
+                last_event_id: Some(0),
+            }))
+            .await
+            .unwrap();
+        let roll = stream.into_inner().next().await.unwrap().unwrap();
+        assert_eq!(roll.id, 1);
+        assert_eq!(roll.expression, "1d6");
+
+        let status = service
+            .roll_stream(Request::new(RollStreamRequest {
+                session: String::new(),
+                last_event_id: None,
+            }))
+            .await
+            .err()
+            .unwrap();
+        assert_eq!(status.code(), Code::InvalidArgument);
+        assert_eq!(status.metadata().get("problem-field").unwrap(), "session");
+    }
+}
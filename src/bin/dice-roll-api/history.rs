@@ -0,0 +1,668 @@
+use std::sync::{Arc, Mutex};
+
+use async_graphql::SimpleObject;
+use axum::{
+    extract::{
+        FromRequestParts, Path, Query, State,
+        rejection::{PathRejection, QueryRejection},
+    },
+    http::{StatusCode, request::Parts},
+    response::Response,
+};
+use chrono::{DateTime, SecondsFormat, SubsecRound, Utc};
+use dice_roll::{RollRequest, RollResponse};
+use rusqlite::{Connection, OptionalExtension, Row, params, params_from_iter, types::Value};
+use serde::{Deserialize, Serialize};
+use utoipa::{IntoParams, ToSchema};
+
+use crate::{
+    AppState,
+    proxies::ClientAddress,
+    response::{Format, Negotiable, Problem},
+    tenants::Tenant,
+};
+
+const DEFAULT_PAGE_SIZE: u32 = 50;
+const MAX_PAGE_SIZE: u32 = 200;
+const MAX_TAG_LENGTH: usize = 64;
+
+const SCHEMA: &str = "
+    CREATE TABLE IF NOT EXISTS rolls (
+        id INTEGER PRIMARY KEY AUTOINCREMENT,
+        created_at TEXT NOT NULL,
+        client TEXT,
+        tenant TEXT,
+        session TEXT,
+        campaign TEXT,
+        player TEXT,
+        expression TEXT,
+        request TEXT NOT NULL,
+        response TEXT NOT NULL,
+        total INTEGER NOT NULL,
+        hidden INTEGER NOT NULL DEFAULT 0
+    );
+    CREATE INDEX IF NOT EXISTS rolls_tenant ON rolls (tenant, id);
+    CREATE INDEX IF NOT EXISTS rolls_session ON rolls (session, id);
+    CREATE INDEX IF NOT EXISTS rolls_campaign ON rolls (campaign, id);
+    CREATE INDEX IF NOT EXISTS rolls_created_at ON rolls (created_at);
+";
+
+/// Who made a roll and where, stored alongside it in the history.
+#[derive(Clone, Debug, Default)]
+pub struct RollContext {
+    pub client: Option<String>,
+    /// Tenant whose API key the roll was made with, its limits apply to the roll and only its
+    /// requests can read the roll back.
+    pub tenant: Option<Arc<Tenant>>,
+    pub session: Option<String>,
+    pub campaign: Option<String>,
+    pub player: Option<String>,
+    pub expression: Option<String>,
+    /// Kept out of the history listing and webhooks, for campaign rolls only some of its
+    /// participants may see.
+    pub hidden: bool,
+}
+
+/// A roll read back from the history.
+#[derive(Serialize, Debug, ToSchema, SimpleObject)]
+pub struct StoredRoll {
+    pub id: i64,
+    pub timestamp: DateTime<Utc>,
+    pub session: Option<String>,
+    pub campaign: Option<String>,
+    pub player: Option<String>,
+    pub expression: Option<String>,
+    #[schema(value_type = RollRequest)]
+    pub request: serde_json::Value,
+    #[schema(value_type = RollResponse)]
+    pub response: serde_json::Value,
+}
+
+impl Negotiable for StoredRoll {
+    fn into_text(self) -> String {
+        let timestamp = self.timestamp.to_rfc3339_opts(SecondsFormat::Secs, true);
+        return match self.expression {
+            Some(expression) => format!(
+                "#{} {} {} = {}",
+                self.id, timestamp, expression, self.response["total"]
+            ),
+            None => format!("#{} {} = {}", self.id, timestamp, self.response["total"]),
+        };
+    }
+}
+
+#[derive(Serialize, Debug, ToSchema, SimpleObject)]
+pub struct HistoryPage {
+    pub rolls: Vec<StoredRoll>,
+    /// Pass as `before` to fetch the next page, absent on the last page.
+    pub next: Option<i64>,
+}
+
+impl Negotiable for HistoryPage {
+    fn into_text(self) -> String {
+        return self
+            .rolls
+            .into_iter()
+            .map(|roll| roll.into_text())
+            .collect::<Vec<_>>()
+            .join("\n");
+    }
+}
+
+#[derive(Deserialize, Debug, Default, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct HistoryFilter {
+    /// Only return rolls made in this session.
+    pub session: Option<String>,
+    /// Only return rolls tagged with this campaign.
+    pub campaign: Option<String>,
+    /// Only return rolls made by this player.
+    pub player: Option<String>,
+    /// Only return rolls made at or after this RFC 3339 timestamp.
+    pub since: Option<DateTime<Utc>>,
+    /// Only return rolls made before this RFC 3339 timestamp.
+    pub until: Option<DateTime<Utc>>,
+    /// Only return rolls with an id lower than this one, used for pagination.
+    pub before: Option<i64>,
+    /// Maximum number of rolls to return, defaults to 50 and is capped at 200.
+    pub limit: Option<u32>,
+}
+
+/// Optional tags attaching a roll to a session or campaign in the history.
+#[derive(Deserialize, Debug, Default, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct HistoryTags {
+    /// Session to record the roll under.
+    pub session: Option<String>,
+    /// Campaign to record the roll under.
+    pub campaign: Option<String>,
+}
+
+impl HistoryTags {
+    pub fn from_query(
+        query: Result<Query<HistoryTags>, QueryRejection>,
+    ) -> Result<HistoryTags, Problem> {
+        let tags = match query {
+            Ok(query) => query.0,
+            Err(e) => return Err(Problem::from_query_rejection(e)),
+        };
+        for (field, value) in [("session", &tags.session), ("campaign", &tags.campaign)] {
+            if let Some(value) = value
+                && (value.is_empty() || value.len() > MAX_TAG_LENGTH)
+            {
+                return Err(Problem::new(
+                    StatusCode::BAD_REQUEST,
+                    "INVALID_HISTORY_TAG",
+                    format!(
+                        "{} must be between 1 and {} characters.",
+                        field, MAX_TAG_LENGTH
+                    ),
+                )
+                .with_field(field.to_string()));
+            }
+        }
+
+        return Ok(tags);
+    }
+}
+
+/// Who is making a request: their address, looking through trusted proxies, and the tenant of
+/// the API key they sent, if any.
+#[derive(Clone)]
+pub struct Client {
+    pub address: Option<String>,
+    pub tenant: Option<Arc<Tenant>>,
+}
+
+impl Client {
+    /// Id of the client's tenant, rolls in the history are only visible to the tenant that made
+    /// them.
+    pub fn tenant_id(&self) -> Option<String> {
+        return self.tenant.as_ref().map(|tenant| tenant.id.clone());
+    }
+
+    /// Context for rolls made by this client, to be tagged further by the caller.
+    pub fn context(&self) -> RollContext {
+        return RollContext {
+            client: self.address.clone(),
+            tenant: self.tenant.clone(),
+            ..RollContext::default()
+        };
+    }
+}
+
+impl<S: Send + Sync> FromRequestParts<S> for Client {
+    type Rejection = Problem;
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+        let address = parts
+            .extensions
+            .get::<ClientAddress>()
+            .map(|ClientAddress(address)| address.clone());
+        let tenant = parts.extensions.get::<Arc<Tenant>>().cloned();
+
+        return Ok(Client { address, tenant });
+    }
+}
+
+/// Roll history persisted to an SQLite database.
+#[derive(Clone)]
+pub struct History {
+    connection: Arc<Mutex<Connection>>,
+}
+
+impl History {
+    pub fn open(path: &str) -> Result<History, rusqlite::Error> {
+        let connection = Connection::open(path)?;
+        connection.pragma_update(None, "journal_mode", "WAL")?;
+        connection.execute_batch(SCHEMA)?;
+
+        return Ok(History {
+            connection: Arc::new(Mutex::new(connection)),
+        });
+    }
+
+    /// Creates any tables another module keeps alongside the roll history.
+    pub fn migrate(&self, schema: &str) -> Result<(), rusqlite::Error> {
+        return self.with_connection_blocking(|connection| connection.execute_batch(schema));
+    }
+
+    /// Runs a query against the database on the current thread, for use while starting up.
+    pub fn with_connection_blocking<T>(
+        &self,
+        query: impl FnOnce(&Connection) -> Result<T, rusqlite::Error>,
+    ) -> Result<T, rusqlite::Error> {
+        return query(&self.connection.lock().unwrap());
+    }
+
+    /// Runs a query against the database without blocking the async runtime.
+    pub async fn with_connection<T, F>(&self, query: F) -> Result<T, Problem>
+    where
+        T: Send + 'static,
+        F: FnOnce(&Connection) -> Result<T, rusqlite::Error> + Send + 'static,
+    {
+        let connection = self.connection.clone();
+        let result = tokio::task::spawn_blocking(move || {
+            let connection = connection.lock().unwrap();
+            return query(&connection);
+        })
+        .await;
+
+        match result {
+            Ok(Ok(value)) => return Ok(value),
+            Ok(Err(e)) => {
+                tracing::error!(error = %e, "Roll history query failed");
+            }
+            Err(e) => {
+                tracing::error!(error = %e, "Roll history task failed");
+            }
+        }
+        return Err(Problem::new(
+            StatusCode::INTERNAL_SERVER_ERROR,
+            "HISTORY_UNAVAILABLE",
+            "The roll history could not be accessed.".to_string(),
+        ));
+    }
+
+    /// Stores a roll, returning its id in the history.
+    pub async fn record(
+        &self,
+        context: RollContext,
+        request: &RollRequest,
+        response: &RollResponse,
+    ) -> Result<i64, Problem> {
+        return self
+            .record_with(context, request, response, |_, _| Ok(()))
+            .await
+            .map(|(id, _)| id);
+    }
+
+    /// Stores a roll along with what `log` stores about it, given the roll's id, in a single
+    /// transaction so neither is kept without the other. Returns the roll's id in the history and
+    /// the time it was stored at.
+    pub async fn record_with<F>(
+        &self,
+        context: RollContext,
+        request: &RollRequest,
+        response: &RollResponse,
+        log: F,
+    ) -> Result<(i64, DateTime<Utc>), Problem>
+    where
+        F: FnOnce(&Connection, i64) -> Result<(), rusqlite::Error> + Send + 'static,
+    {
+        let request = serde_json::to_string(request).unwrap_or_default();
+        let response_json = serde_json::to_value(response).unwrap_or_default();
+        let total = response_json["total"].as_i64().unwrap_or_default();
+        let response = response_json.to_string();
+        // Truncated to the precision it's stored with, so it reads back the same.
+        let created_at = Utc::now().trunc_subsecs(6);
+        let stored_at = created_at.to_rfc3339_opts(SecondsFormat::Micros, true);
+        let tenant = context.tenant.as_ref().map(|tenant| tenant.id.clone());
+
+        return self
+            .with_connection(move |connection| {
+                let transaction = connection.unchecked_transaction()?;
+                transaction.execute(
+                    "INSERT INTO rolls (created_at, client, tenant, session, campaign, player, expression, request, response, total, hidden)
+                     VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11)",
+                    params![
+                        stored_at,
+                        context.client,
+                        tenant,
+                        context.session,
+                        context.campaign,
+                        context.player,
+                        context.expression,
+                        request,
+                        response,
+                        total,
+                        context.hidden,
+                    ],
+                )?;
+                let id = transaction.last_insert_rowid();
+                log(&transaction, id)?;
+                transaction.commit()?;
+                return Ok((id, created_at));
+            })
+            .await;
+    }
+
+    /// Reads back one of `tenant`'s rolls, `None` for rolls made without an API key.
+    pub async fn get(
+        &self,
+        id: i64,
+        tenant: Option<String>,
+    ) -> Result<Option<StoredRoll>, Problem> {
+        return self
+            .with_connection(move |connection| {
+                return connection
+                    .query_row(
+                        "SELECT id, created_at, session, campaign, player, expression, request, response
+                         FROM rolls WHERE id = ?1 AND tenant IS ?2 AND hidden = 0",
+                        params![id, tenant],
+                        stored_roll,
+                    )
+                    .optional();
+            })
+            .await;
+    }
+
+    /// Lists `tenant`'s rolls matching `filter`, `None` for rolls made without an API key.
+    pub async fn query(
+        &self,
+        filter: HistoryFilter,
+        tenant: Option<String>,
+    ) -> Result<HistoryPage, Problem> {
+        let limit = filter
+            .limit
+            .unwrap_or(DEFAULT_PAGE_SIZE)
+            .clamp(1, MAX_PAGE_SIZE);
+        let mut conditions = vec!["hidden = 0".to_string(), "tenant IS ?".to_string()];
+        let mut values: Vec<Value> = vec![tenant.map_or(Value::Null, Value::Text)];
+        let text_filters = [
+            ("session", filter.session),
+            ("campaign", filter.campaign),
+            ("player", filter.player),
+        ];
+        for (column, value) in text_filters {
+            if let Some(value) = value {
+                conditions.push(format!("{} = ?", column));
+                values.push(Value::Text(value));
+            }
+        }
+        if let Some(since) = filter.since {
+            conditions.push("created_at >= ?".to_string());
+            values.push(Value::Text(
+                since.to_rfc3339_opts(SecondsFormat::Micros, true),
+            ));
+        }
+        if let Some(until) = filter.until {
+            conditions.push("created_at < ?".to_string());
+            values.push(Value::Text(
+                until.to_rfc3339_opts(SecondsFormat::Micros, true),
+            ));
+        }
+        if let Some(before) = filter.before {
+            conditions.push("id < ?".to_string());
+            values.push(Value::Integer(before));
+        }
+        let mut sql = "SELECT id, created_at, session, campaign, player, expression, request, response FROM rolls WHERE ".to_string();
+        sql.push_str(&conditions.join(" AND "));
+        // Fetch one extra row to find out whether there is another page.
+        sql.push_str(&format!(" ORDER BY id DESC LIMIT {}", limit + 1));
+
+        let mut rolls = self
+            .with_connection(move |connection| {
+                let mut statement = connection.prepare(&sql)?;
+                let rows = statement.query_map(params_from_iter(values), stored_roll)?;
+                return rows.collect::<Result<Vec<_>, _>>();
+            })
+            .await?;
+
+        let next = if rolls.len() > limit as usize {
+            rolls.truncate(limit as usize);
+            rolls.last().map(|roll| roll.id)
+        } else {
+            None
+        };
+        return Ok(HistoryPage { rolls, next });
+    }
+}
+
+fn stored_roll(row: &Row) -> Result<StoredRoll, rusqlite::Error> {
+    let created_at: String = row.get(1)?;
+    let request: String = row.get(6)?;
+    let response: String = row.get(7)?;
+
+    return Ok(StoredRoll {
+        id: row.get(0)?,
+        timestamp: DateTime::parse_from_rfc3339(&created_at)
+            .map(|timestamp| timestamp.with_timezone(&Utc))
+            .unwrap_or_default(),
+        session: row.get(2)?,
+        campaign: row.get(3)?,
+        player: row.get(4)?,
+        expression: row.get(5)?,
+        request: serde_json::from_str(&request).unwrap_or_default(),
+        response: serde_json::from_str(&response).unwrap_or_default(),
+    });
+}
+
+/// Lists rolls recorded with the caller's API key, or without one when none is sent, newest
+/// first.
+#[utoipa::path(
+    get,
+    tag = "history",
+    path = "/v1/rolls",
+    params(HistoryFilter),
+    responses(
+        (status = 200, description = "A page of recorded rolls", body = HistoryPage),
+        (status = 400, description = "Invalid filter", content_type = "application/problem+json", body = Problem),
+    )
+)]
+pub async fn list_rolls(
+    State(state): State<AppState>,
+    format: Format,
+    client: Client,
+    filter: Result<Query<HistoryFilter>, QueryRejection>,
+) -> Response {
+    let filter = match filter {
+        Ok(filter) => filter.0,
+        Err(e) => {
+            return format.problem(Problem::from_query_rejection(e));
+        }
+    };
+    match state.history.query(filter, client.tenant_id()).await {
+        Ok(page) => return format.respond(StatusCode::OK, page),
+        Err(problem) => return format.problem(problem),
+    }
+}
+
+/// Returns a single roll recorded with the caller's API key, or without one when none is sent.
+#[utoipa::path(
+    get,
+    tag = "history",
+    path = "/v1/rolls/{id}",
+    params(("id" = i64, Path, description = "Id of the roll in the history.")),
+    responses(
+        (status = 200, description = "The recorded roll", body = StoredRoll),
+        (status = 400, description = "Invalid roll id", content_type = "application/problem+json", body = Problem),
+        (status = 404, description = "Unknown roll", content_type = "application/problem+json", body = Problem),
+    )
+)]
+pub async fn get_roll(
+    State(state): State<AppState>,
+    format: Format,
+    client: Client,
+    id: Result<Path<i64>, PathRejection>,
+) -> Response {
+    let id = match id {
+        Ok(id) => id.0,
+        Err(_) => {
+            return format.problem(
+                Problem::new(
+                    StatusCode::BAD_REQUEST,
+                    "INVALID_ROLL_ID",
+                    "Roll ids must be integers.".to_string(),
+                )
+                .with_field("id".to_string()),
+            );
+        }
+    };
+    match state.history.get(id, client.tenant_id()).await {
+        Ok(Some(roll)) => return format.respond(StatusCode::OK, roll),
+        Ok(None) => {
+            return format.problem(Problem::new(
+                StatusCode::NOT_FOUND,
+                "ROLL_NOT_FOUND",
+                format!("No roll with id {} exists in the history.", id),
+            ));
+        }
+        Err(problem) => return format.problem(problem),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::http::Method;
+
+    use super::*;
+    use crate::{
+        tenants::TenantPolicy,
+        tests::{request, send, state},
+    };
+
+    fn roll_request() -> RollRequest {
+        return crate::parse_notation("expression", "1d6").unwrap();
+    }
+
+    fn tenant(id: &str) -> Option<Arc<Tenant>> {
+        return Some(Arc::new(Tenant {
+            id: id.to_string(),
+            policy: TenantPolicy::default(),
+        }));
+    }
+
+    async fn record(history: &History, context: RollContext) -> i64 {
+        let roll_request = roll_request();
+        let roll_response = roll_request.roll_dice().unwrap();
+        return history
+            .record(context, &roll_request, &roll_response)
+            .await
+            .unwrap();
+    }
+
+    #[tokio::test]
+    async fn client_addresses_are_not_returned() {
+        let state = state();
+        let (status, _) = send(
+            &state,
+            request(Method::GET, "/v1/rolls/notation?q=1d6&session=table", None),
+        )
+        .await;
+        assert_eq!(status, StatusCode::OK);
+
+        let (status, body) = send(&state, request(Method::GET, "/v1/rolls", None)).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body["rolls"][0]["session"], "table");
+        assert!(body["rolls"][0].get("client").is_none());
+    }
+
+    #[tokio::test]
+    async fn rolls_are_only_visible_to_their_tenant() {
+        let history = History::open(":memory:").unwrap();
+        let anonymous = record(&history, RollContext::default()).await;
+        let tenanted = record(
+            &history,
+            RollContext {
+                tenant: tenant("acme"),
+                ..RollContext::default()
+            },
+        )
+        .await;
+
+        let page = history
+            .query(HistoryFilter::default(), Some("acme".to_string()))
+            .await
+            .unwrap();
+        assert_eq!(
+            page.rolls.iter().map(|roll| roll.id).collect::<Vec<_>>(),
+            vec![tenanted]
+        );
+        let page = history.query(HistoryFilter::default(), None).await.unwrap();
+        assert_eq!(
+            page.rolls.iter().map(|roll| roll.id).collect::<Vec<_>>(),
+            vec![anonymous]
+        );
+
+        assert!(history.get(tenanted, None).await.unwrap().is_none());
+        assert!(
+            history
+                .get(anonymous, Some("acme".to_string()))
+                .await
+                .unwrap()
+                .is_none()
+        );
+        assert!(
+            history
+                .get(tenanted, Some("acme".to_string()))
+                .await
+                .unwrap()
+                .is_some()
+        );
+    }
+
+    #[tokio::test]
+    async fn hidden_rolls_are_left_out() {
+        let history = History::open(":memory:").unwrap();
+        let hidden = record(
+            &history,
+            RollContext {
+                hidden: true,
+                ..RollContext::default()
+            },
+        )
+        .await;
+
+        assert!(history.get(hidden, None).await.unwrap().is_none());
+        let page = history.query(HistoryFilter::default(), None).await.unwrap();
+        assert!(page.rolls.is_empty());
+    }
+
+    #[tokio::test]
+    async fn rolls_are_not_kept_when_logging_them_fails() {
+        let history = History::open(":memory:").unwrap();
+        let roll_request = roll_request();
+        let roll_response = roll_request.roll_dice().unwrap();
+        let failed = history
+            .record_with(
+                RollContext::default(),
+                &roll_request,
+                &roll_response,
+                |connection, _| {
+                    connection.execute("INSERT INTO missing_table VALUES (1)", [])?;
+                    return Ok(());
+                },
+            )
+            .await;
+        assert!(failed.is_err());
+
+        let page = history.query(HistoryFilter::default(), None).await.unwrap();
+        assert!(page.rolls.is_empty());
+
+        let (id, created_at) = history
+            .record_with(
+                RollContext::default(),
+                &roll_request,
+                &roll_response,
+                |_, _| Ok(()),
+            )
+            .await
+            .unwrap();
+        let stored = history.get(id, None).await.unwrap().unwrap();
+        assert_eq!(stored.timestamp, created_at);
+    }
+
+    #[tokio::test]
+    async fn pages_follow_on_from_next() {
+        let history = History::open(":memory:").unwrap();
+        for _ in 0..3 {
+            record(&history, RollContext::default()).await;
+        }
+
+        let filter = HistoryFilter {
+            limit: Some(2),
+            ..HistoryFilter::default()
+        };
+        let first = history.query(filter, None).await.unwrap();
+        assert_eq!(first.rolls.len(), 2);
+        let filter = HistoryFilter {
+            limit: Some(2),
+            before: first.next,
+            ..HistoryFilter::default()
+        };
+        let second = history.query(filter, None).await.unwrap();
+        assert_eq!(second.rolls.len(), 1);
+        assert_eq!(second.next, None);
+    }
+}
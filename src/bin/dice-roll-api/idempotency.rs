@@ -0,0 +1,420 @@
+use std::sync::Arc;
+
+use axum::{
+    body::{Body, Bytes},
+    extract::{MatchedPath, Request, State},
+    http::{HeaderValue, Method, StatusCode, header},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use chrono::{SecondsFormat, TimeDelta, Utc};
+use rusqlite::{Connection, OptionalExtension, params};
+use sha2::{Digest, Sha256};
+
+use crate::{
+    AppState,
+    campaigns::PARTICIPANT_TOKEN_HEADER,
+    proxies::{ClientAddress, UNKNOWN_ADDRESS},
+    response::Problem,
+    tenants::Tenant,
+};
+
+pub const IDEMPOTENCY_KEY_HEADER: &str = "idempotency-key";
+/// Set on responses replayed from an earlier request with the same key.
+pub const REPLAYED_HEADER: &str = "idempotent-replayed";
+const MAX_KEY_LENGTH: usize = 255;
+/// How long a key's response is kept for retries.
+const KEY_TTL: TimeDelta = TimeDelta::hours(24);
+/// Requests still unanswered after this long are assumed lost, e.g. to a restart, and can be
+/// retried.
+const PENDING_TTL: TimeDelta = TimeDelta::minutes(1);
+
+pub const SCHEMA: &str = "
+    CREATE TABLE IF NOT EXISTS idempotency_keys (
+        scope TEXT NOT NULL,
+        key TEXT NOT NULL,
+        fingerprint TEXT NOT NULL,
+        created_at TEXT NOT NULL,
+        status INTEGER,
+        content_type TEXT,
+        body BLOB,
+        PRIMARY KEY (scope, key)
+    );
+    CREATE INDEX IF NOT EXISTS idempotency_keys_created_at ON idempotency_keys (created_at);
+";
+
+/// Routes that roll dice, only requests to these are made idempotent. GraphQL requests are
+/// included for their `roll` mutation, receipts as each one is for a new roll.
+fn is_roll_route(method: &Method, route: &str) -> bool {
+    return matches!(
+        (method, route),
+        (&Method::POST, "/v1/rolls")
+            | (&Method::POST, "/v1/rolls:batch")
+            | (&Method::POST | &Method::GET, "/v1/rolls/notation")
+            | (&Method::POST, "/v1/sessions/{id}/rolls")
+            | (&Method::POST, "/v1/fair/seeds/{id}/rolls")
+            | (&Method::POST, "/v1/campaigns/{id}/rolls")
+            | (&Method::POST, "/v1/receipts")
+            | (&Method::POST, "/v1/graphql")
+            | (&Method::POST, "/")
+            | (&Method::POST | &Method::GET, "/roll")
+    );
+}
+
+/// A response kept for replaying to retries.
+struct StoredResponse {
+    status: u16,
+    content_type: Option<String>,
+    body: Vec<u8>,
+}
+
+impl StoredResponse {
+    fn into_response(self) -> Response {
+        let status = StatusCode::from_u16(self.status).unwrap_or(StatusCode::OK);
+        let mut response = (status, Body::from(self.body)).into_response();
+        let headers = response.headers_mut();
+        if let Some(content_type) = self
+            .content_type
+            .and_then(|content_type| HeaderValue::from_str(&content_type).ok())
+        {
+            headers.insert(header::CONTENT_TYPE, content_type);
+        }
+        headers.insert(REPLAYED_HEADER, HeaderValue::from_static("true"));
+        return response;
+    }
+}
+
+enum Claim {
+    /// The key is new, the request should go ahead.
+    Claimed,
+    /// The key was used for an identical request, here's what it was answered with.
+    Replay(StoredResponse),
+    /// The key was used for an identical request that hasn't been answered yet.
+    InProgress,
+    /// The key was used for a different request.
+    Mismatch,
+}
+
+fn timestamp(delta: TimeDelta) -> String {
+    return (Utc::now() - delta).to_rfc3339_opts(SecondsFormat::Micros, true);
+}
+
+/// Claims the key for a request, unless it was already used.
+fn claim(
+    connection: &Connection,
+    scope: &str,
+    key: &str,
+    fingerprint: &str,
+) -> Result<Claim, rusqlite::Error> {
+    connection.execute(
+        "DELETE FROM idempotency_keys WHERE created_at < ?1 OR (status IS NULL AND created_at < ?2)",
+        params![timestamp(KEY_TTL), timestamp(PENDING_TTL)],
+    )?;
+    let existing = connection
+        .query_row(
+            "SELECT fingerprint, status, content_type, body FROM idempotency_keys
+             WHERE scope = ?1 AND key = ?2",
+            params![scope, key],
+            |row| {
+                return Ok((
+                    row.get::<_, String>(0)?,
+                    row.get::<_, Option<u16>>(1)?,
+                    row.get::<_, Option<String>>(2)?,
+                    row.get::<_, Option<Vec<u8>>>(3)?,
+                ));
+            },
+        )
+        .optional()?;
+
+    match existing {
+        None => {
+            connection.execute(
+                "INSERT INTO idempotency_keys (scope, key, fingerprint, created_at) VALUES (?1, ?2, ?3, ?4)",
+                params![scope, key, fingerprint, timestamp(TimeDelta::zero())],
+            )?;
+            return Ok(Claim::Claimed);
+        }
+        Some((existing, _, _, _)) if existing != fingerprint => return Ok(Claim::Mismatch),
+        Some((_, None, _, _)) => return Ok(Claim::InProgress),
+        Some((_, Some(status), content_type, body)) => {
+            return Ok(Claim::Replay(StoredResponse {
+                status,
+                content_type,
+                body: body.unwrap_or_default(),
+            }));
+        }
+    }
+}
+
+/// Hash of what makes two requests the same, so a key can't be reused for a different roll.
+fn fingerprint(request: &Request, body: &Bytes) -> String {
+    let mut hasher = Sha256::new();
+    hasher.update(request.method().as_str());
+    hasher.update(b"\n");
+    hasher.update(request.uri().to_string());
+    hasher.update(b"\n");
+    hasher.update(body);
+    // Participants sharing an address mustn't be handed each other's rolls.
+    if let Some(token) = request.headers().get(PARTICIPANT_TOKEN_HEADER) {
+        hasher.update(b"\n");
+        hasher.update(token.as_bytes());
+    }
+    return hex::encode(hasher.finalize());
+}
+
+/// Makes roll requests carrying an `Idempotency-Key` header safe to retry: the first response
+/// to a key is stored and replayed to any retry of the same request, instead of rolling again.
+/// Keys are remembered for 24 hours, separately for each tenant, or client address.
+pub async fn idempotency(State(state): State<AppState>, request: Request, next: Next) -> Response {
+    let key = match request.headers().get(IDEMPOTENCY_KEY_HEADER) {
+        Some(key) => key.clone(),
+        None => return next.run(request).await,
+    };
+    let is_roll = request
+        .extensions()
+        .get::<MatchedPath>()
+        .is_some_and(|route| is_roll_route(request.method(), route.as_str()));
+    if !is_roll {
+        return next.run(request).await;
+    }
+    let key = match key.to_str() {
+        Ok(key) if !key.is_empty() && key.len() <= MAX_KEY_LENGTH => key.to_string(),
+        _ => {
+            return Problem::new(
+                StatusCode::BAD_REQUEST,
+                "INVALID_IDEMPOTENCY_KEY",
+                format!(
+                    "Idempotency keys must be between 1 and {} visible ASCII characters.",
+                    MAX_KEY_LENGTH
+                ),
+            )
+            .into_response();
+        }
+    };
+    let scope = match (
+        request.extensions().get::<Arc<Tenant>>(),
+        request.extensions().get::<ClientAddress>(),
+    ) {
+        (Some(tenant), _) => tenant.bucket(),
+        (None, Some(ClientAddress(address))) => address.clone(),
+        (None, None) => UNKNOWN_ADDRESS.to_string(),
+    };
+
+    // The body is needed to tell retries from different requests reusing a key.
+    let (parts, body) = request.into_parts();
+    let body = match axum::body::to_bytes(body, state.limits.max_body_size).await {
+        Ok(body) => body,
+        Err(_) => {
+            return Problem::new(
+                StatusCode::PAYLOAD_TOO_LARGE,
+                "PAYLOAD_TOO_LARGE",
+                "Request body is too large.".to_string(),
+            )
+            .into_response();
+        }
+    };
+    let request = Request::from_parts(parts, Body::from(body.clone()));
+    let fingerprint = fingerprint(&request, &body);
+
+    let claimed = {
+        let (scope, key) = (scope.clone(), key.clone());
+        state
+            .history
+            .with_connection(move |connection| claim(connection, &scope, &key, &fingerprint))
+            .await
+    };
+    match claimed {
+        Ok(Claim::Claimed) => {}
+        Ok(Claim::Replay(stored)) => return stored.into_response(),
+        Ok(Claim::InProgress) => {
+            let mut problem = Problem::new(
+                StatusCode::CONFLICT,
+                "IDEMPOTENCY_KEY_IN_USE",
+                "A request with this idempotency key is still being processed.".to_string(),
+            );
+            problem.retry_after = Some(1);
+            return problem.into_response();
+        }
+        Ok(Claim::Mismatch) => {
+            return Problem::new(
+                StatusCode::UNPROCESSABLE_ENTITY,
+                "IDEMPOTENCY_KEY_REUSED",
+                "This idempotency key was already used for a different request.".to_string(),
+            )
+            .into_response();
+        }
+        Err(problem) => return problem.into_response(),
+    }
+
+    // Finished even if the client goes away, so their retry gets the result rather than a conflict.
+    match tokio::spawn(respond_and_store(state, scope, key, request, next)).await {
+        Ok(response) => return response,
+        Err(e) => {
+            tracing::error!(error = %e, "Idempotent request failed");
+            return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+        }
+    }
+}
+
+/// Runs a request whose idempotency key was claimed, storing its response for retries.
+async fn respond_and_store(
+    state: AppState,
+    scope: String,
+    key: String,
+    request: Request,
+    next: Next,
+) -> Response {
+    let response = next.run(request).await;
+    let status = response.status();
+    // Rate limits and server errors are transient, the request can be retried for real.
+    if status.is_server_error() || status == StatusCode::TOO_MANY_REQUESTS {
+        let released = state
+            .history
+            .with_connection(move |connection| {
+                return connection.execute(
+                    "DELETE FROM idempotency_keys WHERE scope = ?1 AND key = ?2",
+                    params![scope, key],
+                );
+            })
+            .await;
+        if let Err(problem) = released {
+            tracing::warn!(code = problem.code, "Failed to release idempotency key");
+        }
+        return response;
+    }
+
+    let (parts, body) = response.into_parts();
+    let body = match axum::body::to_bytes(body, usize::MAX).await {
+        Ok(body) => body,
+        Err(e) => {
+            tracing::error!(error = %e, "Failed to read response to store for idempotency key");
+            return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+        }
+    };
+    let content_type = parts
+        .headers
+        .get(header::CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .map(|value| value.to_string());
+    let stored_body = body.to_vec();
+    let stored = state
+        .history
+        .with_connection(move |connection| {
+            return connection.execute(
+                "UPDATE idempotency_keys SET status = ?3, content_type = ?4, body = ?5
+                 WHERE scope = ?1 AND key = ?2",
+                params![scope, key, status.as_u16(), content_type, stored_body],
+            );
+        })
+        .await;
+    if let Err(problem) = stored {
+        tracing::warn!(
+            code = problem.code,
+            "Failed to store response for idempotency key"
+        );
+    }
+
+    return Response::from_parts(parts, Body::from(body));
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::http::Method;
+    use serde_json::{Value, json};
+
+    use super::*;
+    use crate::{
+        proxies::{FORWARDED_FOR_HEADER, TrustedProxies},
+        tests::{request, respond, state, with_header},
+    };
+
+    fn roll(key: &str, expression: &str) -> axum::http::Request<Body> {
+        let request = request(
+            Method::POST,
+            "/v1/rolls/notation",
+            Some(json!({"expression": expression})),
+        );
+        return with_header(request, IDEMPOTENCY_KEY_HEADER, key);
+    }
+
+    #[tokio::test]
+    async fn retries_get_the_first_response() {
+        let state = state();
+        let (status, headers, first) = respond(&state, roll("retry-1", "100d1000")).await;
+        assert_eq!(status, StatusCode::OK);
+        assert!(headers.get(REPLAYED_HEADER).is_none());
+
+        let (status, headers, retry) = respond(&state, roll("retry-1", "100d1000")).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(headers[REPLAYED_HEADER], "true");
+        assert_eq!(headers[header::CONTENT_TYPE], "application/json");
+        assert_eq!(retry, first);
+
+        let (_, headers, other) = respond(&state, roll("retry-2", "100d1000")).await;
+        assert!(headers.get(REPLAYED_HEADER).is_none());
+        assert_ne!(other, first);
+    }
+
+    #[tokio::test]
+    async fn keys_cant_be_reused_for_different_requests() {
+        let state = state();
+        assert_eq!(
+            respond(&state, roll("reused", "1d6")).await.0,
+            StatusCode::OK
+        );
+
+        let (status, _, body) = respond(&state, roll("reused", "2d6")).await;
+        assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY);
+        let body: Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(body["code"], "IDEMPOTENCY_KEY_REUSED");
+
+        let (status, _, _) = respond(&state, roll(&"k".repeat(MAX_KEY_LENGTH + 1), "1d6")).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+    }
+
+    #[tokio::test]
+    async fn clients_behind_a_proxy_have_their_own_keys() {
+        let state = AppState {
+            proxies: TrustedProxies::parse(["127.0.0.1"]).unwrap(),
+            ..state()
+        };
+        let from = |address: &str| {
+            return with_header(roll("shared", "100d1000"), FORWARDED_FOR_HEADER, address);
+        };
+
+        let (_, _, first) = respond(&state, from("203.0.113.1")).await;
+        let (_, headers, second) = respond(&state, from("203.0.113.2")).await;
+        assert!(headers.get(REPLAYED_HEADER).is_none());
+        assert_ne!(second, first);
+
+        let (_, headers, retry) = respond(&state, from("203.0.113.1")).await;
+        assert_eq!(headers[REPLAYED_HEADER], "true");
+        assert_eq!(retry, first);
+    }
+
+    #[tokio::test]
+    async fn receipts_are_not_issued_twice() {
+        let signing_key = dice_roll::receipt::generate_signing_key();
+        let state = AppState {
+            signer: Some(Arc::new(
+                dice_roll::receipt::Signer::new(&signing_key).unwrap(),
+            )),
+            ..state()
+        };
+        let receipt = || {
+            let request = request(
+                Method::POST,
+                "/v1/receipts",
+                Some(json!({"expression": "1d20"})),
+            );
+            return with_header(request, IDEMPOTENCY_KEY_HEADER, "receipt-1");
+        };
+
+        let (status, _, first) = respond(&state, receipt()).await;
+        assert_eq!(status, StatusCode::OK);
+        let (status, headers, retry) = respond(&state, receipt()).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(headers[REPLAYED_HEADER], "true");
+        assert_eq!(retry, first);
+    }
+}
@@ -0,0 +1,343 @@
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use axum::{
+    extract::{Request, State},
+    http::StatusCode,
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use dice_roll::RollRequest;
+
+use crate::{
+    AppState,
+    history::RollContext,
+    proxies::{ClientAddress, UNKNOWN_ADDRESS},
+    response::Problem,
+    tenants::{API_KEY_HEADER, Tenant},
+};
+
+/// Idle buckets are forgotten once this many clients are being tracked.
+const MAX_TRACKED_CLIENTS: usize = 10_000;
+/// Endpoints probed by orchestrators and scrapers are never rate limited.
+pub const EXEMPT_PATHS: [&str; 3] = ["/healthz", "/readyz", "/metrics"];
+
+struct Bucket {
+    tokens: f64,
+    capacity: f64,
+    updated_at: Instant,
+}
+
+impl Bucket {
+    /// Tokens the bucket would hold at `now`, it refills completely over the course of a minute.
+    fn tokens_at(&self, now: Instant) -> f64 {
+        let refilled = now.duration_since(self.updated_at).as_secs_f64() * self.capacity / 60.0;
+        return (self.tokens + refilled).min(self.capacity);
+    }
+}
+
+/// Token buckets refilling at a steady rate, one per client.
+#[derive(Clone)]
+pub struct TokenBuckets {
+    per_minute: u32,
+    buckets: Arc<Mutex<HashMap<String, Bucket>>>,
+}
+
+impl TokenBuckets {
+    /// Buckets holding `per_minute` tokens, refilled over the course of a minute.
+    pub fn per_minute(per_minute: u32) -> TokenBuckets {
+        return TokenBuckets {
+            per_minute,
+            buckets: Arc::new(Mutex::new(HashMap::new())),
+        };
+    }
+
+    /// Takes `cost` tokens from the client's bucket, or returns how long until enough are available.
+    pub fn take(&self, client: &str, cost: f64) -> Result<(), Duration> {
+        return self.take_at(client, cost, self.per_minute);
+    }
+
+    /// Like [`TokenBuckets::take`], for a client with its own rate of `per_minute` tokens.
+    pub fn take_at(&self, client: &str, cost: f64, per_minute: u32) -> Result<(), Duration> {
+        if per_minute == 0 {
+            return Ok(());
+        }
+        let capacity = per_minute as f64;
+        let now = Instant::now();
+        let mut buckets = self.buckets.lock().unwrap();
+        if !buckets.contains_key(client) && buckets.len() >= MAX_TRACKED_CLIENTS {
+            // Buckets that have refilled completely would be recreated full anyway.
+            buckets.retain(|_, bucket| bucket.tokens_at(now) < bucket.capacity);
+        }
+        let bucket = buckets.entry(client.to_string()).or_insert(Bucket {
+            tokens: capacity,
+            capacity,
+            updated_at: now,
+        });
+        bucket.tokens = bucket.tokens_at(now).min(capacity);
+        bucket.capacity = capacity;
+        bucket.updated_at = now;
+
+        if bucket.tokens >= cost {
+            bucket.tokens -= cost;
+            return Ok(());
+        }
+        let missing = cost.min(capacity) - bucket.tokens;
+        return Err(Duration::from_secs_f64(missing * 60.0 / capacity));
+    }
+}
+
+/// Abuse protection applied to every client.
+#[derive(Clone)]
+pub struct Limits {
+    /// Requests per minute for each client address.
+    pub per_ip: TokenBuckets,
+    /// Requests per minute for each tenant, unless their policy sets a rate of its own.
+    pub per_api_key: TokenBuckets,
+    /// Dice rolled per minute for each client address, or tenant.
+    pub dice_budget: TokenBuckets,
+    /// Largest request body, or WebSocket message, accepted in bytes.
+    pub max_body_size: usize,
+}
+
+impl Limits {
+    /// Charges a request against the request rate of its tenant, or else its client address.
+    pub fn charge_request(
+        &self,
+        tenant: Option<&Arc<Tenant>>,
+        address: &str,
+    ) -> Result<(), Problem> {
+        let limited = match tenant {
+            Some(tenant) => self.per_api_key.take_at(
+                &tenant.bucket(),
+                1.0,
+                tenant
+                    .policy
+                    .rate_limit
+                    .unwrap_or(self.per_api_key.per_minute),
+            ),
+            None => self.per_ip.take(address, 1.0),
+        };
+
+        return limited.map_err(|retry_after| {
+            too_many_requests(
+                "RATE_LIMITED",
+                format!(
+                    "Too many requests, try again in {} seconds.",
+                    retry_seconds(retry_after)
+                ),
+                retry_after,
+            )
+        });
+    }
+
+    /// Charges a roll's dice against the dice budget of its tenant, or else its client address.
+    pub fn charge_dice(
+        &self,
+        context: &RollContext,
+        roll_request: &RollRequest,
+    ) -> Result<(), Problem> {
+        let dice = roll_request
+            .dice
+            .iter()
+            .map(|dice| dice.count.max(0) as f64)
+            .sum();
+        let charged = match &context.tenant {
+            Some(tenant) => self.dice_budget.take_at(
+                &tenant.bucket(),
+                dice,
+                tenant
+                    .policy
+                    .dice_budget
+                    .unwrap_or(self.dice_budget.per_minute),
+            ),
+            // Rolls relayed by chat integrations are charged to the user who made them, those
+            // whose user is unknown share a budget.
+            None => self
+                .dice_budget
+                .take(context.client.as_deref().unwrap_or(UNKNOWN_ADDRESS), dice),
+        };
+
+        return charged.map_err(|retry_after| {
+            too_many_requests(
+                "DICE_BUDGET_EXCEEDED",
+                format!(
+                    "This roll needs {} dice, try again in {} seconds.",
+                    dice,
+                    retry_seconds(retry_after)
+                ),
+                retry_after,
+            )
+        });
+    }
+}
+
+fn retry_seconds(retry_after: Duration) -> u16 {
+    return retry_after.as_secs_f64().ceil().clamp(1.0, u16::MAX as f64) as u16;
+}
+
+/// A 429 problem, the `Retry-After` header is added when the problem is turned into a response.
+fn too_many_requests(code: &'static str, detail: String, retry_after: Duration) -> Problem {
+    let mut problem = Problem::new(StatusCode::TOO_MANY_REQUESTS, code, detail);
+    problem.retry_after = Some(retry_seconds(retry_after));
+    return problem;
+}
+
+/// Rejects requests from clients, or tenants, that have used up their request rate. Requests
+/// made with an API key count against its tenant's rate instead of the client address's. This
+/// runs before the key is checked, so requests with unknown keys count against the client
+/// address and can't be used to guess keys unhindered.
+pub async fn enforce(State(state): State<AppState>, request: Request, next: Next) -> Response {
+    if EXEMPT_PATHS.contains(&request.uri().path()) {
+        return next.run(request).await;
+    }
+    let address = match request.extensions().get::<ClientAddress>() {
+        Some(ClientAddress(address)) => address.as_str(),
+        None => UNKNOWN_ADDRESS,
+    };
+    let tenant = request
+        .headers()
+        .get(API_KEY_HEADER)
+        .and_then(|key| key.to_str().ok())
+        .and_then(|key| state.tenants.resolve(key));
+    if let Err(problem) = state.limits.charge_request(tenant.as_ref(), address) {
+        return problem.into_response();
+    }
+
+    return next.run(request).await;
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::http::{Method, header};
+    use serde_json::json;
+
+    use super::*;
+    use crate::tests::{api_key, request, respond, send, state, with_header};
+
+    fn limited(requests: u32, dice: u32) -> AppState {
+        let state = state();
+        return AppState {
+            limits: Limits {
+                per_ip: TokenBuckets::per_minute(requests),
+                per_api_key: TokenBuckets::per_minute(requests),
+                dice_budget: TokenBuckets::per_minute(dice),
+                ..state.limits.clone()
+            },
+            ..state
+        };
+    }
+
+    fn roll(expression: &str) -> axum::http::Request<axum::body::Body> {
+        return request(
+            Method::POST,
+            "/v1/rolls/notation",
+            Some(json!({ "expression": expression })),
+        );
+    }
+
+    #[test]
+    fn buckets_hold_a_minute_of_tokens() {
+        let buckets = TokenBuckets::per_minute(60);
+        assert!(buckets.take("a", 59.0).is_ok());
+        assert!(buckets.take("a", 1.0).is_ok());
+        // One token refills every second.
+        let retry_after = buckets.take("a", 2.0).unwrap_err();
+        assert!(
+            retry_after > Duration::from_millis(1900),
+            "{:?}",
+            retry_after
+        );
+        assert!(retry_after <= Duration::from_secs(2), "{:?}", retry_after);
+        // Clients have buckets of their own.
+        assert!(buckets.take("b", 60.0).is_ok());
+        // Clients with a rate of their own get a bucket that size.
+        assert!(buckets.take_at("c", 100.0, 100).is_ok());
+        assert!(buckets.take_at("c", 1.0, 100).is_err());
+    }
+
+    #[test]
+    fn zero_disables_the_limit() {
+        let buckets = TokenBuckets::per_minute(0);
+        for _ in 0..1000 {
+            assert!(buckets.take("a", 1000.0).is_ok());
+        }
+    }
+
+    #[tokio::test]
+    async fn requests_over_the_rate_are_rejected() {
+        let state = limited(2, 1000);
+        for _ in 0..2 {
+            assert_eq!(send(&state, roll("1d6")).await.0, StatusCode::OK);
+        }
+        let (status, headers, body) = respond(&state, roll("1d6")).await;
+        assert_eq!(status, StatusCode::TOO_MANY_REQUESTS);
+        assert_eq!(headers[header::RETRY_AFTER], "30");
+        let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(body["code"], "RATE_LIMITED");
+
+        // Probes are never limited.
+        let (status, _) = send(&state, request(Method::GET, "/healthz", None)).await;
+        assert_eq!(status, StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn dice_are_charged_to_the_budget() {
+        let state = limited(100, 10);
+        assert_eq!(send(&state, roll("6d6")).await.0, StatusCode::OK);
+        let (status, body) = send(&state, roll("3d6 + 2d4")).await;
+        assert_eq!(status, StatusCode::TOO_MANY_REQUESTS);
+        assert_eq!(body["code"], "DICE_BUDGET_EXCEEDED");
+        // What's left of the budget can still be rolled.
+        assert_eq!(send(&state, roll("4d6")).await.0, StatusCode::OK);
+        assert_eq!(
+            send(&state, roll("1d6")).await.0,
+            StatusCode::TOO_MANY_REQUESTS
+        );
+    }
+
+    #[tokio::test]
+    async fn tenants_are_charged_to_their_own_budget() {
+        let state = limited(100, 10);
+        let key = api_key(
+            &state,
+            "acme",
+            json!({ "dice_budget": 100, "rate_limit": 3 }),
+        )
+        .await;
+        let tenant_roll = |expression: &str| with_header(roll(expression), "x-api-key", &key);
+
+        assert_eq!(send(&state, tenant_roll("50d6")).await.0, StatusCode::OK);
+        // The client address's budget is left untouched.
+        assert_eq!(send(&state, roll("10d6")).await.0, StatusCode::OK);
+        assert_eq!(send(&state, tenant_roll("50d6")).await.0, StatusCode::OK);
+        let (status, body) = send(&state, tenant_roll("1d6")).await;
+        assert_eq!(status, StatusCode::TOO_MANY_REQUESTS);
+        assert_eq!(body["code"], "DICE_BUDGET_EXCEEDED");
+        // The tenant's rate of 3 requests a minute is used up too.
+        let (_, body) = send(&state, tenant_roll("1d6")).await;
+        assert_eq!(body["code"], "RATE_LIMITED");
+    }
+
+    #[tokio::test]
+    async fn unknown_keys_are_charged_to_the_client_address() {
+        let state = limited(3, 1000);
+        let guess = |key: &str| with_header(roll("1d6"), "x-api-key", key);
+
+        for key in ["guess-1", "guess-2", "guess-3"] {
+            let (status, body) = send(&state, guess(key)).await;
+            assert_eq!(status, StatusCode::UNAUTHORIZED);
+            assert_eq!(body["code"], "INVALID_API_KEY");
+        }
+        let (status, body) = send(&state, guess("guess-4")).await;
+        assert_eq!(status, StatusCode::TOO_MANY_REQUESTS);
+        assert_eq!(body["code"], "RATE_LIMITED");
+        assert_eq!(
+            send(&state, roll("1d6")).await.0,
+            StatusCode::TOO_MANY_REQUESTS
+        );
+    }
+}
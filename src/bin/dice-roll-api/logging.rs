@@ -0,0 +1,73 @@
+use std::io::IsTerminal;
+
+use axum::{extract::Request, http::HeaderName};
+use tracing::Span;
+use tracing_subscriber::EnvFilter;
+
+pub const REQUEST_ID_HEADER: HeaderName = HeaderName::from_static("x-request-id");
+
+/// Installs the global subscriber. `level` takes anything `RUST_LOG` would, e.g. `debug` or
+/// `info,dice_roll_api=debug`.
+pub fn init(level: &str, format: &str) -> Result<(), String> {
+    let filter = EnvFilter::try_new(level).map_err(|e| e.to_string())?;
+    let subscriber = tracing_subscriber::fmt().with_env_filter(filter);
+    let result = match format {
+        "json" => subscriber
+            .json()
+            .flatten_event(true)
+            .with_span_list(false)
+            .try_init(),
+        _ => subscriber
+            .with_ansi(std::io::stdout().is_terminal())
+            .try_init(),
+    };
+
+    return result.map_err(|e| e.to_string());
+}
+
+/// Span every request is handled in, tagged with the id echoed in its `X-Request-Id` header
+/// and, once its API key is resolved, the tenant making it.
+pub fn request_span(request: &Request) -> Span {
+    let request_id = request
+        .headers()
+        .get(REQUEST_ID_HEADER)
+        .and_then(|value| value.to_str().ok())
+        .unwrap_or_default();
+
+    return tracing::info_span!(
+        "request",
+        request_id,
+        method = %request.method(),
+        path = request.uri().path(),
+        tenant = tracing::field::Empty,
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::http::{Method, StatusCode};
+
+    use super::*;
+    use crate::tests::{request, respond, state, with_header};
+
+    #[tokio::test]
+    async fn responses_carry_a_request_id() {
+        let state = state();
+        let (status, headers, _) = respond(&state, request(Method::GET, "/healthz", None)).await;
+        assert_eq!(status, StatusCode::OK);
+        let generated = headers[REQUEST_ID_HEADER].to_str().unwrap();
+        let groups = generated.split('-').map(str::len).collect::<Vec<_>>();
+        assert_eq!(groups, vec![8, 4, 4, 4, 12], "{}", generated);
+
+        let (_, headers, _) = respond(
+            &state,
+            with_header(
+                request(Method::GET, "/v1/unknown", None),
+                "x-request-id",
+                "trace-1234",
+            ),
+        )
+        .await;
+        assert_eq!(headers[REQUEST_ID_HEADER], "trace-1234");
+    }
+}
@@ -0,0 +1,1156 @@
+use std::{path::PathBuf, process::ExitCode, sync::Arc, time::Duration};
+
+use axum::{
+    Json, Router,
+    extract::{
+        DefaultBodyLimit, Path, Query, State,
+        rejection::{JsonRejection, QueryRejection},
+    },
+    http::{StatusCode, header},
+    middleware,
+    response::{IntoResponse, Response},
+    routing::{delete, get, post, put},
+};
+use clap::{self, ArgAction};
+use dice_roll::{RollRequest, RollResponse, parser};
+use serde::Deserialize;
+use tower::ServiceBuilder;
+use tower_http::{
+    request_id::{MakeRequestUuid, PropagateRequestIdLayer, SetRequestIdLayer},
+    trace::{DefaultOnResponse, TraceLayer},
+};
+use tracing::Level;
+use utoipa::{IntoParams, OpenApi, ToSchema};
+use utoipa_swagger_ui::SwaggerUi;
+
+mod batch;
+mod campaigns;
+mod config;
+mod discord;
+mod fair;
+mod graphql;
+mod grpc;
+mod history;
+mod idempotency;
+mod limits;
+mod logging;
+mod metrics;
+mod openapi;
+mod proxies;
+mod receipts;
+mod response;
+mod rooms;
+mod server;
+mod sessions;
+mod slack;
+mod tenants;
+mod web;
+mod webhooks;
+
+use history::{Client, History, HistoryTags, RollContext};
+use openapi::ApiDoc;
+use response::{Format, Problem};
+
+#[derive(Clone)]
+pub struct AppState {
+    pub history: History,
+    pub rooms: rooms::Rooms,
+    pub sessions: sessions::Sessions,
+    pub signer: Option<Arc<dice_roll::receipt::Signer>>,
+    pub discord_public_key: Option<ed25519_dalek::VerifyingKey>,
+    pub slash_commands: slack::SlashCommandSecrets,
+    pub webhooks: webhooks::Webhooks,
+    pub metrics: metrics::Metrics,
+    pub limits: limits::Limits,
+    pub proxies: proxies::TrustedProxies,
+    pub tenants: tenants::Tenants,
+    pub admin_token: Option<String>,
+    pub graphql: graphql::DiceRollSchema,
+}
+
+#[derive(Deserialize, Debug, ToSchema)]
+#[serde(deny_unknown_fields)]
+pub struct ExpressionRequest {
+    #[schema(example = "1d20 + 1d4 + 2")]
+    pub expression: String,
+}
+
+#[derive(Deserialize, Debug, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct ExpressionQuery {
+    /// Dice roll notation to parse and roll, e.g. `1d20 + 1d4 + 2`.
+    pub q: String,
+}
+
+fn command() -> clap::Command {
+    return clap::Command::new("dice-roll-api")
+        .about("Dice rolls as a service")
+        .arg(
+            clap::Arg::new("config")
+                .long("config")
+                .action(ArgAction::Set)
+                .help("TOML file to read settings from, arguments and environment variables override it."),
+        )
+        .arg(
+            clap::Arg::new("host")
+                .long("host")
+                .default_value("0.0.0.0")
+                .action(ArgAction::Set)
+                .help("Host to run the webserver on."),
+        )
+        .arg(
+            clap::Arg::new("port")
+                .long("port")
+                .default_value("3000")
+                .action(ArgAction::Set)
+                .help("Port to run the webserver on."),
+        )
+        .arg(
+            clap::Arg::new("grpc_port")
+                .long("grpc-port")
+                .value_parser(clap::value_parser!(u16))
+                .action(ArgAction::Set)
+                .help("Port to serve the gRPC API on, it's disabled without one."),
+        )
+        .arg(
+            clap::Arg::new("unix_socket")
+                .long("unix-socket")
+                .action(ArgAction::Set)
+                .help("Unix domain socket to listen on instead of a TCP port."),
+        )
+        .arg(
+            clap::Arg::new("tls_cert")
+                .long("tls-cert")
+                .action(ArgAction::Set)
+                .help("PEM file holding the certificate chain to serve HTTPS with."),
+        )
+        .arg(
+            clap::Arg::new("tls_key")
+                .long("tls-key")
+                .action(ArgAction::Set)
+                .help("PEM file holding the private key of the --tls-cert certificate."),
+        )
+        .arg(
+            clap::Arg::new("shutdown_timeout")
+                .long("shutdown-timeout")
+                .default_value("30")
+                .value_parser(clap::value_parser!(u64))
+                .action(ArgAction::Set)
+                .help("Seconds to let in-flight requests finish after SIGTERM or SIGINT."),
+        )
+        .arg(
+            clap::Arg::new("database")
+                .long("database")
+                .default_value("dice-roll.db")
+                .action(ArgAction::Set)
+                .help("SQLite database to record roll history in."),
+        )
+        .arg(
+            clap::Arg::new("signing_key")
+                .long("signing-key")
+                .action(ArgAction::Set)
+                .help("File holding the Ed25519 key roll receipts are signed with, generated when missing."),
+        )
+        .arg(
+            clap::Arg::new("discord_public_key")
+                .long("discord-public-key")
+                .action(ArgAction::Set)
+                .help("Public key of the Discord application allowed to send interactions."),
+        )
+        .arg(
+            clap::Arg::new("slack_signing_secret")
+                .long("slack-signing-secret")
+                .action(ArgAction::Set)
+                .help("Signing secret of the Slack app allowed to send slash commands."),
+        )
+        .arg(
+            clap::Arg::new("mattermost_token")
+                .long("mattermost-token")
+                .action(ArgAction::Set)
+                .help("Token of the Mattermost slash command allowed to send requests."),
+        )
+        .arg(
+            clap::Arg::new("webhook")
+                .long("webhook")
+                .action(ArgAction::Append)
+                .value_delimiter(',')
+                .help("URL to POST rolls to, can be passed multiple times."),
+        )
+        .arg(
+            clap::Arg::new("webhook_secret")
+                .long("webhook-secret")
+                .action(ArgAction::Set)
+                .help("Secret deliveries to the --webhook URLs are signed with."),
+        )
+        .arg(
+            clap::Arg::new("webhook_filter")
+                .long("webhook-filter")
+                .default_value("all")
+                .value_parser(["all", "crits", "fumbles"])
+                .action(ArgAction::Set)
+                .help("Which rolls are POSTed to the --webhook URLs."),
+        )
+        .arg(
+            clap::Arg::new("rate_limit")
+                .long("rate-limit")
+                .default_value("120")
+                .value_parser(clap::value_parser!(u32))
+                .action(ArgAction::Set)
+                .help("Requests each client address can make per minute, 0 disables the limit."),
+        )
+        .arg(
+            clap::Arg::new("api_key_rate_limit")
+                .long("api-key-rate-limit")
+                .default_value("600")
+                .value_parser(clap::value_parser!(u32))
+                .action(ArgAction::Set)
+                .help("Requests each tenant can make per minute unless their policy says otherwise, 0 disables the limit."),
+        )
+        .arg(
+            clap::Arg::new("dice_budget")
+                .long("dice-budget")
+                .default_value("1000")
+                .value_parser(clap::value_parser!(u32).range(dice_roll::MAX_DICE as i64..))
+                .action(ArgAction::Set)
+                .help("Dice each client address, or tenant, can roll per minute."),
+        )
+        .arg(
+            clap::Arg::new("max_body_size")
+                .long("max-body-size")
+                .default_value("16384")
+                .value_parser(clap::value_parser!(usize))
+                .action(ArgAction::Set)
+                .help("Largest request body, or WebSocket message, accepted in bytes."),
+        )
+        .arg(
+            clap::Arg::new("trusted_proxy")
+                .long("trusted-proxy")
+                .action(ArgAction::Append)
+                .value_delimiter(',')
+                .help("Address or network, e.g. 10.0.0.0/8, of a reverse proxy whose X-Forwarded-For and X-Real-IP headers are believed, unix for the Unix socket. Can be passed multiple times."),
+        )
+        .arg(
+            clap::Arg::new("admin_token")
+                .long("admin-token")
+                .action(ArgAction::Set)
+                .help("Bearer token for the admin endpoints managing tenants and API keys, they're disabled without one."),
+        )
+        .arg(
+            clap::Arg::new("anonymous_policy")
+                .long("anonymous-policy")
+                .action(ArgAction::Set)
+                .help("Tenant policy, as JSON, limiting the roll limits and features of requests without an API key."),
+        )
+        .arg(
+            clap::Arg::new("disable_feature")
+                .long("disable-feature")
+                .action(ArgAction::Append)
+                .value_delimiter(',')
+                .value_parser(tenants::Feature::ALL.map(|feature| feature.as_str()))
+                .help("Feature to turn off for everyone, e.g. webhooks, can be passed multiple times."),
+        )
+        .arg(
+            clap::Arg::new("cors_origin")
+                .long("cors-origin")
+                .action(ArgAction::Append)
+                .value_delimiter(',')
+                .help("Origin browsers can call the API from, can be passed multiple times, * allows any origin."),
+        )
+        .arg(
+            clap::Arg::new("log_level")
+                .long("log-level")
+                .default_value("info")
+                .action(ArgAction::Set)
+                .help("Minimum level of logs to output, accepts RUST_LOG style directives."),
+        )
+        .arg(
+            clap::Arg::new("log_format")
+                .long("log-format")
+                .default_value("json")
+                .value_parser(["json", "text"])
+                .action(ArgAction::Set)
+                .help("Format logs are written in."),
+        );
+}
+
+#[tokio::main]
+async fn main() -> ExitCode {
+    let matches = config::with_env_overrides(command()).get_matches();
+    let config = match matches.get_one::<String>("config") {
+        Some(path) => match config::Config::load(path) {
+            Ok(config) => config,
+            Err(e) => {
+                eprintln!("Invalid config file {}: {}", path, e);
+                return ExitCode::FAILURE;
+            }
+        },
+        None => config::Config::default(),
+    };
+    // Parsed again so the file's settings fill in for arguments that weren't passed.
+    let matches = config::with_env_overrides(config.apply(command())).get_matches();
+    if let Err(e) = config.check(&matches) {
+        eprintln!("Invalid settings: {}", e);
+        return ExitCode::FAILURE;
+    }
+
+    let log_level = matches.get_one::<String>("log_level").unwrap();
+    let log_format = matches.get_one::<String>("log_format").unwrap();
+    if let Err(e) = logging::init(log_level, log_format) {
+        eprintln!("Invalid log level {}: {}", log_level, e);
+        return ExitCode::FAILURE;
+    }
+
+    let host = matches.get_one::<String>("host").unwrap();
+    let port = matches.get_one::<String>("port").unwrap();
+    let database = matches.get_one::<String>("database").unwrap();
+
+    let history = match History::open(database) {
+        Ok(history) => history,
+        Err(e) => {
+            tracing::error!(database, error = %e, "Failed to open roll history database");
+            return ExitCode::FAILURE;
+        }
+    };
+    if let Err(e) = history
+        .migrate(fair::SCHEMA)
+        .and_then(|_| history.migrate(tenants::SCHEMA))
+        .and_then(|_| history.migrate(idempotency::SCHEMA))
+        .and_then(|_| history.migrate(campaigns::SCHEMA))
+    {
+        tracing::error!(database, error = %e, "Failed to prepare roll history database");
+        return ExitCode::FAILURE;
+    }
+    let signer = match matches.get_one::<String>("signing_key") {
+        Some(path) => match receipts::load_signer(path) {
+            Ok(signer) => {
+                tracing::info!(public_key = signer.public_key(), "Signing roll receipts");
+                Some(Arc::new(signer))
+            }
+            Err(e) => {
+                tracing::error!(path, error = e, "Failed to load signing key");
+                return ExitCode::FAILURE;
+            }
+        },
+        None => None,
+    };
+    let discord_public_key = match matches.get_one::<String>("discord_public_key") {
+        Some(public_key) => match discord::parse_public_key(public_key) {
+            Ok(public_key) => Some(public_key),
+            Err(e) => {
+                tracing::error!(error = e, "Invalid Discord public key");
+                return ExitCode::FAILURE;
+            }
+        },
+        None => None,
+    };
+    let webhook_secret = matches.get_one::<String>("webhook_secret");
+    let webhook_filter = matches.get_one::<String>("webhook_filter").unwrap();
+    let configured_webhooks = matches
+        .get_many::<String>("webhook")
+        .unwrap_or_default()
+        .enumerate()
+        .map(|(index, url)| webhooks::Webhook {
+            id: format!("configured-{}", index + 1),
+            url: url.clone(),
+            secret: webhook_secret.cloned().unwrap_or_default(),
+            filter: webhooks::WebhookFilter::parse(webhook_filter).unwrap_or_default(),
+            session: None,
+            campaign: None,
+            tenant: None,
+            configured: true,
+        })
+        .collect();
+    let webhooks = match webhooks::Webhooks::new(history.clone(), configured_webhooks) {
+        Ok(webhooks) => webhooks,
+        Err(e) => {
+            tracing::error!(error = e, "Failed to load webhooks");
+            return ExitCode::FAILURE;
+        }
+    };
+    let anonymous_policy = match matches.get_one::<String>("anonymous_policy") {
+        Some(policy) => match tenants::TenantPolicy::parse_anonymous(policy) {
+            Ok(policy) => policy,
+            Err(e) => {
+                tracing::error!(error = e, "Invalid anonymous policy");
+                return ExitCode::FAILURE;
+            }
+        },
+        None => tenants::TenantPolicy::default(),
+    };
+    let disabled_features = matches
+        .get_many::<String>("disable_feature")
+        .unwrap_or_default()
+        .filter_map(|name| tenants::Feature::parse(name))
+        .collect();
+    let tenants = match tenants::Tenants::load(history.clone(), anonymous_policy, disabled_features)
+    {
+        Ok(tenants) => tenants,
+        Err(e) => {
+            tracing::error!(error = e, "Failed to load API keys");
+            return ExitCode::FAILURE;
+        }
+    };
+    let metrics = match metrics::Metrics::new() {
+        Ok(metrics) => metrics,
+        Err(e) => {
+            tracing::error!(error = %e, "Failed to register metrics");
+            return ExitCode::FAILURE;
+        }
+    };
+    let limits = limits::Limits {
+        per_ip: limits::TokenBuckets::per_minute(*matches.get_one::<u32>("rate_limit").unwrap()),
+        per_api_key: limits::TokenBuckets::per_minute(
+            *matches.get_one::<u32>("api_key_rate_limit").unwrap(),
+        ),
+        dice_budget: limits::TokenBuckets::per_minute(
+            *matches.get_one::<u32>("dice_budget").unwrap(),
+        ),
+        max_body_size: *matches.get_one::<usize>("max_body_size").unwrap(),
+    };
+    let proxies = matches
+        .get_many::<String>("trusted_proxy")
+        .unwrap_or_default()
+        .map(String::as_str);
+    let proxies = match proxies::TrustedProxies::parse(proxies) {
+        Ok(proxies) => proxies,
+        Err(e) => {
+            tracing::error!(error = e, "Invalid trusted proxy");
+            return ExitCode::FAILURE;
+        }
+    };
+    let cors_origins = matches
+        .get_many::<String>("cors_origin")
+        .unwrap_or_default()
+        .cloned()
+        .collect::<Vec<_>>();
+    let cors = if cors_origins.is_empty() {
+        None
+    } else {
+        match web::cors(&cors_origins) {
+            Ok(cors) => Some(cors),
+            Err(e) => {
+                tracing::error!(error = e, "Invalid CORS origin");
+                return ExitCode::FAILURE;
+            }
+        }
+    };
+    let state = AppState {
+        history,
+        rooms: rooms::Rooms::default(),
+        sessions: sessions::Sessions::default(),
+        signer,
+        discord_public_key,
+        slash_commands: slack::SlashCommandSecrets {
+            slack_signing_secret: matches.get_one::<String>("slack_signing_secret").cloned(),
+            mattermost_token: matches.get_one::<String>("mattermost_token").cloned(),
+        },
+        webhooks,
+        metrics,
+        limits,
+        proxies,
+        tenants,
+        admin_token: matches.get_one::<String>("admin_token").cloned(),
+        graphql: graphql::schema(),
+    };
+
+    let app = app(state.clone(), cors);
+
+    let endpoint = match matches.get_one::<String>("unix_socket") {
+        Some(path) => server::Endpoint::Unix(PathBuf::from(path)),
+        None => server::Endpoint::Tcp {
+            host: host.clone(),
+            port: port.clone(),
+            tls: matches
+                .get_one::<String>("tls_cert")
+                .zip(matches.get_one::<String>("tls_key"))
+                .map(|(certificate, key)| server::TlsFiles {
+                    certificate: certificate.clone(),
+                    key: key.clone(),
+                }),
+        },
+    };
+    let listener = match server::bind(endpoint).await {
+        Ok(listener) => listener,
+        Err(e) => {
+            tracing::error!(error = e, "Failed to start server");
+            return ExitCode::FAILURE;
+        }
+    };
+    let grpc_listener = match matches.get_one::<u16>("grpc_port") {
+        Some(grpc_port) => {
+            let address = format!("{}:{}", host, grpc_port);
+            match tokio::net::TcpListener::bind(&address).await {
+                Ok(listener) => Some(listener),
+                Err(e) => {
+                    tracing::error!(address, error = %e, "Failed to start gRPC server");
+                    return ExitCode::FAILURE;
+                }
+            }
+        }
+        None => None,
+    };
+    let drain_timeout = Duration::from_secs(*matches.get_one::<u64>("shutdown_timeout").unwrap());
+    let http = async {
+        return server::serve(listener, app, drain_timeout)
+            .await
+            .map_err(|e| tracing::error!(error = %e, "Server stopped unexpectedly"));
+    };
+    let grpc = async {
+        let served = match grpc_listener {
+            Some(listener) => grpc::serve(listener, state, drain_timeout).await,
+            None => Ok(()),
+        };
+        return served.map_err(|e| tracing::error!(error = %e, "gRPC server stopped unexpectedly"));
+    };
+    // Either server failing stops the other one.
+    if tokio::try_join!(http, grpc).is_err() {
+        return ExitCode::FAILURE;
+    }
+    tracing::info!("Server stopped");
+
+    return ExitCode::SUCCESS;
+}
+
+/// Routes of the API and the middleware every request goes through.
+fn app(state: AppState, cors: Option<tower_http::cors::CorsLayer>) -> Router {
+    let v1 = Router::new()
+        .route("/rolls", post(roll).get(history::list_rolls))
+        .route("/rolls:batch", post(batch::roll_batch))
+        .route("/rolls/{id}", get(history::get_roll))
+        .route(
+            "/rolls/notation",
+            post(roll_expression).get(roll_expression_query),
+        )
+        .route("/schemas/{name}", get(schema))
+        .route(
+            "/graphql",
+            post(graphql::graphql).get(graphql::graphql_socket),
+        )
+        .route("/graphql/schema", get(graphql::graphql_schema))
+        .route("/rooms/{id}/ws", get(rooms::room_socket))
+        .route("/sessions/{id}/rolls", post(sessions::session_roll))
+        .route("/sessions/{id}/events", get(sessions::session_events))
+        .route(
+            "/campaigns",
+            post(campaigns::create_campaign).get(campaigns::list_campaigns),
+        )
+        .route("/campaigns/{id}", get(campaigns::get_campaign))
+        .route("/campaigns/{id}/close", post(campaigns::close_campaign))
+        .route(
+            "/campaigns/{id}/participants",
+            post(campaigns::join_campaign).get(campaigns::list_participants),
+        )
+        .route(
+            "/campaigns/{id}/sessions",
+            post(campaigns::create_session).get(campaigns::list_sessions),
+        )
+        .route(
+            "/campaigns/{id}/sessions/{session}/close",
+            post(campaigns::close_session),
+        )
+        .route(
+            "/campaigns/{id}/rolls",
+            post(campaigns::campaign_roll).get(campaigns::list_campaign_rolls),
+        )
+        .route("/fair/seeds", post(fair::create_seed))
+        .route("/fair/seeds/{id}", get(fair::get_seed))
+        .route("/fair/seeds/{id}/reveal", post(fair::reveal_seed))
+        .route("/fair/seeds/{id}/rolls", post(fair::fair_roll))
+        .route("/receipts", post(receipts::create_receipt))
+        .route("/receipts/key", get(receipts::receipt_key))
+        .route(
+            "/webhooks",
+            post(webhooks::create_webhook).get(webhooks::list_webhooks),
+        )
+        .route("/webhooks/{id}", delete(webhooks::delete_webhook))
+        .route("/webhooks/dead-letters", get(webhooks::dead_letters))
+        .route("/admin/tenants", get(tenants::list_tenants))
+        .route("/admin/tenants/{id}", put(tenants::put_tenant))
+        .route("/admin/tenants/{id}/keys", post(tenants::create_key))
+        .route("/admin/keys", get(tenants::list_keys))
+        .route("/admin/keys/{id}", delete(tenants::revoke_key));
+    return Router::new()
+        .nest("/v1", v1)
+        // Unversioned aliases kept for compatibility with existing clients.
+        .route("/", get(web::index).post(roll))
+        .route("/roll", post(roll_expression).get(roll_expression_query))
+        .route("/discord/interactions", post(discord::interactions))
+        .route("/slack/commands", post(slack::slash_command))
+        .route("/healthz", get(metrics::healthz))
+        .route("/readyz", get(metrics::readyz))
+        .route("/metrics", get(metrics::metrics))
+        .merge(SwaggerUi::new("/docs").url("/openapi.json", ApiDoc::openapi()))
+        .fallback(not_found)
+        .method_not_allowed_fallback(method_not_allowed)
+        .layer(DefaultBodyLimit::max(state.limits.max_body_size))
+        .layer(middleware::from_fn_with_state(
+            state.clone(),
+            idempotency::idempotency,
+        ))
+        .layer(middleware::from_fn_with_state(
+            state.clone(),
+            tenants::authenticate,
+        ))
+        .layer(middleware::from_fn_with_state(
+            state.clone(),
+            limits::enforce,
+        ))
+        .layer(middleware::from_fn_with_state(
+            state.clone(),
+            proxies::resolve,
+        ))
+        .layer(middleware::from_fn_with_state(
+            state.clone(),
+            metrics::track,
+        ))
+        .layer(
+            ServiceBuilder::new()
+                .layer(SetRequestIdLayer::new(
+                    logging::REQUEST_ID_HEADER,
+                    MakeRequestUuid,
+                ))
+                .layer(
+                    TraceLayer::new_for_http()
+                        .make_span_with(logging::request_span)
+                        .on_response(DefaultOnResponse::new().level(Level::INFO)),
+                )
+                .layer(PropagateRequestIdLayer::new(logging::REQUEST_ID_HEADER))
+                .option_layer(cors),
+        )
+        .with_state(state);
+}
+
+/// Rolls the provided dice.
+#[utoipa::path(
+    post,
+    tag = "dice-roll",
+    path = "/v1/rolls",
+    params(HistoryTags),
+    request_body = RollRequest,
+    responses(
+        (status = 200, description = "Dice were rolled", content(
+            (RollResponse = "application/json"),
+            (String = "text/plain"),
+            (RollResponse = "application/cbor"),
+            (RollResponse = "application/msgpack"),
+        )),
+        (status = 400, description = "Invalid roll request", content_type = "application/problem+json", body = Problem),
+        (status = 406, description = "None of the accepted media types are supported", content_type = "application/problem+json", body = Problem),
+    )
+)]
+pub async fn roll(
+    State(state): State<AppState>,
+    format: Format,
+    client: Client,
+    tags: Result<Query<HistoryTags>, QueryRejection>,
+    payload: Result<Json<RollRequest>, JsonRejection>,
+) -> Response {
+    let tags = match HistoryTags::from_query(tags) {
+        Ok(tags) => tags,
+        Err(problem) => {
+            return format.problem(problem);
+        }
+    };
+    let roll_request = match payload {
+        Ok(roll_request) => roll_request,
+        Err(e) => {
+            return format.problem(Problem::from_json_rejection(e));
+        }
+    };
+    let context = RollContext {
+        session: tags.session,
+        campaign: tags.campaign,
+        ..client.context()
+    };
+    return respond_with_roll(
+        format,
+        roll_and_record(&state, roll_request.0, context).await,
+    );
+}
+
+/// Parses the provided dice roll notation and rolls the resulting dice.
+#[utoipa::path(
+    post,
+    tag = "dice-roll",
+    path = "/v1/rolls/notation",
+    params(HistoryTags),
+    request_body = ExpressionRequest,
+    responses(
+        (status = 200, description = "Dice were rolled", content(
+            (RollResponse = "application/json"),
+            (String = "text/plain"),
+            (RollResponse = "application/cbor"),
+            (RollResponse = "application/msgpack"),
+        )),
+        (status = 400, description = "Invalid expression", content_type = "application/problem+json", body = Problem),
+        (status = 406, description = "None of the accepted media types are supported", content_type = "application/problem+json", body = Problem),
+    )
+)]
+pub async fn roll_expression(
+    State(state): State<AppState>,
+    format: Format,
+    client: Client,
+    tags: Result<Query<HistoryTags>, QueryRejection>,
+    payload: Result<Json<ExpressionRequest>, JsonRejection>,
+) -> Response {
+    let tags = match HistoryTags::from_query(tags) {
+        Ok(tags) => tags,
+        Err(problem) => {
+            return format.problem(problem);
+        }
+    };
+    let expression_request = match payload {
+        Ok(expression_request) => expression_request,
+        Err(e) => {
+            return format.problem(Problem::from_json_rejection(e));
+        }
+    };
+    let context = RollContext {
+        session: tags.session,
+        campaign: tags.campaign,
+        ..client.context()
+    };
+    let result = roll_notation(
+        &state,
+        "expression",
+        expression_request.0.expression,
+        context,
+    )
+    .await;
+    return respond_with_roll(format, result);
+}
+
+/// Parses the dice roll notation in the `q` query parameter and rolls the resulting dice.
+#[utoipa::path(
+    get,
+    tag = "dice-roll",
+    path = "/v1/rolls/notation",
+    params(ExpressionQuery, HistoryTags),
+    responses(
+        (status = 200, description = "Dice were rolled", content(
+            (RollResponse = "application/json"),
+            (String = "text/plain"),
+            (RollResponse = "application/cbor"),
+            (RollResponse = "application/msgpack"),
+        )),
+        (status = 400, description = "Invalid expression", content_type = "application/problem+json", body = Problem),
+        (status = 406, description = "None of the accepted media types are supported", content_type = "application/problem+json", body = Problem),
+    )
+)]
+pub async fn roll_expression_query(
+    State(state): State<AppState>,
+    format: Format,
+    client: Client,
+    tags: Result<Query<HistoryTags>, QueryRejection>,
+    query: Result<Query<ExpressionQuery>, QueryRejection>,
+) -> Response {
+    let tags = match HistoryTags::from_query(tags) {
+        Ok(tags) => tags,
+        Err(problem) => {
+            return format.problem(problem);
+        }
+    };
+    let expression_query = match query {
+        Ok(expression_query) => expression_query,
+        Err(e) => {
+            return format.problem(Problem::from_query_rejection(e));
+        }
+    };
+    let context = RollContext {
+        session: tags.session,
+        campaign: tags.campaign,
+        ..client.context()
+    };
+    let result = roll_notation(&state, "q", expression_query.0.q, context).await;
+    return respond_with_roll(format, result);
+}
+
+fn respond_with_roll(format: Format, result: Result<RollResponse, Problem>) -> Response {
+    match result {
+        Ok(roll_response) => {
+            return format.respond(StatusCode::OK, roll_response);
+        }
+        Err(problem) => {
+            return format.problem(problem);
+        }
+    }
+}
+
+/// Parses dice roll notation into a roll request. Parser errors point at `field`.
+pub fn parse_notation(field: &str, expression: &str) -> Result<RollRequest, Problem> {
+    match parser::parse(expression.trim().to_string()) {
+        Ok(roll_request) => return Ok(roll_request),
+        Err(e) => return Err(Problem::from(e).with_field(field.to_string())),
+    }
+}
+
+/// Checks a roll against the limits of the tenant making it, or of requests without an API key,
+/// then charges it to their dice budget.
+pub fn admit_roll(
+    state: &AppState,
+    context: &RollContext,
+    roll_request: &RollRequest,
+) -> Result<(), Problem> {
+    let limits = state.tenants.limits(context.tenant.as_deref());
+    if let Err(e) = roll_request.validate(&limits) {
+        return Err(Problem::from(e));
+    }
+
+    return state.limits.charge_dice(context, roll_request);
+}
+
+/// Rolls the request's dice and records the result in the roll history.
+pub async fn roll_and_record(
+    state: &AppState,
+    roll_request: RollRequest,
+    context: RollContext,
+) -> Result<RollResponse, Problem> {
+    admit_roll(state, &context, &roll_request)?;
+    let roll_response = match roll_request.roll_dice() {
+        Ok(roll_response) => roll_response,
+        Err(e) => return Err(Problem::from(e)),
+    };
+    record_roll(state, context, &roll_request, &roll_response).await?;
+
+    return Ok(roll_response);
+}
+
+/// A roll stored in the roll history.
+pub struct RecordedRoll {
+    /// Id of the roll in the history.
+    pub id: i64,
+    pub timestamp: chrono::DateTime<chrono::Utc>,
+    /// The roll as sent to its session's followers, for rolls made in a session.
+    pub session_roll: Option<sessions::SessionRoll>,
+}
+
+/// Records a roll in the history, then notifies the webhooks and, for rolls made in a session,
+/// the session's followers.
+pub async fn record_roll(
+    state: &AppState,
+    context: RollContext,
+    roll_request: &RollRequest,
+    roll_response: &RollResponse,
+) -> Result<RecordedRoll, Problem> {
+    return record_roll_with(state, context, roll_request, roll_response, |_, _| Ok(())).await;
+}
+
+/// Records a roll like [`record_roll`], along with what `log` stores about it, see
+/// [`History::record_with`].
+pub async fn record_roll_with<F>(
+    state: &AppState,
+    context: RollContext,
+    roll_request: &RollRequest,
+    roll_response: &RollResponse,
+    log: F,
+) -> Result<RecordedRoll, Problem>
+where
+    F: FnOnce(&rusqlite::Connection, i64) -> Result<(), rusqlite::Error> + Send + 'static,
+{
+    let event = webhooks::RollEvent {
+        event: "roll",
+        roll_id: 0,
+        timestamp: chrono::Utc::now(),
+        session: context.session.clone(),
+        campaign: context.campaign.clone(),
+        tenant: context.tenant.as_ref().map(|tenant| tenant.id.clone()),
+        player: context.player.clone(),
+        expression: context.expression.clone(),
+        result: roll_response.clone(),
+    };
+    let hidden = context.hidden;
+    // Only tenants allowed to follow sessions have their rolls sent to them.
+    let follows_sessions = state
+        .tenants
+        .require(context.tenant.as_deref(), tenants::Feature::Sessions)
+        .is_ok();
+    let (roll_id, timestamp) = state
+        .history
+        .record_with(context, roll_request, roll_response, log)
+        .await?;
+    tracing::info!(
+        roll_id,
+        expression = event.expression,
+        session = event.session,
+        campaign = event.campaign,
+        player = event.player,
+        "Roll recorded"
+    );
+    state.metrics.record_roll(roll_response);
+    if hidden {
+        return Ok(RecordedRoll {
+            id: roll_id,
+            timestamp,
+            session_roll: None,
+        });
+    }
+
+    let session_roll = match &event.session {
+        Some(session) if follows_sessions => Some(
+            state.sessions.record(
+                event.tenant.as_deref(),
+                session,
+                event.player.clone(),
+                event
+                    .expression
+                    .clone()
+                    .unwrap_or_else(|| roll_request.to_string()),
+                roll_response.clone(),
+            ),
+        ),
+        _ => None,
+    };
+    state.webhooks.notify(webhooks::RollEvent {
+        roll_id,
+        timestamp,
+        ..event
+    });
+
+    return Ok(RecordedRoll {
+        id: roll_id,
+        timestamp,
+        session_roll,
+    });
+}
+
+/// Parses dice roll notation, then rolls and records the resulting dice.
+pub async fn roll_notation(
+    state: &AppState,
+    field: &str,
+    expression: String,
+    context: RollContext,
+) -> Result<RollResponse, Problem> {
+    let roll_request = parse_notation(field, &expression)?;
+    let context = RollContext {
+        expression: Some(expression),
+        ..context
+    };
+
+    return roll_and_record(state, roll_request, context).await;
+}
+
+/// Returns a standalone JSON Schema document for one of the API's payloads, e.g. `RollRequest`.
+#[utoipa::path(
+    get,
+    tag = "dice-roll",
+    path = "/v1/schemas/{name}",
+    params(("name" = String, Path, description = "Name of the schema, e.g. `RollRequest`.")),
+    responses(
+        (status = 200, description = "JSON Schema document", content_type = "application/schema+json", body = Object),
+        (status = 404, description = "Unknown schema", content_type = "application/problem+json", body = Problem),
+    )
+)]
+pub async fn schema(Path(name): Path<String>) -> Response {
+    match openapi::json_schema(&name) {
+        Some(schema) => {
+            return (
+                [(header::CONTENT_TYPE, "application/schema+json")],
+                Json(schema),
+            )
+                .into_response();
+        }
+        None => {
+            return Problem::new(
+                StatusCode::NOT_FOUND,
+                "UNKNOWN_SCHEMA",
+                format!(
+                    "No schema named {} exists. Available schemas are {}.",
+                    name,
+                    openapi::schema_names().join(", ")
+                ),
+            )
+            .into_response();
+        }
+    }
+}
+
+async fn not_found(format: Format) -> Response {
+    return format.problem(Problem::new(
+        StatusCode::NOT_FOUND,
+        "NOT_FOUND",
+        "No endpoint exists at the requested path.".to_string(),
+    ));
+}
+
+async fn method_not_allowed(format: Format) -> Response {
+    return format.problem(Problem::new(
+        StatusCode::METHOD_NOT_ALLOWED,
+        "METHOD_NOT_ALLOWED",
+        "The requested endpoint does not support this HTTP method.".to_string(),
+    ));
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::SocketAddr;
+
+    use axum::{
+        body::{Body, to_bytes},
+        extract::ConnectInfo,
+        http::{Method, Request, header},
+    };
+    use tower::ServiceExt;
+
+    use super::*;
+
+    pub const ADMIN_TOKEN: &str = "admin-token";
+
+    /// Server state with an in-memory history and no rooms.
+    pub fn state() -> AppState {
+        let history = History::open(":memory:").unwrap();
+        history
+            .migrate(fair::SCHEMA)
+            .and_then(|_| history.migrate(tenants::SCHEMA))
+            .and_then(|_| history.migrate(idempotency::SCHEMA))
+            .and_then(|_| history.migrate(campaigns::SCHEMA))
+            .unwrap();
+        return AppState {
+            webhooks: webhooks::Webhooks::new(history.clone(), Vec::new()).unwrap(),
+            metrics: metrics::Metrics::new().unwrap(),
+            limits: limits::Limits {
+                per_ip: limits::TokenBuckets::per_minute(120),
+                per_api_key: limits::TokenBuckets::per_minute(600),
+                dice_budget: limits::TokenBuckets::per_minute(1000),
+                max_body_size: 16384,
+            },
+            proxies: proxies::TrustedProxies::default(),
+            rooms: rooms::Rooms::default(),
+            sessions: sessions::Sessions::default(),
+            signer: None,
+            discord_public_key: None,
+            slash_commands: slack::SlashCommandSecrets::default(),
+            tenants: tenants::Tenants::load(
+                history.clone(),
+                tenants::TenantPolicy::default(),
+                Vec::new(),
+            )
+            .unwrap(),
+            admin_token: Some(ADMIN_TOKEN.to_string()),
+            graphql: graphql::schema(),
+            history,
+        };
+    }
+
+    /// A request with a JSON body when one is provided.
+    pub fn request(method: Method, uri: &str, body: Option<serde_json::Value>) -> Request<Body> {
+        let mut request = Request::builder().method(method).uri(uri);
+        let body = match body {
+            Some(body) => {
+                request = request.header(header::CONTENT_TYPE, "application/json");
+                Body::from(body.to_string())
+            }
+            None => Body::empty(),
+        };
+
+        let mut request = request.body(body).unwrap();
+        request
+            .extensions_mut()
+            .insert(ConnectInfo(SocketAddr::from(([127, 0, 0, 1], 40000))));
+        return request;
+    }
+
+    /// Sends a request through the API's routes, returning the response status and its body
+    /// parsed as JSON, or `Null` when it isn't JSON.
+    pub async fn send(state: &AppState, request: Request<Body>) -> (StatusCode, serde_json::Value) {
+        let (status, _, body) = respond(state, request).await;
+
+        return (
+            status,
+            serde_json::from_slice(&body).unwrap_or(serde_json::Value::Null),
+        );
+    }
+
+    /// Sends a request through the API's routes, returning the response status, headers and
+    /// raw body.
+    pub async fn respond(
+        state: &AppState,
+        request: Request<Body>,
+    ) -> (StatusCode, axum::http::HeaderMap, Vec<u8>) {
+        let response = app(state.clone(), None).oneshot(request).await.unwrap();
+        let (parts, body) = response.into_parts();
+        let body = to_bytes(body, usize::MAX).await.unwrap();
+
+        return (parts.status, parts.headers, body.to_vec());
+    }
+
+    /// Creates a tenant with the policy through the admin endpoints, returning an API key for it.
+    pub async fn api_key(state: &AppState, tenant: &str, policy: serde_json::Value) -> String {
+        let mut put = request(
+            Method::PUT,
+            &format!("/v1/admin/tenants/{}", tenant),
+            Some(policy),
+        );
+        let mut create = request(
+            Method::POST,
+            &format!("/v1/admin/tenants/{}/keys", tenant),
+            None,
+        );
+        for request in [&mut put, &mut create] {
+            request.headers_mut().insert(
+                header::AUTHORIZATION,
+                format!("Bearer {}", ADMIN_TOKEN).parse().unwrap(),
+            );
+        }
+        assert_eq!(send(state, put).await.0, StatusCode::OK);
+        let (status, body) = send(state, create).await;
+        assert_eq!(status, StatusCode::CREATED);
+
+        return body["key"].as_str().unwrap().to_string();
+    }
+
+    /// Adds a header to a request built with [`request`].
+    pub fn with_header(
+        mut request: Request<Body>,
+        name: &'static str,
+        value: &str,
+    ) -> Request<Body> {
+        request.headers_mut().insert(name, value.parse().unwrap());
+        return request;
+    }
+
+    #[tokio::test]
+    async fn rolls_notation() {
+        let state = state();
+        let (status, body) = send(
+            &state,
+            request(
+                Method::POST,
+                "/v1/rolls/notation",
+                Some(serde_json::json!({"expression": "2d6 + 1"})),
+            ),
+        )
+        .await;
+
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body["rolls"][0]["rolls"].as_array().unwrap().len(), 2);
+    }
+
+    #[tokio::test]
+    async fn rejects_unknown_fields() {
+        let state = state();
+        let (status, body) = send(
+            &state,
+            request(
+                Method::POST,
+                "/v1/rolls",
+                Some(serde_json::json!({"dice": [{"sides": 20, "bonus": 2}]})),
+            ),
+        )
+        .await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert_eq!(body["code"], "UNKNOWN_FIELD");
+        assert_eq!(body["field"], "dice[0].bonus");
+
+        let (status, body) = send(
+            &state,
+            request(
+                Method::POST,
+                "/v1/rolls/notation",
+                Some(serde_json::json!({"expression": "1d20", "expresion": "1d4"})),
+            ),
+        )
+        .await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert_eq!(body["field"], "expresion");
+    }
+}
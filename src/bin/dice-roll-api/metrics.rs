@@ -0,0 +1,239 @@
+use std::time::Instant;
+
+use axum::{
+    Json,
+    extract::{MatchedPath, Request, State},
+    http::{StatusCode, header},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use dice_roll::RollResponse;
+use prometheus::{
+    Encoder, HistogramOpts, HistogramVec, IntCounterVec, Opts, Registry, TextEncoder,
+};
+use serde_json::json;
+
+use crate::{
+    AppState,
+    response::{Problem, ProblemCode},
+};
+
+/// Prometheus metrics collected by the server.
+#[derive(Clone)]
+pub struct Metrics {
+    registry: Registry,
+    requests: IntCounterVec,
+    request_duration: HistogramVec,
+    validation_errors: IntCounterVec,
+    dice_rolled: IntCounterVec,
+}
+
+impl Metrics {
+    pub fn new() -> Result<Metrics, prometheus::Error> {
+        let registry = Registry::new_custom(Some("dice_roll".to_string()), None)?;
+        let requests = IntCounterVec::new(
+            Opts::new("http_requests_total", "HTTP requests handled."),
+            &["route", "method", "status"],
+        )?;
+        let request_duration = HistogramVec::new(
+            HistogramOpts::new(
+                "http_request_duration_seconds",
+                "Time taken to handle HTTP requests.",
+            ),
+            &["route", "method"],
+        )?;
+        let validation_errors = IntCounterVec::new(
+            Opts::new(
+                "validation_errors_total",
+                "Requests rejected as invalid, by problem code.",
+            ),
+            &["code"],
+        )?;
+        let dice_rolled = IntCounterVec::new(
+            Opts::new("dice_rolled_total", "Dice rolled, by number of sides."),
+            &["sides"],
+        )?;
+        registry.register(Box::new(requests.clone()))?;
+        registry.register(Box::new(request_duration.clone()))?;
+        registry.register(Box::new(validation_errors.clone()))?;
+        registry.register(Box::new(dice_rolled.clone()))?;
+
+        return Ok(Metrics {
+            registry,
+            requests,
+            request_duration,
+            validation_errors,
+            dice_rolled,
+        });
+    }
+
+    pub fn record_roll(&self, roll_response: &RollResponse) {
+        for (sides, count) in roll_response.dice_rolled() {
+            self.dice_rolled
+                .with_label_values(&[sides.to_string()])
+                .inc_by(count.max(0) as u64);
+        }
+    }
+}
+
+/// Counts and times every request by its route template, e.g. `/v1/rolls/{id}`.
+pub async fn track(State(state): State<AppState>, request: Request, next: Next) -> Response {
+    let route = match request.extensions().get::<MatchedPath>() {
+        Some(path) => path.as_str().to_string(),
+        None => "unmatched".to_string(),
+    };
+    let method = request.method().to_string();
+    let started = Instant::now();
+    let response = next.run(request).await;
+
+    let metrics = &state.metrics;
+    metrics
+        .request_duration
+        .with_label_values(&[&route, &method])
+        .observe(started.elapsed().as_secs_f64());
+    metrics
+        .requests
+        .with_label_values(&[&route, &method, response.status().as_str()])
+        .inc();
+    if response.status() == StatusCode::BAD_REQUEST
+        && let Some(ProblemCode(code)) = response.extensions().get::<ProblemCode>()
+    {
+        metrics.validation_errors.with_label_values(&[*code]).inc();
+    }
+
+    return response;
+}
+
+/// Liveness probe, answers as long as the server is running.
+#[utoipa::path(
+    get,
+    tag = "operations",
+    path = "/healthz",
+    responses((status = 200, description = "Server is running", body = Object))
+)]
+pub async fn healthz() -> Response {
+    return Json(json!({ "status": "ok" })).into_response();
+}
+
+/// Readiness probe, answers once the roll history database can be queried.
+#[utoipa::path(
+    get,
+    tag = "operations",
+    path = "/readyz",
+    responses(
+        (status = 200, description = "Server is ready to take requests", body = Object),
+        (status = 503, description = "Server is not ready", content_type = "application/problem+json", body = Problem),
+    )
+)]
+pub async fn readyz(State(state): State<AppState>) -> Response {
+    let result = state
+        .history
+        .with_connection(|connection| {
+            return connection.query_row("SELECT 1", [], |row| row.get::<_, i64>(0));
+        })
+        .await;
+
+    match result {
+        Ok(_) => return Json(json!({ "status": "ready" })).into_response(),
+        Err(_) => {
+            return Problem::new(
+                StatusCode::SERVICE_UNAVAILABLE,
+                "NOT_READY",
+                "The roll history database can not be queried.".to_string(),
+            )
+            .into_response();
+        }
+    }
+}
+
+/// Metrics in the Prometheus text exposition format.
+#[utoipa::path(
+    get,
+    tag = "operations",
+    path = "/metrics",
+    responses((status = 200, description = "Prometheus metrics", content_type = "text/plain", body = String))
+)]
+pub async fn metrics(State(state): State<AppState>) -> Response {
+    let encoder = TextEncoder::new();
+    let mut buffer = Vec::new();
+    if let Err(e) = encoder.encode(&state.metrics.registry.gather(), &mut buffer) {
+        return Problem::new(
+            StatusCode::INTERNAL_SERVER_ERROR,
+            "METRICS_UNAVAILABLE",
+            format!("Failed to encode metrics, {}.", e),
+        )
+        .into_response();
+    }
+
+    return (
+        [(header::CONTENT_TYPE, encoder.format_type().to_string())],
+        buffer,
+    )
+        .into_response();
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::http::Method;
+    use serde_json::json;
+
+    use super::*;
+    use crate::tests::{request, respond, send, state};
+
+    #[tokio::test]
+    async fn probes_answer() {
+        let state = state();
+        let (status, body) = send(&state, request(Method::GET, "/healthz", None)).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body["status"], "ok");
+
+        let (status, body) = send(&state, request(Method::GET, "/readyz", None)).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body["status"], "ready");
+    }
+
+    #[tokio::test]
+    async fn counts_requests_dice_and_validation_errors() {
+        let state = state();
+        let roll = |expression: &str| {
+            return request(
+                Method::POST,
+                "/v1/rolls/notation",
+                Some(json!({ "expression": expression })),
+            );
+        };
+        send(&state, roll("2d6 + 1")).await;
+        send(&state, roll("1d20")).await;
+        send(&state, roll("2d")).await;
+
+        let (status, headers, body) = respond(&state, request(Method::GET, "/metrics", None)).await;
+        assert_eq!(status, StatusCode::OK);
+        assert!(
+            headers[header::CONTENT_TYPE]
+                .to_str()
+                .unwrap()
+                .starts_with("text/plain")
+        );
+        let body = String::from_utf8(body).unwrap();
+        for line in [
+            r#"dice_roll_http_requests_total{method="POST",route="/v1/rolls/notation",status="200"} 2"#,
+            r#"dice_roll_http_requests_total{method="POST",route="/v1/rolls/notation",status="400"} 1"#,
+            r#"dice_roll_dice_rolled_total{sides="6"} 2"#,
+            r#"dice_roll_dice_rolled_total{sides="20"} 1"#,
+            r#"dice_roll_http_request_duration_seconds_count{method="POST",route="/v1/rolls/notation"} 3"#,
+        ] {
+            assert!(
+                body.lines().any(|l| l == line),
+                "{} missing from\n{}",
+                line,
+                body
+            );
+        }
+        assert!(
+            body.lines()
+                .any(|line| line.starts_with("dice_roll_validation_errors_total{code=")),
+            "{}",
+            body
+        );
+    }
+}
@@ -0,0 +1,193 @@
+use serde_json::{Value, json};
+use utoipa::{
+    Modify, OpenApi,
+    openapi::security::{ApiKey, ApiKeyValue, HttpAuthScheme, HttpBuilder, SecurityScheme},
+};
+
+use crate::{response::Problem, rooms::RoomEvent, webhooks::RollEvent};
+
+#[derive(OpenApi)]
+#[openapi(
+    info(title = "dice-roll-api", description = "Dice rolls as a service"),
+    paths(
+        crate::roll,
+        crate::roll_expression,
+        crate::roll_expression_query,
+        crate::batch::roll_batch,
+        crate::schema,
+        crate::graphql::graphql,
+        crate::graphql::graphql_socket,
+        crate::graphql::graphql_schema,
+        crate::rooms::room_socket,
+        crate::sessions::session_roll,
+        crate::sessions::session_events,
+        crate::history::list_rolls,
+        crate::history::get_roll,
+        crate::campaigns::create_campaign,
+        crate::campaigns::list_campaigns,
+        crate::campaigns::get_campaign,
+        crate::campaigns::close_campaign,
+        crate::campaigns::join_campaign,
+        crate::campaigns::list_participants,
+        crate::campaigns::create_session,
+        crate::campaigns::list_sessions,
+        crate::campaigns::close_session,
+        crate::campaigns::campaign_roll,
+        crate::campaigns::list_campaign_rolls,
+        crate::fair::create_seed,
+        crate::fair::get_seed,
+        crate::fair::reveal_seed,
+        crate::fair::fair_roll,
+        crate::receipts::create_receipt,
+        crate::receipts::receipt_key,
+        crate::discord::interactions,
+        crate::slack::slash_command,
+        crate::webhooks::create_webhook,
+        crate::webhooks::list_webhooks,
+        crate::webhooks::delete_webhook,
+        crate::webhooks::dead_letters,
+        crate::tenants::list_tenants,
+        crate::tenants::put_tenant,
+        crate::tenants::create_key,
+        crate::tenants::list_keys,
+        crate::tenants::revoke_key,
+        crate::metrics::healthz,
+        crate::metrics::readyz,
+        crate::metrics::metrics
+    ),
+    components(schemas(Problem, RoomEvent, RollEvent)),
+    modifiers(&SecuritySchemes)
+)]
+pub struct ApiDoc;
+
+/// Declares how API keys, participant and seed tokens and the admin token are sent.
+struct SecuritySchemes;
+
+impl Modify for SecuritySchemes {
+    fn modify(&self, openapi: &mut utoipa::openapi::OpenApi) {
+        let components = openapi.components.get_or_insert_with(Default::default);
+        components.add_security_scheme(
+            "api_key",
+            SecurityScheme::ApiKey(ApiKey::Header(ApiKeyValue::new("X-API-Key"))),
+        );
+        components.add_security_scheme(
+            "participant_token",
+            SecurityScheme::ApiKey(ApiKey::Header(ApiKeyValue::new("X-Participant-Token"))),
+        );
+        components.add_security_scheme(
+            "seed_token",
+            SecurityScheme::ApiKey(ApiKey::Header(ApiKeyValue::new("X-Seed-Token"))),
+        );
+        components.add_security_scheme(
+            "admin_token",
+            SecurityScheme::Http(HttpBuilder::new().scheme(HttpAuthScheme::Bearer).build()),
+        );
+    }
+}
+
+/// Builds a standalone JSON Schema document for one of the API's component schemas.
+/// Schemas it references are inlined under `$defs`.
+pub fn json_schema(name: &str) -> Option<Value> {
+    let components = ApiDoc::openapi().components?;
+    if !components.schemas.contains_key(name) {
+        return None;
+    }
+    let mut defs = match serde_json::to_value(&components.schemas) {
+        Ok(defs) => defs,
+        Err(_) => return None,
+    };
+    rewrite_refs(&mut defs);
+
+    return Some(json!({
+        "$schema": "https://json-schema.org/draft/2020-12/schema",
+        "$ref": format!("#/$defs/{}", name),
+        "$defs": defs,
+    }));
+}
+
+fn rewrite_refs(value: &mut Value) {
+    match value {
+        Value::Object(object) => {
+            if let Some(Value::String(reference)) = object.get_mut("$ref")
+                && let Some(name) = reference.strip_prefix("#/components/schemas/")
+            {
+                *reference = format!("#/$defs/{}", name);
+            }
+            object.values_mut().for_each(rewrite_refs);
+        }
+        Value::Array(array) => array.iter_mut().for_each(rewrite_refs),
+        _ => {}
+    }
+}
+
+pub fn schema_names() -> Vec<String> {
+    return match ApiDoc::openapi().components {
+        Some(components) => components.schemas.keys().cloned().collect(),
+        None => Vec::new(),
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::http::{Method, StatusCode, header};
+
+    use super::*;
+    use crate::tests::{request, respond, state};
+
+    #[test]
+    fn schemas_inline_what_they_reference() {
+        let schema = json_schema("RollRequest").unwrap();
+        assert_eq!(schema["$ref"], "#/$defs/RollRequest");
+        let dice = &schema["$defs"]["RollRequest"]["properties"]["dice"]["items"];
+        assert_eq!(dice["$ref"], "#/$defs/Dice");
+        let dice = &schema["$defs"]["Dice"];
+        assert_eq!(dice["required"], json!(["sides"]));
+        assert_eq!(dice["properties"]["count"]["default"], 1);
+        assert_eq!(dice["additionalProperties"], false);
+
+        assert!(json_schema("Nothing").is_none());
+        assert!(schema_names().contains(&"ExpressionRequest".to_string()));
+    }
+
+    #[tokio::test]
+    async fn schemas_are_served() {
+        let state = state();
+        let (status, headers, body) = respond(
+            &state,
+            request(Method::GET, "/v1/schemas/ExpressionRequest", None),
+        )
+        .await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(headers[header::CONTENT_TYPE], "application/schema+json");
+        let schema: Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(schema["$ref"], "#/$defs/ExpressionRequest");
+
+        let (status, _, body) =
+            respond(&state, request(Method::GET, "/v1/schemas/Nothing", None)).await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+        let problem: Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(problem["code"], "UNKNOWN_SCHEMA");
+    }
+
+    #[tokio::test]
+    async fn the_openapi_document_and_its_docs_are_served() {
+        let state = state();
+        let (status, _, body) = respond(&state, request(Method::GET, "/openapi.json", None)).await;
+        assert_eq!(status, StatusCode::OK);
+        let document: utoipa::openapi::OpenApi = serde_json::from_slice(&body).unwrap();
+        assert!(document.openapi == utoipa::openapi::OpenApiVersion::Version31);
+        for path in ["/v1/rolls", "/v1/rolls/notation"] {
+            assert!(document.paths.paths.contains_key(path), "{}", path);
+        }
+
+        let (status, headers, body) = respond(&state, request(Method::GET, "/docs/", None)).await;
+        assert_eq!(status, StatusCode::OK);
+        assert!(
+            headers[header::CONTENT_TYPE]
+                .to_str()
+                .unwrap()
+                .starts_with("text/html")
+        );
+        assert!(String::from_utf8(body).unwrap().contains("swagger-ui"));
+    }
+}
@@ -0,0 +1,305 @@
+use std::net::{IpAddr, SocketAddr};
+
+use axum::{
+    extract::{ConnectInfo, Request, State},
+    middleware::Next,
+    response::Response,
+};
+
+use crate::AppState;
+
+/// Address rate limits and dice budgets are charged to when a request's origin isn't known,
+/// e.g. rolls relayed by chat integrations. Such requests share its bucket instead of going
+/// unlimited.
+pub const UNKNOWN_ADDRESS: &str = "unknown";
+pub const FORWARDED_FOR_HEADER: &str = "x-forwarded-for";
+pub const REAL_IP_HEADER: &str = "x-real-ip";
+/// Stands in for the peer of connections to the Unix domain socket, which have no address.
+const UNIX_PEER: &str = "unix";
+
+/// A reverse proxy whose `X-Forwarded-For` and `X-Real-IP` headers are believed.
+#[derive(Clone, Debug, PartialEq)]
+enum TrustedProxy {
+    /// Anything connecting over the Unix domain socket.
+    Unix,
+    /// Addresses within a network, a single address having a prefix covering all of its bits.
+    Network { address: IpAddr, prefix: u8 },
+}
+
+impl TrustedProxy {
+    fn parse(value: &str) -> Result<TrustedProxy, String> {
+        let value = value.trim();
+        if value == UNIX_PEER {
+            return Ok(TrustedProxy::Unix);
+        }
+        let (address, prefix) = match value.split_once('/') {
+            Some((address, prefix)) => (address, Some(prefix)),
+            None => (value, None),
+        };
+        let address = match address.parse::<IpAddr>() {
+            Ok(address) => address.to_canonical(),
+            Err(_) => return Err(format!("{} is not an IP address, network or unix", value)),
+        };
+        let bits = if address.is_ipv4() { 32 } else { 128 };
+        let prefix = match prefix.map(str::parse::<u8>) {
+            None => bits,
+            Some(Ok(prefix)) if prefix <= bits => prefix,
+            Some(_) => {
+                return Err(format!(
+                    "{} must have a prefix length between 0 and {}",
+                    value, bits
+                ));
+            }
+        };
+
+        return Ok(TrustedProxy::Network { address, prefix });
+    }
+
+    fn contains(&self, peer: &Peer) -> bool {
+        let (network, prefix, address) = match (self, peer) {
+            (TrustedProxy::Unix, Peer::Unix) => return true,
+            (TrustedProxy::Network { address, prefix }, Peer::Ip(peer)) => {
+                (address, *prefix, peer.to_canonical())
+            }
+            _ => return false,
+        };
+        let mask = |bits: u32| u128::MAX.checked_shl(bits - prefix as u32).unwrap_or(0);
+        return match (network, address) {
+            (IpAddr::V4(network), IpAddr::V4(address)) => {
+                (u32::from(*network) as u128 ^ u32::from(address) as u128) & mask(32) == 0
+            }
+            (IpAddr::V6(network), IpAddr::V6(address)) => {
+                (u128::from(*network) ^ u128::from(address)) & mask(128) == 0
+            }
+            _ => false,
+        };
+    }
+}
+
+/// Whoever is on the other end of a connection.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Peer {
+    Ip(IpAddr),
+    Unix,
+}
+
+/// The reverse proxies requests are forwarded by, see [`TrustedProxies::client_address`].
+#[derive(Clone, Debug, Default)]
+pub struct TrustedProxies {
+    proxies: Vec<TrustedProxy>,
+}
+
+/// The address of whoever made a request, after looking through any trusted proxies.
+#[derive(Clone, Debug)]
+pub struct ClientAddress(pub String);
+
+impl TrustedProxies {
+    /// Parses `--trusted-proxy` values: IP addresses, networks like `10.0.0.0/8`, or `unix` for
+    /// the Unix domain socket.
+    pub fn parse<'a>(values: impl IntoIterator<Item = &'a str>) -> Result<TrustedProxies, String> {
+        let proxies = values
+            .into_iter()
+            .map(TrustedProxy::parse)
+            .collect::<Result<Vec<_>, _>>()?;
+
+        return Ok(TrustedProxies { proxies });
+    }
+
+    fn trusts(&self, peer: &Peer) -> bool {
+        return self.proxies.iter().any(|proxy| proxy.contains(peer));
+    }
+
+    /// The address of the client a request came from. When the peer is a trusted proxy, this is
+    /// the address closest to it in `X-Forwarded-For` that isn't a trusted proxy itself, or else
+    /// `X-Real-IP`. Headers sent by any other peer are ignored, as anyone can send them.
+    pub fn client_address(
+        &self,
+        peer: Option<Peer>,
+        forwarded_for: Option<&str>,
+        real_ip: Option<&str>,
+    ) -> String {
+        let peer = match peer {
+            Some(peer) => peer,
+            None => return UNKNOWN_ADDRESS.to_string(),
+        };
+        let name = |peer: &Peer| match peer {
+            Peer::Ip(address) => address.to_canonical().to_string(),
+            Peer::Unix => UNIX_PEER.to_string(),
+        };
+        if !self.trusts(&peer) {
+            return name(&peer);
+        }
+
+        let parse = |address: &str| address.trim().parse::<IpAddr>().ok().map(Peer::Ip);
+        if let Some(forwarded_for) = forwarded_for {
+            let forwarded = forwarded_for
+                .split(',')
+                .map(parse)
+                .collect::<Option<Vec<_>>>();
+            // A malformed header can't be told apart from a forged one, so it isn't used.
+            if let Some(forwarded) = forwarded {
+                let client = forwarded
+                    .iter()
+                    .rev()
+                    .find(|address| !self.trusts(address))
+                    // Every hop being a trusted proxy, the first of them made the request.
+                    .unwrap_or(&forwarded[0]);
+                return name(client);
+            }
+        }
+        if let Some(real_ip) = real_ip.and_then(parse) {
+            return name(&real_ip);
+        }
+
+        return name(&peer);
+    }
+}
+
+/// Resolves the address of whoever made the request for the rate limits, idempotency keys and
+/// history to use, looking through trusted reverse proxies.
+pub async fn resolve(State(state): State<AppState>, mut request: Request, next: Next) -> Response {
+    let address = client_address(&state.proxies, &request);
+    request.extensions_mut().insert(ClientAddress(address));
+
+    return next.run(request).await;
+}
+
+fn client_address(proxies: &TrustedProxies, request: &Request) -> String {
+    let extensions = request.extensions();
+    let peer = match extensions.get::<ConnectInfo<SocketAddr>>() {
+        Some(ConnectInfo(address)) => Some(Peer::Ip(address.ip())),
+        None => extensions
+            .get::<ConnectInfo<tokio::net::unix::SocketAddr>>()
+            .map(|_| Peer::Unix),
+    };
+    let header = |name: &str| {
+        return request
+            .headers()
+            .get(name)
+            .and_then(|value| value.to_str().ok());
+    };
+
+    return proxies.client_address(peer, header(FORWARDED_FOR_HEADER), header(REAL_IP_HEADER));
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::{
+        body::Body,
+        http::{Method, StatusCode},
+    };
+    use serde_json::json;
+
+    use super::*;
+    use crate::{
+        limits::{Limits, TokenBuckets},
+        tests::{request, send, state, with_header},
+    };
+
+    fn ip(address: &str) -> Option<Peer> {
+        return Some(Peer::Ip(address.parse().unwrap()));
+    }
+
+    #[test]
+    fn parses_addresses_networks_and_unix() {
+        let proxies = TrustedProxies::parse(["10.0.0.0/8", "::1", "unix"]).unwrap();
+        assert!(proxies.trusts(&ip("10.1.2.3").unwrap()));
+        assert!(proxies.trusts(&ip("::ffff:10.1.2.3").unwrap()));
+        assert!(!proxies.trusts(&ip("11.0.0.1").unwrap()));
+        assert!(proxies.trusts(&ip("::1").unwrap()));
+        assert!(!proxies.trusts(&ip("::2").unwrap()));
+        assert!(proxies.trusts(&Peer::Unix));
+        assert!(
+            TrustedProxies::parse(["0.0.0.0/0"])
+                .unwrap()
+                .trusts(&ip("8.8.8.8").unwrap())
+        );
+
+        assert!(TrustedProxies::parse(["10.0.0.0/33"]).is_err());
+        assert!(TrustedProxies::parse(["proxy.example.com"]).is_err());
+        assert!(!TrustedProxies::default().trusts(&Peer::Unix));
+    }
+
+    #[test]
+    fn looks_through_trusted_proxies_only() {
+        let proxies = TrustedProxies::parse(["10.0.0.0/8", "unix"]).unwrap();
+        let forwarded = Some("203.0.113.7, 198.51.100.2, 10.0.0.2");
+        assert_eq!(
+            proxies.client_address(ip("10.0.0.1"), forwarded, None),
+            "198.51.100.2"
+        );
+        assert_eq!(
+            proxies.client_address(ip("192.0.2.1"), forwarded, None),
+            "192.0.2.1"
+        );
+        assert_eq!(
+            proxies.client_address(Some(Peer::Unix), Some("10.0.0.3, 10.0.0.2"), None),
+            "10.0.0.3"
+        );
+        assert_eq!(
+            proxies.client_address(ip("10.0.0.1"), None, Some("203.0.113.7")),
+            "203.0.113.7"
+        );
+        // Malformed headers are ignored.
+        assert_eq!(
+            proxies.client_address(ip("10.0.0.1"), Some("203.0.113.7, nonsense"), None),
+            "10.0.0.1"
+        );
+        assert_eq!(proxies.client_address(Some(Peer::Unix), None, None), "unix");
+        assert_eq!(
+            proxies.client_address(None, forwarded, None),
+            UNKNOWN_ADDRESS
+        );
+    }
+
+    #[tokio::test]
+    async fn forwarded_clients_get_rate_limits_of_their_own() {
+        let state = state();
+        let state = AppState {
+            limits: Limits {
+                per_ip: TokenBuckets::per_minute(1),
+                ..state.limits.clone()
+            },
+            proxies: TrustedProxies::parse(["127.0.0.1"]).unwrap(),
+            ..state
+        };
+        let roll = |forwarded_for: &str| {
+            let request = request(
+                Method::POST,
+                "/v1/rolls/notation",
+                Some(json!({"expression": "1d6"})),
+            );
+            return with_header(request, FORWARDED_FOR_HEADER, forwarded_for);
+        };
+
+        assert_eq!(send(&state, roll("203.0.113.7")).await.0, StatusCode::OK);
+        assert_eq!(send(&state, roll("198.51.100.2")).await.0, StatusCode::OK);
+        let (status, problem) = send(&state, roll("203.0.113.7")).await;
+        assert_eq!(status, StatusCode::TOO_MANY_REQUESTS);
+        assert_eq!(problem["code"], "RATE_LIMITED");
+    }
+
+    #[tokio::test]
+    async fn requests_from_unknown_addresses_are_still_limited() {
+        let state = state();
+        let state = AppState {
+            limits: Limits {
+                per_ip: TokenBuckets::per_minute(1),
+                ..state.limits.clone()
+            },
+            ..state
+        };
+        let roll = || {
+            return axum::http::Request::builder()
+                .method(Method::GET)
+                .uri("/roll?q=1d6")
+                .body(Body::empty())
+                .unwrap();
+        };
+
+        assert_eq!(send(&state, roll()).await.0, StatusCode::OK);
+        let (status, problem) = send(&state, roll()).await;
+        assert_eq!(status, StatusCode::TOO_MANY_REQUESTS);
+        assert_eq!(problem["code"], "RATE_LIMITED");
+    }
+}
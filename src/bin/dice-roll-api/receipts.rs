@@ -0,0 +1,135 @@
+use std::{fs, io, path::Path};
+
+use axum::{
+    Json,
+    extract::{State, rejection::JsonRejection},
+    http::StatusCode,
+    response::Response,
+};
+use dice_roll::receipt::{self, Receipt, Signer};
+use serde::Serialize;
+use utoipa::ToSchema;
+
+use crate::{
+    AppState, ExpressionRequest,
+    history::{Client, RollContext},
+    response::{Format, Negotiable, Problem},
+    roll_notation,
+};
+
+/// Loads the signing key stored in `path`, generating and saving a new one when it doesn't exist.
+pub fn load_signer(path: &str) -> Result<Signer, String> {
+    let signing_key = match fs::read_to_string(path) {
+        Ok(signing_key) => signing_key,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => {
+            let signing_key = receipt::generate_signing_key();
+            if let Some(parent) = Path::new(path).parent()
+                && !parent.as_os_str().is_empty()
+            {
+                fs::create_dir_all(parent).map_err(|e| e.to_string())?;
+            }
+            fs::write(path, format!("{}\n", signing_key)).map_err(|e| e.to_string())?;
+            signing_key
+        }
+        Err(e) => return Err(e.to_string()),
+    };
+
+    return Signer::new(&signing_key).map_err(|e| e.to_string());
+}
+
+impl Negotiable for Receipt {
+    /// Receipts are meant to be pasted around, so their text form is compact JSON.
+    fn into_text(self) -> String {
+        return serde_json::to_string(&self).unwrap_or_default();
+    }
+}
+
+/// Public key receipts issued by this server can be verified with.
+#[derive(Serialize, Debug, ToSchema)]
+pub struct ReceiptKey {
+    #[schema(example = "Ed25519")]
+    pub algorithm: &'static str,
+    /// Hex encoded Ed25519 public key.
+    pub public_key: String,
+}
+
+impl Negotiable for ReceiptKey {
+    fn into_text(self) -> String {
+        return self.public_key;
+    }
+}
+
+fn signer(state: &AppState) -> Result<&Signer, Problem> {
+    match &state.signer {
+        Some(signer) => return Ok(signer),
+        None => {
+            return Err(Problem::new(
+                StatusCode::NOT_FOUND,
+                "RECEIPTS_DISABLED",
+                "This server has not been configured with a signing key.".to_string(),
+            ));
+        }
+    }
+}
+
+/// Rolls dice notation and returns the result as a receipt signed by the server.
+#[utoipa::path(
+    post,
+    tag = "receipts",
+    path = "/v1/receipts",
+    request_body = ExpressionRequest,
+    responses(
+        (status = 200, description = "Dice were rolled and signed", body = Receipt),
+        (status = 400, description = "Invalid expression", content_type = "application/problem+json", body = Problem),
+        (status = 404, description = "Receipts are not enabled", content_type = "application/problem+json", body = Problem),
+    )
+)]
+pub async fn create_receipt(
+    State(state): State<AppState>,
+    format: Format,
+    client: Client,
+    payload: Result<Json<ExpressionRequest>, JsonRejection>,
+) -> Response {
+    if let Err(problem) = signer(&state) {
+        return format.problem(problem);
+    }
+    let expression = match payload {
+        Ok(request) => request.0.expression,
+        Err(e) => return format.problem(Problem::from_json_rejection(e)),
+    };
+    let context = RollContext { ..client.context() };
+    let result = match roll_notation(&state, "expression", expression.clone(), context).await {
+        Ok(result) => result,
+        Err(problem) => return format.problem(problem),
+    };
+
+    match signer(&state) {
+        Ok(signer) => return format.respond(StatusCode::OK, signer.sign(expression, result)),
+        Err(problem) => return format.problem(problem),
+    }
+}
+
+/// Returns the public key receipts can be verified with.
+#[utoipa::path(
+    get,
+    tag = "receipts",
+    path = "/v1/receipts/key",
+    responses(
+        (status = 200, description = "The server's public key", body = ReceiptKey),
+        (status = 404, description = "Receipts are not enabled", content_type = "application/problem+json", body = Problem),
+    )
+)]
+pub async fn receipt_key(State(state): State<AppState>, format: Format) -> Response {
+    match signer(&state) {
+        Ok(signer) => {
+            return format.respond(
+                StatusCode::OK,
+                ReceiptKey {
+                    algorithm: "Ed25519",
+                    public_key: signer.public_key(),
+                },
+            );
+        }
+        Err(problem) => return format.problem(problem),
+    }
+}
@@ -0,0 +1,622 @@
+use std::error::Error;
+
+use axum::{
+    extract::{
+        FromRequestParts,
+        rejection::{JsonRejection, QueryRejection},
+    },
+    http::{HeaderValue, StatusCode, header, request::Parts},
+    response::{IntoResponse, Response},
+};
+use dice_roll::{RollRequestErrors, RollResponse, fair::FairErrors, parser::ParserErrors};
+use serde::Serialize;
+use utoipa::ToSchema;
+
+/// An RFC 7807 problem details document.
+#[derive(Serialize, Clone, Debug, ToSchema)]
+pub struct Problem {
+    #[serde(rename = "type")]
+    #[schema(example = "urn:dice-roll:problem:invalid-dice-sides")]
+    pub problem_type: String,
+    #[schema(example = "Invalid dice sides")]
+    pub title: String,
+    #[schema(example = 400)]
+    pub status: u16,
+    #[schema(example = "Dice sides must be between 1 and 1000, 0 provided")]
+    pub detail: String,
+    #[schema(example = "INVALID_DICE_SIDES")]
+    pub code: &'static str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[schema(example = "dice[2].sides")]
+    pub field: Option<String>,
+    /// Seconds until the request can be retried, sent as a `Retry-After` header.
+    #[serde(skip)]
+    pub retry_after: Option<u16>,
+}
+
+impl Problem {
+    /// Builds a problem whose `type` and `title` are derived from its machine readable `code`.
+    pub fn new(status: StatusCode, code: &'static str, detail: String) -> Problem {
+        let words = code
+            .to_lowercase()
+            .split('_')
+            .map(|word| match word {
+                "api" => "API",
+                "gm" => "GM",
+                "json" => "JSON",
+                "url" => "URL",
+                "websocket" => "WebSocket",
+                _ => word,
+            })
+            .collect::<Vec<_>>()
+            .join(" ");
+        let mut title = String::new();
+        let mut chars = words.chars();
+        if let Some(first) = chars.next() {
+            title.push(first.to_ascii_uppercase());
+            title.push_str(chars.as_str());
+        }
+
+        return Problem {
+            problem_type: format!(
+                "urn:dice-roll:problem:{}",
+                code.to_lowercase().replace('_', "-")
+            ),
+            title,
+            status: status.as_u16(),
+            detail,
+            code,
+            field: None,
+            retry_after: None,
+        };
+    }
+
+    pub fn with_field(mut self, field: String) -> Problem {
+        self.field = Some(field);
+        return self;
+    }
+
+    pub fn status_code(&self) -> StatusCode {
+        return StatusCode::from_u16(self.status).unwrap_or(StatusCode::INTERNAL_SERVER_ERROR);
+    }
+
+    pub fn from_json_rejection(rejection: JsonRejection) -> Problem {
+        match rejection {
+            JsonRejection::MissingJsonContentType(_) => {
+                return Problem::new(
+                    StatusCode::UNSUPPORTED_MEDIA_TYPE,
+                    "UNSUPPORTED_MEDIA_TYPE",
+                    "Request body must be JSON sent with a `Content-Type: application/json` header."
+                        .to_string(),
+                );
+            }
+            JsonRejection::JsonSyntaxError(e) => {
+                let detail = match find_json_error(&e) {
+                    Some((_, inner)) => format!(
+                        "Request body is not valid JSON, error found at line {} column {}.",
+                        inner.line(),
+                        inner.column()
+                    ),
+                    None => "Request body is not valid JSON.".to_string(),
+                };
+                return Problem::new(StatusCode::BAD_REQUEST, "MALFORMED_JSON", detail);
+            }
+            JsonRejection::JsonDataError(e) => {
+                let (path, inner) = match find_json_error(&e) {
+                    Some(found) => found,
+                    None => {
+                        return Problem::new(
+                            StatusCode::BAD_REQUEST,
+                            "INVALID_FIELD",
+                            "Request body does not match the expected structure.".to_string(),
+                        );
+                    }
+                };
+                return Problem::from_data_error(path, inner);
+            }
+            JsonRejection::BytesRejection(e) => {
+                if e.status() == StatusCode::PAYLOAD_TOO_LARGE {
+                    return Problem::new(
+                        StatusCode::PAYLOAD_TOO_LARGE,
+                        "PAYLOAD_TOO_LARGE",
+                        "Request body is too large.".to_string(),
+                    );
+                }
+                return Problem::new(
+                    e.status(),
+                    "BODY_READ_FAILED",
+                    "Failed to read the request body.".to_string(),
+                );
+            }
+            e => {
+                return Problem::new(e.status(), "INVALID_BODY", e.body_text());
+            }
+        }
+    }
+
+    pub fn from_query_rejection(rejection: QueryRejection) -> Problem {
+        let text = rejection.body_text();
+        let message = text
+            .strip_prefix("Failed to deserialize query string: ")
+            .unwrap_or(&text);
+        if let Some(name) = backticked(message, "missing field `") {
+            return Problem::new(
+                StatusCode::BAD_REQUEST,
+                "MISSING_QUERY_PARAMETER",
+                format!("Missing required query parameter {}.", name),
+            )
+            .with_field(name);
+        }
+        return Problem::new(
+            StatusCode::BAD_REQUEST,
+            "INVALID_QUERY",
+            format!("Invalid query string, {}.", message),
+        );
+    }
+
+    /// Maps a serde data error onto a problem pointing at the offending field.
+    fn from_data_error(path: Option<String>, inner: &serde_json::Error) -> Problem {
+        let message = inner.to_string();
+        let position = format!(" at line {} column {}", inner.line(), inner.column());
+        let message = message
+            .strip_suffix(&position)
+            .unwrap_or(&message)
+            .to_string();
+
+        if let Some(name) = backticked(&message, "missing field `") {
+            let field = match &path {
+                Some(path) => format!("{}.{}", path, name),
+                None => name,
+            };
+            return Problem::new(
+                StatusCode::BAD_REQUEST,
+                "MISSING_FIELD",
+                format!("Missing required field {}.", field),
+            )
+            .with_field(field);
+        }
+        if let Some(name) = backticked(&message, "unknown field `") {
+            // Unlike missing fields, the path to an unknown field already ends with its name.
+            let field = path.unwrap_or(name);
+            return Problem::new(
+                StatusCode::BAD_REQUEST,
+                "UNKNOWN_FIELD",
+                format!("Unknown field {}.", field),
+            )
+            .with_field(field);
+        }
+        match path {
+            Some(path) => {
+                return Problem::new(
+                    StatusCode::BAD_REQUEST,
+                    "INVALID_FIELD",
+                    format!("Invalid value for {}, {}.", path, message),
+                )
+                .with_field(path);
+            }
+            None => {
+                return Problem::new(
+                    StatusCode::BAD_REQUEST,
+                    "INVALID_FIELD",
+                    format!("Invalid request body, {}.", message),
+                );
+            }
+        }
+    }
+}
+
+/// Walks an error's sources looking for the serde error behind a JSON rejection.
+fn find_json_error<'a>(
+    error: &'a (dyn Error + 'static),
+) -> Option<(Option<String>, &'a serde_json::Error)> {
+    let mut current = Some(error);
+    while let Some(error) = current {
+        if let Some(e) = error.downcast_ref::<serde_path_to_error::Error<serde_json::Error>>() {
+            let path = e.path().to_string();
+            let path = if path == "." { None } else { Some(path) };
+            return Some((path, e.inner()));
+        }
+        if let Some(e) = error.downcast_ref::<serde_json::Error>() {
+            return Some((None, e));
+        }
+        current = error.source();
+    }
+
+    return None;
+}
+
+fn backticked(message: &str, prefix: &str) -> Option<String> {
+    let rest = message.strip_prefix(prefix)?;
+    let end = rest.find('`')?;
+    return Some(rest[..end].to_string());
+}
+
+impl From<RollRequestErrors> for Problem {
+    fn from(e: RollRequestErrors) -> Problem {
+        let field = e.field();
+        return Problem::new(StatusCode::BAD_REQUEST, e.code(), e.to_string()).with_field(field);
+    }
+}
+
+impl From<ParserErrors> for Problem {
+    fn from(e: ParserErrors) -> Problem {
+        return Problem::new(StatusCode::BAD_REQUEST, e.code(), e.to_string());
+    }
+}
+
+impl From<FairErrors> for Problem {
+    fn from(e: FairErrors) -> Problem {
+        return Problem::new(StatusCode::BAD_REQUEST, e.code(), e.to_string());
+    }
+}
+
+impl IntoResponse for Problem {
+    fn into_response(self) -> Response {
+        return Format::Json.problem(self);
+    }
+}
+
+/// Bodies that can be rendered in any of the supported response formats.
+pub trait Negotiable: Serialize {
+    fn into_text(self) -> String;
+
+    fn json_content_type(&self) -> &'static str {
+        return "application/json";
+    }
+}
+
+impl Negotiable for RollResponse {
+    fn into_text(self) -> String {
+        return self.to_string();
+    }
+}
+
+impl Negotiable for Problem {
+    fn into_text(self) -> String {
+        return self.detail;
+    }
+
+    fn json_content_type(&self) -> &'static str {
+        return "application/problem+json";
+    }
+}
+
+/// Machine readable code of the problem a response carries, kept in its extensions.
+#[derive(Clone, Copy, Debug)]
+pub struct ProblemCode(pub &'static str);
+
+/// Response format selected from the request's `Accept` header.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Format {
+    Json,
+    PlainText,
+    Cbor,
+    MessagePack,
+}
+
+impl Format {
+    fn from_media_type(media_type: &str) -> Option<Format> {
+        match media_type {
+            "*/*" | "application/*" | "application/json" | "application/problem+json" => {
+                Some(Format::Json)
+            }
+            "text/*" | "text/plain" => Some(Format::PlainText),
+            "application/cbor" => Some(Format::Cbor),
+            "application/msgpack" | "application/x-msgpack" | "application/vnd.msgpack" => {
+                Some(Format::MessagePack)
+            }
+            _ => None,
+        }
+    }
+
+    /// Picks the supported format with the highest quality value from an `Accept` header.
+    /// Ties are broken by the order the media types were listed in.
+    pub fn from_accept(accept: &str) -> Option<Format> {
+        let mut best: Option<(f32, Format)> = None;
+        for media_range in accept.split(',') {
+            let mut parts = media_range.split(';');
+            let media_type = parts.next().unwrap_or("").trim().to_ascii_lowercase();
+            let mut quality = 1.0;
+            for param in parts {
+                if let Some((key, value)) = param.split_once('=')
+                    && key.trim() == "q"
+                {
+                    quality = value.trim().parse().unwrap_or(0.0);
+                }
+            }
+            if quality <= 0.0 {
+                continue;
+            }
+            let format = match Format::from_media_type(&media_type) {
+                Some(format) => format,
+                None => continue,
+            };
+            match best {
+                Some((best_quality, _)) if best_quality >= quality => {}
+                _ => best = Some((quality, format)),
+            }
+        }
+
+        return best.map(|(_, format)| format);
+    }
+
+    pub fn content_type<T: Negotiable>(&self, body: &T) -> &'static str {
+        match self {
+            Format::Json => body.json_content_type(),
+            Format::PlainText => "text/plain; charset=utf-8",
+            Format::Cbor => "application/cbor",
+            Format::MessagePack => "application/msgpack",
+        }
+    }
+
+    pub fn respond<T: Negotiable>(self, status: StatusCode, body: T) -> Response {
+        let content_type = self.content_type(&body);
+        let encoded = match self {
+            Format::Json => serde_json::to_vec(&body).map_err(|e| e.to_string()),
+            Format::PlainText => Ok(body.into_text().into_bytes()),
+            Format::Cbor => {
+                let mut buffer = Vec::new();
+                ciborium::into_writer(&body, &mut buffer)
+                    .map(|_| buffer)
+                    .map_err(|e| e.to_string())
+            }
+            Format::MessagePack => rmp_serde::to_vec_named(&body).map_err(|e| e.to_string()),
+        };
+        let encoded = match encoded {
+            Ok(encoded) => encoded,
+            Err(e) => {
+                return Problem::new(
+                    StatusCode::INTERNAL_SERVER_ERROR,
+                    "SERIALIZATION_FAILED",
+                    format!("Failed to serialize the response, {}.", e),
+                )
+                .into_response();
+            }
+        };
+
+        return (
+            status,
+            [(header::CONTENT_TYPE, HeaderValue::from_static(content_type))],
+            encoded,
+        )
+            .into_response();
+    }
+
+    /// Responds with the body, or with the problem that got in the way of it.
+    pub fn result<T: Negotiable>(self, status: StatusCode, result: Result<T, Problem>) -> Response {
+        match result {
+            Ok(body) => return self.respond(status, body),
+            Err(problem) => return self.problem(problem),
+        }
+    }
+
+    pub fn problem(self, problem: Problem) -> Response {
+        let code = ProblemCode(problem.code);
+        let retry_after = problem.retry_after;
+        let mut response = self.respond(problem.status_code(), problem);
+        response.extensions_mut().insert(code);
+        if let Some(retry_after) = retry_after {
+            response
+                .headers_mut()
+                .insert(header::RETRY_AFTER, HeaderValue::from(retry_after));
+        }
+        return response;
+    }
+}
+
+impl<S: Send + Sync> FromRequestParts<S> for Format {
+    type Rejection = Problem;
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+        let accept = match parts.headers.get(header::ACCEPT) {
+            Some(accept) => accept.to_str().unwrap_or(""),
+            None => return Ok(Format::Json),
+        };
+        if accept.trim().is_empty() {
+            return Ok(Format::Json);
+        }
+
+        match Format::from_accept(accept) {
+            Some(format) => Ok(format),
+            None => Err(Problem::new(
+                StatusCode::NOT_ACCEPTABLE,
+                "NOT_ACCEPTABLE",
+                format!(
+                    "None of the requested media types are supported, {} provided. \
+                     Supported media types are application/json, text/plain, \
+                     application/cbor and application/msgpack.",
+                    accept
+                ),
+            )),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::http::Method;
+    use serde_json::json;
+
+    use super::*;
+    use crate::tests::{request, respond, state, with_header};
+
+    #[test]
+    fn picks_the_preferred_supported_format() {
+        assert_eq!(Format::from_accept("text/plain"), Some(Format::PlainText));
+        assert_eq!(
+            Format::from_accept("text/html, application/cbor;q=0.5, text/*;q=0.8"),
+            Some(Format::PlainText)
+        );
+        assert_eq!(
+            Format::from_accept("application/msgpack, application/json"),
+            Some(Format::MessagePack)
+        );
+        assert_eq!(
+            Format::from_accept("application/json;q=0, */*;q=0.1"),
+            Some(Format::Json)
+        );
+        assert_eq!(Format::from_accept("image/png, text/plain;q=0"), None);
+    }
+
+    fn roll(accept: &str) -> axum::http::Request<axum::body::Body> {
+        let request = request(
+            Method::POST,
+            "/v1/rolls/notation",
+            Some(json!({"expression": "1d1 + 1"})),
+        );
+        return with_header(request, "accept", accept);
+    }
+
+    #[tokio::test]
+    async fn responds_in_the_negotiated_format() {
+        let state = state();
+        let (status, headers, body) = respond(&state, roll("text/plain")).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(headers[header::CONTENT_TYPE], "text/plain; charset=utf-8");
+        assert_eq!(String::from_utf8(body).unwrap(), "(1 of 1) + 1 = 2");
+
+        let (_, headers, body) = respond(&state, roll("application/cbor")).await;
+        assert_eq!(headers[header::CONTENT_TYPE], "application/cbor");
+        let rolled: serde_json::Value = ciborium::from_reader(body.as_slice()).unwrap();
+        assert_eq!(rolled["total"], 2);
+
+        let (_, headers, body) = respond(&state, roll("application/msgpack")).await;
+        assert_eq!(headers[header::CONTENT_TYPE], "application/msgpack");
+        let rolled: serde_json::Value = rmp_serde::from_slice(&body).unwrap();
+        assert_eq!(rolled["total"], 2);
+
+        let (status, headers, _) = respond(&state, roll("image/png")).await;
+        assert_eq!(status, StatusCode::NOT_ACCEPTABLE);
+        assert_eq!(headers[header::CONTENT_TYPE], "application/problem+json");
+    }
+
+    #[tokio::test]
+    async fn routes_are_versioned_with_unversioned_aliases() {
+        let state = state();
+        let body = json!({"dice": [{"count": 1, "sides": 1}]});
+        let (status, _, _) = respond(
+            &state,
+            request(Method::POST, "/v1/rolls", Some(body.clone())),
+        )
+        .await;
+        assert_eq!(status, StatusCode::OK);
+        let (status, _, _) = respond(&state, request(Method::POST, "/", Some(body.clone()))).await;
+        assert_eq!(status, StatusCode::OK);
+        let (status, _, _) = respond(&state, request(Method::GET, "/roll?q=1d6", None)).await;
+        assert_eq!(status, StatusCode::OK);
+        let (status, _, _) = respond(&state, request(Method::POST, "/v2/rolls", Some(body))).await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+    }
+
+    #[test]
+    fn problems_get_their_type_and_title_from_their_code() {
+        let problem = Problem::new(
+            StatusCode::NOT_ACCEPTABLE,
+            "NOT_ACCEPTABLE",
+            "None of the requested media types are supported.".to_string(),
+        );
+        assert_eq!(problem.problem_type, "urn:dice-roll:problem:not-acceptable");
+        assert_eq!(problem.title, "Not acceptable");
+        assert_eq!(problem.status, 406);
+        assert_eq!(
+            Problem::new(StatusCode::BAD_REQUEST, "MALFORMED_JSON", String::new()).title,
+            "Malformed JSON"
+        );
+        assert_eq!(
+            Problem::new(StatusCode::FORBIDDEN, "GM_REQUIRED", String::new()).title,
+            "GM required"
+        );
+    }
+
+    /// Sends a request through the API, returning the problem it was answered with.
+    async fn problem(
+        request: axum::http::Request<axum::body::Body>,
+    ) -> (StatusCode, serde_json::Value) {
+        let (status, headers, body) = respond(&state(), request).await;
+        assert_eq!(headers[header::CONTENT_TYPE], "application/problem+json");
+        let problem: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(problem["status"], status.as_u16());
+
+        return (status, problem);
+    }
+
+    #[tokio::test]
+    async fn errors_are_problem_documents() {
+        let (status, body) = problem(request(
+            Method::POST,
+            "/v1/rolls",
+            Some(json!({"dice": [{"count": 1, "sides": 0}]})),
+        ))
+        .await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert_eq!(body["type"], "urn:dice-roll:problem:invalid-dice-sides");
+        assert_eq!(body["code"], "INVALID_DICE_SIDES");
+        assert_eq!(body["field"], "dice[0].sides");
+
+        let (status, body) = problem(request(
+            Method::POST,
+            "/v1/rolls",
+            Some(json!({"dice": [{"count": 1}]})),
+        ))
+        .await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert_eq!(body["code"], "MISSING_FIELD");
+        assert_eq!(body["field"], "dice[0].sides");
+
+        let (_, body) = problem(request(
+            Method::POST,
+            "/v1/rolls",
+            Some(json!({"dice": [{"count": "two", "sides": 6}]})),
+        ))
+        .await;
+        assert_eq!(body["code"], "INVALID_FIELD");
+        assert_eq!(body["field"], "dice[0].count");
+
+        let (status, body) = problem(request(Method::GET, "/v1/rolls/notation", None)).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert_eq!(body["code"], "MISSING_QUERY_PARAMETER");
+        assert_eq!(body["field"], "q");
+
+        let (status, body) = problem(request(Method::GET, "/v1/nowhere", None)).await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+        assert_eq!(body["code"], "NOT_FOUND");
+    }
+
+    #[tokio::test]
+    async fn unreadable_bodies_are_problem_documents() {
+        let mut malformed = request(Method::POST, "/v1/rolls", None);
+        *malformed.body_mut() = axum::body::Body::from("{\n\"dice\": [");
+        let malformed = with_header(malformed, "content-type", "application/json");
+        let (status, body) = problem(malformed).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert_eq!(body["code"], "MALFORMED_JSON");
+        assert!(
+            body["detail"].as_str().unwrap().contains("line 2"),
+            "{}",
+            body
+        );
+
+        let mut untyped = request(Method::POST, "/v1/rolls", None);
+        *untyped.body_mut() = axum::body::Body::from("{}");
+        let (status, body) = problem(untyped).await;
+        assert_eq!(status, StatusCode::UNSUPPORTED_MEDIA_TYPE);
+        assert_eq!(body["code"], "UNSUPPORTED_MEDIA_TYPE");
+    }
+
+    #[tokio::test]
+    async fn problems_are_negotiated_like_other_responses() {
+        let request = with_header(
+            request(
+                Method::POST,
+                "/v1/rolls/notation",
+                Some(json!({"expression": ""})),
+            ),
+            "accept",
+            "text/plain",
+        );
+        let (status, headers, body) = respond(&state(), request).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert_eq!(headers[header::CONTENT_TYPE], "text/plain; charset=utf-8");
+        assert!(!body.is_empty());
+    }
+}
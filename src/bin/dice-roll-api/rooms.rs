@@ -0,0 +1,461 @@
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+};
+
+use axum::{
+    extract::{
+        Path, Query, State, WebSocketUpgrade,
+        rejection::QueryRejection,
+        ws::{Message, WebSocket, rejection::WebSocketUpgradeRejection},
+    },
+    http::StatusCode,
+    response::{IntoResponse, Response},
+};
+use chrono::{DateTime, Utc};
+use dice_roll::RollResponse;
+use serde::{Deserialize, Serialize};
+use tokio::sync::broadcast;
+use utoipa::{IntoParams, ToSchema};
+
+use crate::{
+    AppState, ExpressionRequest,
+    history::{Client, RollContext},
+    proxies::UNKNOWN_ADDRESS,
+    response::Problem,
+    roll_notation,
+};
+
+const ROOM_CAPACITY: usize = 64;
+const MAX_ROOM_ID_LENGTH: usize = 64;
+const MAX_PLAYER_NAME_LENGTH: usize = 32;
+
+/// Events sent to every participant of a room.
+#[derive(Serialize, Clone, Debug, ToSchema)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum RoomEvent {
+    Joined {
+        player: String,
+        timestamp: DateTime<Utc>,
+    },
+    Left {
+        player: String,
+        timestamp: DateTime<Utc>,
+    },
+    Roll {
+        player: String,
+        expression: String,
+        result: RollResponse,
+        timestamp: DateTime<Utc>,
+    },
+    /// Only sent to the participant whose message could not be handled.
+    Error { error: Problem },
+}
+
+/// Rooms by their tenant, `None` for rooms joined without an API key, and id. Tenants using the
+/// same room id each get a room of their own.
+type RoomKey = (Option<String>, String);
+
+/// Broadcast channels for the rooms that currently have participants.
+#[derive(Clone, Default)]
+pub struct Rooms {
+    channels: Arc<Mutex<HashMap<RoomKey, broadcast::Sender<RoomEvent>>>>,
+}
+
+impl Rooms {
+    fn join(
+        &self,
+        room: &RoomKey,
+    ) -> (broadcast::Sender<RoomEvent>, broadcast::Receiver<RoomEvent>) {
+        let mut channels = self.channels.lock().unwrap();
+        let sender = channels
+            .entry(room.clone())
+            .or_insert_with(|| broadcast::channel(ROOM_CAPACITY).0);
+
+        return (sender.clone(), sender.subscribe());
+    }
+
+    /// Drops the room's channel once its last participant has left.
+    fn leave(&self, room: &RoomKey) {
+        let mut channels = self.channels.lock().unwrap();
+        if let Some(sender) = channels.get(room)
+            && sender.receiver_count() == 0
+        {
+            channels.remove(room);
+        }
+    }
+}
+
+#[derive(Deserialize, Debug, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct JoinQuery {
+    /// Name shown to the other participants of the room.
+    pub name: String,
+}
+
+/// Joins a shared roll room over a WebSocket.
+///
+/// Participants send `{"expression": "1d20 + 5"}` text messages. The roll is executed on
+/// the server and broadcast to everyone in the room as a `RoomEvent`. Participants only share
+/// a room with those using an API key of the same tenant, or no API key when they don't.
+#[utoipa::path(
+    get,
+    tag = "rooms",
+    path = "/v1/rooms/{id}/ws",
+    params(
+        ("id" = String, Path, description = "Identifier of the room to join."),
+        JoinQuery,
+    ),
+    responses(
+        (status = 101, description = "Switching to the WebSocket protocol, messages sent to the client are `RoomEvent`s"),
+        (status = 400, description = "Invalid room or player name", content_type = "application/problem+json", body = Problem),
+        (status = 426, description = "Request was not a WebSocket upgrade", content_type = "application/problem+json", body = Problem),
+    )
+)]
+pub async fn room_socket(
+    State(state): State<AppState>,
+    client: Client,
+    Path(room): Path<String>,
+    query: Result<Query<JoinQuery>, QueryRejection>,
+    upgrade: Result<WebSocketUpgrade, WebSocketUpgradeRejection>,
+) -> Response {
+    if room.is_empty() || room.len() > MAX_ROOM_ID_LENGTH {
+        return Problem::new(
+            StatusCode::BAD_REQUEST,
+            "INVALID_ROOM_ID",
+            format!(
+                "Room identifiers must be between 1 and {} characters.",
+                MAX_ROOM_ID_LENGTH
+            ),
+        )
+        .with_field("id".to_string())
+        .into_response();
+    }
+    let player = match query {
+        Ok(query) => query.0.name.trim().to_string(),
+        Err(_) => String::new(),
+    };
+    if player.is_empty() || player.chars().count() > MAX_PLAYER_NAME_LENGTH {
+        return Problem::new(
+            StatusCode::BAD_REQUEST,
+            "INVALID_PLAYER_NAME",
+            format!(
+                "A `name` query parameter between 1 and {} characters is required.",
+                MAX_PLAYER_NAME_LENGTH
+            ),
+        )
+        .with_field("name".to_string())
+        .into_response();
+    }
+    let upgrade = match upgrade {
+        Ok(upgrade) => upgrade,
+        Err(e) => {
+            return Problem::new(
+                StatusCode::UPGRADE_REQUIRED,
+                "WEBSOCKET_UPGRADE_REQUIRED",
+                format!(
+                    "Rooms can only be joined over a WebSocket, {}.",
+                    e.body_text()
+                ),
+            )
+            .into_response();
+        }
+    };
+
+    return upgrade
+        .max_message_size(state.limits.max_body_size)
+        .on_upgrade(move |socket| handle_socket(socket, state, client, room, player));
+}
+
+async fn handle_socket(
+    mut socket: WebSocket,
+    state: AppState,
+    client: Client,
+    room: String,
+    player: String,
+) {
+    let tenant = client.tenant_id();
+    let key = (tenant.clone(), room.clone());
+    let (sender, mut receiver) = state.rooms.join(&key);
+    let _ = sender.send(RoomEvent::Joined {
+        player: player.clone(),
+        timestamp: Utc::now(),
+    });
+
+    loop {
+        tokio::select! {
+            message = socket.recv() => {
+                let text = match message {
+                    Some(Ok(Message::Text(text))) => text,
+                    Some(Ok(Message::Binary(_))) => {
+                        let error = invalid_message("Binary messages are not supported.");
+                        if send_event(&mut socket, &error).await.is_err() {
+                            break;
+                        }
+                        continue;
+                    }
+                    Some(Ok(Message::Close(_))) | Some(Err(_)) | None => break,
+                    Some(Ok(_)) => continue,
+                };
+                // Every message is a request of its own against the client's request rate.
+                let address = client.address.as_deref().unwrap_or(UNKNOWN_ADDRESS);
+                if let Err(problem) = state.limits.charge_request(client.tenant.as_ref(), address) {
+                    if send_event(&mut socket, &RoomEvent::Error { error: problem })
+                        .await
+                        .is_err()
+                    {
+                        break;
+                    }
+                    continue;
+                }
+                let expression = match serde_json::from_str::<ExpressionRequest>(text.as_str()) {
+                    Ok(request) => request.expression,
+                    Err(_) => {
+                        let error = invalid_message(
+                            "Messages must be JSON of the form {\"expression\": \"1d20 + 5\"}.",
+                        );
+                        if send_event(&mut socket, &error).await.is_err() {
+                            break;
+                        }
+                        continue;
+                    }
+                };
+                // Rolls made in a room are also sent to the session sharing its id.
+                let context = RollContext {
+                    session: Some(room.clone()),
+                    player: Some(player.clone()),
+                    ..client.context()
+                };
+                match roll_notation(&state, "expression", expression.clone(), context).await {
+                    Ok(result) => {
+                        let _ = sender.send(RoomEvent::Roll {
+                            player: player.clone(),
+                            expression,
+                            result,
+                            timestamp: Utc::now(),
+                        });
+                    }
+                    Err(problem) => {
+                        if send_event(&mut socket, &RoomEvent::Error { error: problem })
+                            .await
+                            .is_err()
+                        {
+                            break;
+                        }
+                    }
+                }
+            }
+            event = receiver.recv() => {
+                match event {
+                    Ok(event) => {
+                        if send_event(&mut socket, &event).await.is_err() {
+                            break;
+                        }
+                    }
+                    Err(broadcast::error::RecvError::Lagged(_)) => continue,
+                    Err(broadcast::error::RecvError::Closed) => break,
+                }
+            }
+        }
+    }
+
+    drop(receiver);
+    let _ = sender.send(RoomEvent::Left {
+        player,
+        timestamp: Utc::now(),
+    });
+    state.rooms.leave(&key);
+}
+
+fn invalid_message(detail: &str) -> RoomEvent {
+    return RoomEvent::Error {
+        error: Problem::new(
+            StatusCode::BAD_REQUEST,
+            "INVALID_MESSAGE",
+            detail.to_string(),
+        ),
+    };
+}
+
+async fn send_event(socket: &mut WebSocket, event: &RoomEvent) -> Result<(), axum::Error> {
+    let text = match serde_json::to_string(event) {
+        Ok(text) => text,
+        Err(e) => return Err(axum::Error::new(e)),
+    };
+    return socket.send(Message::Text(text.into())).await;
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use axum::http::Method;
+    use futures_util::{SinkExt, StreamExt};
+    use tokio_tungstenite::{MaybeTlsStream, WebSocketStream, connect_async, tungstenite};
+
+    use super::*;
+    use crate::tests::{request, send, state};
+
+    fn key(tenant: Option<&str>, room: &str) -> RoomKey {
+        return (tenant.map(|tenant| tenant.to_string()), room.to_string());
+    }
+
+    #[tokio::test]
+    async fn participants_of_a_room_share_its_events() {
+        let rooms = Rooms::default();
+        let table = key(None, "table");
+        let (sender, mut alice) = rooms.join(&table);
+        let (_, mut bob) = rooms.join(&table);
+        let (_, mut elsewhere) = rooms.join(&key(Some("acme"), "table"));
+
+        sender
+            .send(RoomEvent::Joined {
+                player: "Carol".to_string(),
+                timestamp: Utc::now(),
+            })
+            .unwrap();
+        for receiver in [&mut alice, &mut bob] {
+            assert!(matches!(
+                receiver.recv().await.unwrap(),
+                RoomEvent::Joined { player, .. } if player == "Carol"
+            ));
+        }
+        assert!(elsewhere.try_recv().is_err());
+    }
+
+    #[test]
+    fn rooms_are_dropped_once_everyone_left() {
+        let rooms = Rooms::default();
+        let table = key(None, "table");
+        let (_, alice) = rooms.join(&table);
+        let (_, bob) = rooms.join(&table);
+
+        drop(alice);
+        rooms.leave(&table);
+        assert!(rooms.channels.lock().unwrap().contains_key(&table));
+        drop(bob);
+        rooms.leave(&table);
+        assert!(rooms.channels.lock().unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn joining_takes_a_name_and_a_websocket() {
+        let state = state();
+        let (status, problem) =
+            send(&state, request(Method::GET, "/v1/rooms/table/ws", None)).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert_eq!(problem["code"], "INVALID_PLAYER_NAME");
+
+        let long_room = format!(
+            "/v1/rooms/{}/ws?name=Alice",
+            "r".repeat(MAX_ROOM_ID_LENGTH + 1)
+        );
+        let (status, problem) = send(&state, request(Method::GET, &long_room, None)).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert_eq!(problem["code"], "INVALID_ROOM_ID");
+
+        let (status, problem) = send(
+            &state,
+            request(Method::GET, "/v1/rooms/table/ws?name=Alice", None),
+        )
+        .await;
+        assert_eq!(status, StatusCode::UPGRADE_REQUIRED);
+        assert_eq!(problem["code"], "WEBSOCKET_UPGRADE_REQUIRED");
+    }
+
+    async fn next_event(
+        socket: &mut WebSocketStream<MaybeTlsStream<tokio::net::TcpStream>>,
+    ) -> serde_json::Value {
+        let message = tokio::time::timeout(Duration::from_secs(1), socket.next())
+            .await
+            .unwrap()
+            .unwrap()
+            .unwrap();
+        return serde_json::from_str(message.to_text().unwrap()).unwrap();
+    }
+
+    /// Serves the API on a local port, returning its address.
+    async fn serve(state: &AppState) -> std::net::SocketAddr {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+        let app = crate::app(state.clone(), None);
+        tokio::spawn(async move {
+            axum::serve(
+                listener,
+                app.into_make_service_with_connect_info::<std::net::SocketAddr>(),
+            )
+            .await
+            .unwrap()
+        });
+
+        return address;
+    }
+
+    #[tokio::test]
+    async fn rolls_are_broadcast_over_the_websocket() {
+        let state = state();
+        let address = serve(&state).await;
+
+        let url = format!("ws://{}/v1/rooms/table/ws?name=Alice", address);
+        let (mut socket, _) = connect_async(url).await.unwrap();
+        let joined = next_event(&mut socket).await;
+        assert_eq!(joined["type"], "joined");
+        assert_eq!(joined["player"], "Alice");
+
+        for message in [r#"{"expression":"1d6"}"#, "not json"] {
+            socket
+                .send(tungstenite::Message::text(message))
+                .await
+                .unwrap();
+        }
+        let roll = next_event(&mut socket).await;
+        assert_eq!(roll["type"], "roll");
+        assert_eq!(roll["player"], "Alice");
+        assert_eq!(roll["expression"], "1d6");
+        assert_eq!(roll["result"]["rolls"][0]["sides"], 6);
+        let invalid = next_event(&mut socket).await;
+        assert_eq!(invalid["type"], "error");
+        assert_eq!(invalid["error"]["code"], "INVALID_MESSAGE");
+        // The room's roll was the first one recorded in the session sharing its id.
+        let (status, body) = send(
+            &state,
+            request(
+                Method::POST,
+                "/v1/sessions/table/rolls",
+                Some(serde_json::json!({"expression": "1d6"})),
+            ),
+        )
+        .await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body["id"], 2);
+    }
+
+    #[tokio::test]
+    async fn room_messages_count_against_the_rate_limit() {
+        let mut state = state();
+        // The upgrade and each message are charged as requests of their own.
+        state.limits.per_ip = crate::limits::TokenBuckets::per_minute(3);
+        let address = serve(&state).await;
+
+        let url = format!("ws://{}/v1/rooms/table/ws?name=Alice", address);
+        let (mut socket, _) = connect_async(url).await.unwrap();
+        assert_eq!(next_event(&mut socket).await["type"], "joined");
+        for _ in 0..3 {
+            socket
+                .send(tungstenite::Message::text(r#"{"expression":"1d6"}"#))
+                .await
+                .unwrap();
+        }
+        // Errors are sent straight back, they can overtake the broadcast rolls.
+        let mut events = Vec::new();
+        for _ in 0..3 {
+            let event = next_event(&mut socket).await;
+            events.push(match event["type"].as_str().unwrap() {
+                "error" => event["error"]["code"].as_str().unwrap().to_string(),
+                other => other.to_string(),
+            });
+        }
+        events.sort();
+        assert_eq!(events, vec!["RATE_LIMITED", "roll", "roll"]);
+    }
+}
@@ -0,0 +1,406 @@
+use std::{future::IntoFuture, io, net::SocketAddr, path::PathBuf, sync::Arc, time::Duration};
+
+use axum::{Router, serve::Listener, serve::ListenerExt};
+use tokio::{
+    net::{TcpListener, TcpStream, UnixListener},
+    sync::{Notify, mpsc},
+};
+use tokio_rustls::{
+    TlsAcceptor,
+    rustls::{
+        self,
+        pki_types::{CertificateDer, PrivateKeyDer, pem::PemObject},
+    },
+    server::TlsStream,
+};
+
+/// TLS handshakes taking longer than this are abandoned.
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+/// Connections that completed their handshake, waiting to be accepted.
+const ACCEPT_BACKLOG: usize = 128;
+
+/// Where the server listens for requests.
+pub enum Endpoint {
+    Tcp {
+        host: String,
+        port: String,
+        tls: Option<TlsFiles>,
+    },
+    Unix(PathBuf),
+}
+
+/// PEM files holding the certificate chain to present and its private key.
+pub struct TlsFiles {
+    pub certificate: String,
+    pub key: String,
+}
+
+pub enum BoundListener {
+    Tcp(TcpListener),
+    Tls(TlsListener),
+    Unix(UnixListener, PathBuf),
+}
+
+fn load_tls_config(files: &TlsFiles) -> Result<rustls::ServerConfig, String> {
+    let certificates = CertificateDer::pem_file_iter(&files.certificate)
+        .and_then(|certificates| certificates.collect::<Result<Vec<_>, _>>())
+        .map_err(|e| format!("Failed to read certificate {}: {}", files.certificate, e))?;
+    if certificates.is_empty() {
+        return Err(format!("No certificates found in {}", files.certificate));
+    }
+    let key = PrivateKeyDer::from_pem_file(&files.key)
+        .map_err(|e| format!("Failed to read private key {}: {}", files.key, e))?;
+
+    let provider = Arc::new(rustls::crypto::ring::default_provider());
+    let mut config = rustls::ServerConfig::builder_with_provider(provider)
+        .with_safe_default_protocol_versions()
+        .and_then(|builder| {
+            builder
+                .with_no_client_auth()
+                .with_single_cert(certificates, key)
+        })
+        .map_err(|e| format!("Invalid TLS certificate or key: {}", e))?;
+    config.alpn_protocols = vec![b"http/1.1".to_vec()];
+
+    return Ok(config);
+}
+
+/// Binds the endpoint, failing with a message fit to show whoever is starting the server.
+pub async fn bind(endpoint: Endpoint) -> Result<BoundListener, String> {
+    match endpoint {
+        Endpoint::Tcp { host, port, tls } => {
+            let address = format!("{}:{}", host, port);
+            // Certificates are checked before binding so a bad one doesn't hold the port.
+            let tls_config = match &tls {
+                Some(files) => Some(load_tls_config(files)?),
+                None => None,
+            };
+            let listener = TcpListener::bind(&address)
+                .await
+                .map_err(|e| format!("Failed to listen on {}: {}", address, e))?;
+            return match tls_config {
+                Some(config) => Ok(BoundListener::Tls(TlsListener::new(
+                    listener,
+                    TlsAcceptor::from(Arc::new(config)),
+                )?)),
+                None => Ok(BoundListener::Tcp(listener)),
+            };
+        }
+        Endpoint::Unix(path) => {
+            if path.exists() {
+                // A socket left behind by a server that didn't exit cleanly is safe to replace,
+                // one that is still accepting connections is not.
+                if std::os::unix::net::UnixStream::connect(&path).is_ok() {
+                    return Err(format!(
+                        "Failed to listen on {}: another server is already listening on it",
+                        path.display()
+                    ));
+                }
+                std::fs::remove_file(&path).map_err(|e| {
+                    format!("Failed to remove stale socket {}: {}", path.display(), e)
+                })?;
+            }
+            let listener = UnixListener::bind(&path)
+                .map_err(|e| format!("Failed to listen on {}: {}", path.display(), e))?;
+            return Ok(BoundListener::Unix(listener, path));
+        }
+    }
+}
+
+/// Accepts TCP connections and completes their TLS handshakes in the background, so a slow
+/// client can't hold up the others.
+pub struct TlsListener {
+    local_addr: SocketAddr,
+    handshaken: mpsc::Receiver<(TlsStream<TcpStream>, SocketAddr)>,
+}
+
+impl TlsListener {
+    fn new(listener: TcpListener, acceptor: TlsAcceptor) -> Result<TlsListener, String> {
+        let local_addr = listener
+            .local_addr()
+            .map_err(|e| format!("Failed to find local address: {}", e))?;
+        let (sender, handshaken) = mpsc::channel(ACCEPT_BACKLOG);
+        tokio::spawn(async move {
+            let mut listener = listener;
+            loop {
+                let (stream, address) = Listener::accept(&mut listener).await;
+                if sender.is_closed() {
+                    return;
+                }
+                let (acceptor, sender) = (acceptor.clone(), sender.clone());
+                tokio::spawn(async move {
+                    match tokio::time::timeout(HANDSHAKE_TIMEOUT, acceptor.accept(stream)).await {
+                        Ok(Ok(stream)) => {
+                            let _ = sender.send((stream, address)).await;
+                        }
+                        Ok(Err(e)) => {
+                            tracing::debug!(%address, error = %e, "TLS handshake failed");
+                        }
+                        Err(_) => tracing::debug!(%address, "TLS handshake timed out"),
+                    }
+                });
+            }
+        });
+
+        return Ok(TlsListener {
+            local_addr,
+            handshaken,
+        });
+    }
+}
+
+impl Listener for TlsListener {
+    type Io = TlsStream<TcpStream>;
+    type Addr = SocketAddr;
+
+    async fn accept(&mut self) -> (Self::Io, Self::Addr) {
+        match self.handshaken.recv().await {
+            Some(connection) => return connection,
+            // The accepting task only stops once this listener is dropped.
+            None => return std::future::pending().await,
+        }
+    }
+
+    fn local_addr(&self) -> io::Result<Self::Addr> {
+        return Ok(self.local_addr);
+    }
+}
+
+/// Resolves once the process is asked to stop with SIGTERM or SIGINT.
+async fn shutdown_signal() {
+    let terminate = async {
+        match tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate()) {
+            Ok(mut signal) => {
+                signal.recv().await;
+            }
+            Err(e) => {
+                tracing::warn!(error = %e, "Failed to listen for SIGTERM");
+                std::future::pending::<()>().await;
+            }
+        }
+    };
+    tokio::select! {
+        _ = tokio::signal::ctrl_c() => {}
+        _ = terminate => {}
+    }
+}
+
+fn log_address(address: io::Result<SocketAddr>, scheme: &str) {
+    match address {
+        Ok(address) => tracing::info!("Server running on {}://{}", scheme, address),
+        Err(e) => tracing::warn!(error = %e, "Failed to find local address server is running on"),
+    }
+}
+
+/// Futures for shutting a server down: the first resolves once the process is asked to stop,
+/// the second `drain_timeout` after that, when whatever is still in flight gets cut off.
+pub fn shutdown(
+    server: &'static str,
+    drain_timeout: Duration,
+) -> (
+    impl Future<Output = ()> + Send + 'static,
+    impl Future<Output = ()> + Send + 'static,
+) {
+    return shutdown_on(shutdown_signal(), server, drain_timeout);
+}
+
+/// Like [`shutdown`], stopping once `signal` resolves rather than the process is asked to.
+fn shutdown_on(
+    signal: impl Future<Output = ()> + Send + 'static,
+    server: &'static str,
+    drain_timeout: Duration,
+) -> (
+    impl Future<Output = ()> + Send + 'static,
+    impl Future<Output = ()> + Send + 'static,
+) {
+    let stopping = Arc::new(Notify::new());
+    let signalled = stopping.clone();
+    let shutdown = async move {
+        signal.await;
+        tracing::info!(
+            server,
+            timeout_seconds = drain_timeout.as_secs(),
+            "Shutting down, draining in-flight requests"
+        );
+        signalled.notify_one();
+    };
+    let drain_deadline = async move {
+        stopping.notified().await;
+        tokio::time::sleep(drain_timeout).await;
+        tracing::warn!(
+            server,
+            "Timed out draining requests, closing remaining connections"
+        );
+    };
+
+    return (shutdown, drain_deadline);
+}
+
+/// Serves the app until asked to stop, then stops accepting connections and gives in-flight
+/// requests up to `drain_timeout` to finish. Rooms and session event streams never finish on
+/// their own, so they are cut off once the timeout passes.
+pub async fn serve(
+    listener: BoundListener,
+    app: Router,
+    drain_timeout: Duration,
+) -> io::Result<()> {
+    return serve_until(shutdown_signal(), listener, app, drain_timeout).await;
+}
+
+/// Like [`serve`], stopping once `signal` resolves rather than the process is asked to.
+async fn serve_until(
+    signal: impl Future<Output = ()> + Send + 'static,
+    listener: BoundListener,
+    app: Router,
+    drain_timeout: Duration,
+) -> io::Result<()> {
+    let (shutdown, drain_deadline) = shutdown_on(signal, "http", drain_timeout);
+
+    let served = match listener {
+        BoundListener::Tcp(listener) => {
+            log_address(listener.local_addr(), "http");
+            let server = axum::serve(
+                listener,
+                app.into_make_service_with_connect_info::<SocketAddr>(),
+            )
+            .with_graceful_shutdown(shutdown);
+            tokio::select! {
+                result = server.into_future() => result,
+                _ = drain_deadline => Ok(()),
+            }
+        }
+        BoundListener::Tls(listener) => {
+            log_address(Listener::local_addr(&listener), "https");
+            // Tapping the listener is what gives its connections a `ConnectInfo<SocketAddr>`.
+            let server = axum::serve(
+                listener.tap_io(|_| {}),
+                app.into_make_service_with_connect_info::<SocketAddr>(),
+            )
+            .with_graceful_shutdown(shutdown);
+            tokio::select! {
+                result = server.into_future() => result,
+                _ = drain_deadline => Ok(()),
+            }
+        }
+        BoundListener::Unix(listener, path) => {
+            tracing::info!("Server running on unix:{}", path.display());
+            // Peers on the socket have no address of their own, but their connect info tells
+            // requests that came over it apart.
+            let server = axum::serve(
+                listener.tap_io(|_| {}),
+                app.into_make_service_with_connect_info::<tokio::net::unix::SocketAddr>(),
+            )
+            .with_graceful_shutdown(shutdown);
+            let result = tokio::select! {
+                result = server.into_future() => result,
+                _ = drain_deadline => Ok(()),
+            };
+            if let Err(e) = std::fs::remove_file(&path) {
+                tracing::warn!(path = %path.display(), error = %e, "Failed to remove socket");
+            }
+            result
+        }
+    };
+
+    return served;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A path in the temporary directory unique to this test run.
+    fn temp_path(name: &str) -> PathBuf {
+        return std::env::temp_dir().join(format!("dice-roll-{}-{}", std::process::id(), name));
+    }
+
+    fn tcp(port: u16, tls: Option<TlsFiles>) -> Endpoint {
+        return Endpoint::Tcp {
+            host: "127.0.0.1".to_string(),
+            port: port.to_string(),
+            tls,
+        };
+    }
+
+    #[tokio::test]
+    async fn ports_in_use_are_an_error() {
+        let taken = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = taken.local_addr().unwrap().port();
+
+        let error = bind(tcp(port, None)).await.err().unwrap();
+        assert!(
+            error.starts_with("Failed to listen on 127.0.0.1:"),
+            "{}",
+            error
+        );
+    }
+
+    #[tokio::test]
+    async fn invalid_certificates_are_rejected_before_binding() {
+        let port = TcpListener::bind("127.0.0.1:0")
+            .await
+            .unwrap()
+            .local_addr()
+            .unwrap()
+            .port();
+        let (certificate, key) = (temp_path("cert.pem"), temp_path("key.pem"));
+        std::fs::write(&certificate, "not a certificate").unwrap();
+        std::fs::write(&key, "not a key").unwrap();
+        let files = |certificate: &PathBuf| TlsFiles {
+            certificate: certificate.display().to_string(),
+            key: key.display().to_string(),
+        };
+
+        let error = bind(tcp(port, Some(files(&certificate))))
+            .await
+            .err()
+            .unwrap();
+        assert!(error.starts_with("No certificates found in"), "{}", error);
+        let error = bind(tcp(port, Some(files(&temp_path("missing.pem")))))
+            .await
+            .err()
+            .unwrap();
+        assert!(error.starts_with("Failed to read certificate"), "{}", error);
+        // The port was never held.
+        TcpListener::bind(("127.0.0.1", port)).await.unwrap();
+        std::fs::remove_file(certificate).unwrap();
+        std::fs::remove_file(key).unwrap();
+    }
+
+    #[tokio::test]
+    async fn stale_sockets_are_replaced_but_live_ones_are_not() {
+        let path = temp_path("stale.sock");
+        drop(std::os::unix::net::UnixListener::bind(&path).unwrap());
+        assert!(path.exists());
+
+        let live = bind(Endpoint::Unix(path.clone())).await.unwrap();
+        let error = bind(Endpoint::Unix(path.clone())).await.err().unwrap();
+        assert!(
+            error.ends_with("another server is already listening on it"),
+            "{}",
+            error
+        );
+        drop(live);
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn sockets_are_removed_once_the_server_stops() {
+        let path = temp_path("served.sock");
+        let listener = bind(Endpoint::Unix(path.clone())).await.unwrap();
+        let (stop, stopped) = tokio::sync::oneshot::channel::<()>();
+        let server = tokio::spawn(serve_until(
+            async move {
+                let _ = stopped.await;
+            },
+            listener,
+            Router::new(),
+            Duration::from_secs(1),
+        ));
+        assert!(path.exists());
+
+        stop.send(()).unwrap();
+        server.await.unwrap().unwrap();
+        assert!(!path.exists());
+    }
+}
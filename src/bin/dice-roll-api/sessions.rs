@@ -0,0 +1,459 @@
+use std::{
+    collections::{HashMap, VecDeque},
+    sync::{Arc, Mutex},
+};
+
+use async_graphql::SimpleObject;
+use axum::{
+    Json,
+    extract::{Path, State, rejection::JsonRejection},
+    http::{HeaderMap, StatusCode},
+    response::{
+        IntoResponse, Response,
+        sse::{Event, KeepAlive, Sse},
+    },
+};
+use chrono::{DateTime, Utc};
+use dice_roll::RollResponse;
+use serde::{Deserialize, Serialize};
+use tokio::sync::broadcast;
+use tokio_stream::{Stream, StreamExt, iter, wrappers::BroadcastStream};
+use utoipa::ToSchema;
+
+use crate::{
+    AppState, RecordedRoll, admit_roll,
+    history::{Client, RollContext},
+    parse_notation, record_roll,
+    response::{Format, Negotiable, Problem},
+};
+
+const SESSION_CAPACITY: usize = 64;
+const SESSION_HISTORY: usize = 100;
+const MAX_SESSIONS: usize = 1024;
+const MAX_SESSION_ID_LENGTH: usize = 64;
+
+/// A roll recorded in a session's history.
+#[derive(Serialize, Clone, Debug, ToSchema, SimpleObject)]
+pub struct SessionRoll {
+    /// Sequential identifier of the roll within its session, used as the SSE event id.
+    pub id: u64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub player: Option<String>,
+    pub expression: String,
+    pub result: RollResponse,
+    pub timestamp: DateTime<Utc>,
+}
+
+impl Negotiable for SessionRoll {
+    fn into_text(self) -> String {
+        return match self.player {
+            Some(player) => format!("{}: {}", player, self.result),
+            None => self.result.to_string(),
+        };
+    }
+}
+
+struct Session {
+    next_id: u64,
+    history: VecDeque<SessionRoll>,
+    sender: broadcast::Sender<SessionRoll>,
+    updated_at: DateTime<Utc>,
+}
+
+impl Session {
+    fn new() -> Session {
+        return Session {
+            next_id: 1,
+            history: VecDeque::new(),
+            sender: broadcast::channel(SESSION_CAPACITY).0,
+            updated_at: Utc::now(),
+        };
+    }
+}
+
+/// Sessions by their tenant, `None` for sessions used without an API key, and id. Tenants using
+/// the same session id each get a session of their own.
+type SessionKey = (Option<String>, String);
+
+/// Recent roll history for each session, along with a channel for following new rolls.
+#[derive(Clone, Default)]
+pub struct Sessions {
+    sessions: Arc<Mutex<HashMap<SessionKey, Session>>>,
+}
+
+impl Sessions {
+    fn session<'a>(
+        sessions: &'a mut HashMap<SessionKey, Session>,
+        tenant: Option<&str>,
+        id: &str,
+    ) -> &'a mut Session {
+        let key = (tenant.map(str::to_string), id.to_string());
+        if !sessions.contains_key(&key) && sessions.len() >= MAX_SESSIONS {
+            // Make room by forgetting the least recently used session nobody is following.
+            let idle = sessions
+                .iter()
+                .filter(|(_, session)| session.sender.receiver_count() == 0)
+                .min_by_key(|(_, session)| session.updated_at)
+                .map(|(key, _)| key.clone());
+            if let Some(idle) = idle {
+                sessions.remove(&idle);
+            }
+        }
+
+        return sessions.entry(key).or_insert_with(Session::new);
+    }
+
+    /// Appends a roll to the tenant's session's history and notifies its followers.
+    pub fn record(
+        &self,
+        tenant: Option<&str>,
+        id: &str,
+        player: Option<String>,
+        expression: String,
+        result: RollResponse,
+    ) -> SessionRoll {
+        let mut sessions = self.sessions.lock().unwrap();
+        let session = Sessions::session(&mut sessions, tenant, id);
+        let roll = SessionRoll {
+            id: session.next_id,
+            player,
+            expression,
+            result,
+            timestamp: Utc::now(),
+        };
+        session.next_id += 1;
+        session.updated_at = roll.timestamp;
+        session.history.push_back(roll.clone());
+        if session.history.len() > SESSION_HISTORY {
+            session.history.pop_front();
+        }
+        let _ = session.sender.send(roll.clone());
+
+        return roll;
+    }
+
+    /// Subscribes to the tenant's session, returning the retained rolls made after `last_event_id`.
+    fn follow(
+        &self,
+        tenant: Option<&str>,
+        id: &str,
+        last_event_id: Option<u64>,
+    ) -> (Vec<SessionRoll>, broadcast::Receiver<SessionRoll>) {
+        let mut sessions = self.sessions.lock().unwrap();
+        let session = Sessions::session(&mut sessions, tenant, id);
+        let replay = match last_event_id {
+            Some(last_event_id) => session
+                .history
+                .iter()
+                .filter(|roll| roll.id > last_event_id)
+                .cloned()
+                .collect(),
+            None => Vec::new(),
+        };
+
+        return (replay, session.sender.subscribe());
+    }
+
+    /// Streams the tenant's session's retained rolls made after `last_event_id`, followed by new
+    /// rolls as they're made.
+    pub fn stream(
+        &self,
+        tenant: Option<&str>,
+        id: &str,
+        last_event_id: Option<u64>,
+    ) -> impl Stream<Item = SessionRoll> + Send + use<> {
+        let (replay, receiver) = self.follow(tenant, id, last_event_id);
+        let last_replayed = replay.last().map(|roll| roll.id).or(last_event_id);
+        let live = BroadcastStream::new(receiver).filter_map(move |roll| match roll {
+            Ok(roll) if Some(roll.id) > last_replayed => Some(roll),
+            // Lagging followers skip ahead; they can reconnect with `Last-Event-ID` to catch up.
+            _ => None,
+        });
+
+        return iter(replay).chain(live);
+    }
+}
+
+#[derive(Deserialize, Debug, ToSchema)]
+#[serde(deny_unknown_fields)]
+pub struct SessionRollRequest {
+    #[schema(example = "1d20 + 5")]
+    pub expression: String,
+    #[schema(example = "Alice")]
+    #[serde(default)]
+    pub player: Option<String>,
+}
+
+pub fn validate_session_id(id: &str) -> Result<(), Problem> {
+    if id.is_empty() || id.len() > MAX_SESSION_ID_LENGTH {
+        return Err(Problem::new(
+            StatusCode::BAD_REQUEST,
+            "INVALID_SESSION_ID",
+            format!(
+                "Session identifiers must be between 1 and {} characters.",
+                MAX_SESSION_ID_LENGTH
+            ),
+        )
+        .with_field("id".to_string()));
+    }
+
+    return Ok(());
+}
+
+/// Rolls dice notation and records the result in the session's history.
+#[utoipa::path(
+    post,
+    tag = "sessions",
+    path = "/v1/sessions/{id}/rolls",
+    params(("id" = String, Path, description = "Identifier of the session.")),
+    request_body = SessionRollRequest,
+    responses(
+        (status = 200, description = "Dice were rolled and recorded", body = SessionRoll),
+        (status = 400, description = "Invalid expression or session", content_type = "application/problem+json", body = Problem),
+    )
+)]
+pub async fn session_roll(
+    State(state): State<AppState>,
+    format: Format,
+    client: Client,
+    Path(id): Path<String>,
+    payload: Result<Json<SessionRollRequest>, JsonRejection>,
+) -> Response {
+    if let Err(problem) = validate_session_id(&id) {
+        return format.problem(problem);
+    }
+    let request = match payload {
+        Ok(request) => request.0,
+        Err(e) => {
+            return format.problem(Problem::from_json_rejection(e));
+        }
+    };
+    let context = RollContext {
+        session: Some(id),
+        player: request.player,
+        expression: Some(request.expression.clone()),
+        ..client.context()
+    };
+    let roll_request = match parse_notation("expression", &request.expression) {
+        Ok(roll_request) => roll_request,
+        Err(problem) => return format.problem(problem),
+    };
+    if let Err(problem) = admit_roll(&state, &context, &roll_request) {
+        return format.problem(problem);
+    }
+    let result = match roll_request.roll_dice() {
+        Ok(result) => result,
+        Err(e) => return format.problem(Problem::from(e)),
+    };
+    match record_roll(&state, context, &roll_request, &result).await {
+        Ok(RecordedRoll {
+            session_roll: Some(roll),
+            ..
+        }) => return format.respond(StatusCode::OK, roll),
+        // Only tenants allowed to use sessions get here, so their rolls are always sent to it.
+        Ok(_) => return StatusCode::INTERNAL_SERVER_ERROR.into_response(),
+        Err(problem) => return format.problem(problem),
+    }
+}
+
+/// Streams the rolls made in a session as Server-Sent Events. Only rolls made with an API key of
+/// the same tenant, or without one when none is sent, are streamed.
+///
+/// Each `roll` event carries a `SessionRoll` as its data and the roll's `id` as its event id.
+/// Reconnecting with a `Last-Event-ID` header replays any retained rolls made since that event.
+#[utoipa::path(
+    get,
+    tag = "sessions",
+    path = "/v1/sessions/{id}/events",
+    params(
+        ("id" = String, Path, description = "Identifier of the session."),
+        ("Last-Event-ID" = Option<u64>, Header, description = "Replay retained rolls made after this event id."),
+    ),
+    responses(
+        (status = 200, description = "Stream of `roll` events", content_type = "text/event-stream", body = SessionRoll),
+        (status = 400, description = "Invalid session", content_type = "application/problem+json", body = Problem),
+    )
+)]
+pub async fn session_events(
+    State(state): State<AppState>,
+    client: Client,
+    Path(id): Path<String>,
+    headers: HeaderMap,
+) -> Response {
+    if let Err(problem) = validate_session_id(&id) {
+        return problem.into_response();
+    }
+    let last_event_id = headers
+        .get("last-event-id")
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.trim().parse::<u64>().ok());
+
+    let stream = state
+        .sessions
+        .stream(client.tenant_id().as_deref(), &id, last_event_id)
+        .map(roll_event);
+
+    return Sse::new(stream)
+        .keep_alive(KeepAlive::default())
+        .into_response();
+}
+
+fn roll_event(roll: SessionRoll) -> Result<Event, axum::Error> {
+    return Event::default()
+        .event("roll")
+        .id(roll.id.to_string())
+        .json_data(&roll);
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use axum::http::Method;
+    use dice_roll::parser;
+    use tower::ServiceExt;
+
+    use super::*;
+    use crate::tests::{request, send, state, with_header};
+
+    fn roll() -> RollResponse {
+        return parser::parse("1d6".to_string())
+            .unwrap()
+            .roll_dice()
+            .unwrap();
+    }
+
+    #[test]
+    fn tenants_get_sessions_of_their_own() {
+        let sessions = Sessions::default();
+        sessions.record(Some("acme"), "table", None, "1d6".to_string(), roll());
+        sessions.record(Some("acme"), "table", None, "1d6".to_string(), roll());
+        let globex = sessions.record(Some("globex"), "table", None, "1d6".to_string(), roll());
+        assert_eq!(globex.id, 1);
+
+        assert_eq!(sessions.follow(Some("acme"), "table", Some(0)).0.len(), 2);
+        assert_eq!(sessions.follow(Some("globex"), "table", Some(0)).0.len(), 1);
+        assert!(sessions.follow(None, "table", Some(0)).0.is_empty());
+    }
+
+    #[tokio::test]
+    async fn streams_replay_missed_rolls_then_follow_new_ones() {
+        let sessions = Sessions::default();
+        for _ in 0..3 {
+            sessions.record(None, "table", None, "1d6".to_string(), roll());
+        }
+
+        let mut stream = Box::pin(sessions.stream(None, "table", Some(1)));
+        sessions.record(
+            None,
+            "table",
+            Some("Alice".to_string()),
+            "1d6".to_string(),
+            roll(),
+        );
+        let mut ids = Vec::new();
+        for _ in 0..3 {
+            let roll = tokio::time::timeout(Duration::from_secs(1), stream.next())
+                .await
+                .unwrap()
+                .unwrap();
+            ids.push(roll.id);
+        }
+        assert_eq!(ids, [2, 3, 4]);
+
+        let mut fresh = Box::pin(sessions.stream(None, "table", None));
+        sessions.record(None, "table", None, "1d6".to_string(), roll());
+        assert_eq!(fresh.next().await.unwrap().id, 5);
+    }
+
+    #[test]
+    fn sessions_only_keep_their_latest_rolls() {
+        let sessions = Sessions::default();
+        for _ in 0..SESSION_HISTORY + 5 {
+            sessions.record(None, "table", None, "1d6".to_string(), roll());
+        }
+
+        let (replay, _) = sessions.follow(None, "table", Some(0));
+        assert_eq!(replay.len(), SESSION_HISTORY);
+        assert_eq!(replay[0].id, 6);
+    }
+
+    #[tokio::test]
+    async fn rolls_are_sent_as_server_sent_events() {
+        let state = state();
+        let roll = request(
+            Method::POST,
+            "/v1/sessions/table/rolls",
+            Some(serde_json::json!({"expression": "1d1 + 1", "player": "Alice"})),
+        );
+        let (status, body) = send(&state, roll).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body["id"], 1);
+
+        let events = with_header(
+            request(Method::GET, "/v1/sessions/table/events", None),
+            "last-event-id",
+            "0",
+        );
+        let response = crate::app(state.clone(), None)
+            .oneshot(events)
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.headers()["content-type"], "text/event-stream");
+        let mut body = response.into_body().into_data_stream();
+        let event = tokio::time::timeout(Duration::from_secs(1), body.next())
+            .await
+            .unwrap()
+            .unwrap()
+            .unwrap();
+        let event = String::from_utf8(event.to_vec()).unwrap();
+        assert!(event.contains("event: roll\n"), "{}", event);
+        assert!(event.contains("id: 1\n"), "{}", event);
+        assert!(event.contains("\"player\":\"Alice\""), "{}", event);
+
+        let (status, problem) = send(
+            &state,
+            request(
+                Method::GET,
+                &format!("/v1/sessions/{}/events", "s".repeat(65)),
+                None,
+            ),
+        )
+        .await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert_eq!(problem["code"], "INVALID_SESSION_ID");
+    }
+
+    #[tokio::test]
+    async fn rolls_made_elsewhere_in_a_session_are_sent_to_it() {
+        let state = state();
+        let roll = request(
+            Method::POST,
+            "/v1/rolls?session=table",
+            Some(serde_json::json!({"dice": [{"count": 2, "sides": 6, "modifier": 1}]})),
+        );
+        let (status, _) = send(&state, roll).await;
+        assert_eq!(status, StatusCode::OK);
+
+        let events = with_header(
+            request(Method::GET, "/v1/sessions/table/events", None),
+            "last-event-id",
+            "0",
+        );
+        let response = crate::app(state.clone(), None)
+            .oneshot(events)
+            .await
+            .unwrap();
+        let mut body = response.into_body().into_data_stream();
+        let event = tokio::time::timeout(Duration::from_secs(1), body.next())
+            .await
+            .unwrap()
+            .unwrap()
+            .unwrap();
+        let event = String::from_utf8(event.to_vec()).unwrap();
+        assert!(event.contains("id: 1\n"), "{}", event);
+        assert!(event.contains("\"expression\":\"2d6 + 1\""), "{}", event);
+        assert_eq!(state.sessions.follow(None, "table", Some(0)).0.len(), 1);
+    }
+}
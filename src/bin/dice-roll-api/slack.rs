@@ -0,0 +1,368 @@
+use axum::{
+    Json,
+    body::Bytes,
+    extract::State,
+    http::{HeaderMap, StatusCode},
+    response::{IntoResponse, Response},
+};
+use chrono::Utc;
+use dice_roll::parser;
+use hmac::{Hmac, Mac};
+use serde::Deserialize;
+use serde_json::json;
+use sha2::Sha256;
+
+use crate::{
+    AppState, discord::MAX_REQUEST_AGE_SECONDS, history::RollContext, response::Problem,
+    roll_and_record,
+};
+
+type HmacSha256 = Hmac<Sha256>;
+
+/// Secrets slash-command requests are authenticated with.
+#[derive(Clone, Default)]
+pub struct SlashCommandSecrets {
+    /// Signing secret of the Slack app, used to check `X-Slack-Signature`.
+    pub slack_signing_secret: Option<String>,
+    /// Token Mattermost sends along with each slash-command request.
+    pub mattermost_token: Option<String>,
+}
+
+impl SlashCommandSecrets {
+    pub fn is_enabled(&self) -> bool {
+        return self.slack_signing_secret.is_some() || self.mattermost_token.is_some();
+    }
+}
+
+#[derive(Deserialize, Debug)]
+struct SlashCommand {
+    #[serde(default)]
+    text: String,
+    #[serde(default)]
+    user_name: Option<String>,
+    /// Only sent by Mattermost.
+    #[serde(default)]
+    token: Option<String>,
+}
+
+/// Compares secrets without bailing out at the first differing byte.
+pub fn secrets_match(expected: &[u8], provided: &[u8]) -> bool {
+    if expected.len() != provided.len() {
+        return false;
+    }
+    return expected
+        .iter()
+        .zip(provided)
+        .fold(0, |difference, (a, b)| difference | (a ^ b))
+        == 0;
+}
+
+/// Checks Slack's `v0` signature, an HMAC-SHA256 over `v0:{timestamp}:{body}`.
+fn verify_slack_signature(signing_secret: &str, headers: &HeaderMap, body: &[u8]) -> bool {
+    let header = |name: &str| headers.get(name).and_then(|value| value.to_str().ok());
+    let (signature, timestamp) = match (
+        header("x-slack-signature"),
+        header("x-slack-request-timestamp"),
+    ) {
+        (Some(signature), Some(timestamp)) => (signature, timestamp),
+        _ => return false,
+    };
+    match timestamp.parse::<i64>() {
+        Ok(signed_at) if (Utc::now().timestamp() - signed_at).abs() <= MAX_REQUEST_AGE_SECONDS => {}
+        _ => return false,
+    }
+    let signature = match signature.strip_prefix("v0=").map(hex::decode) {
+        Some(Ok(signature)) => signature,
+        _ => return false,
+    };
+    let mut mac = match HmacSha256::new_from_slice(signing_secret.as_bytes()) {
+        Ok(mac) => mac,
+        Err(_) => return false,
+    };
+    mac.update(format!("v0:{}:", timestamp).as_bytes());
+    mac.update(body);
+
+    return mac.verify_slice(&signature).is_ok();
+}
+
+fn authenticate(
+    secrets: &SlashCommandSecrets,
+    headers: &HeaderMap,
+    body: &[u8],
+    command: &SlashCommand,
+) -> bool {
+    if let Some(signing_secret) = &secrets.slack_signing_secret
+        && headers.contains_key("x-slack-signature")
+    {
+        return verify_slack_signature(signing_secret, headers, body);
+    }
+    if let (Some(expected), Some(provided)) = (&secrets.mattermost_token, &command.token) {
+        return secrets_match(expected.as_bytes(), provided.as_bytes());
+    }
+
+    return false;
+}
+
+/// Replies to the channel the command was run in. Slack renders the blocks while
+/// Mattermost, which doesn't support them, falls back to the text.
+fn in_channel(text: String) -> Response {
+    return Json(json!({
+        "response_type": "in_channel",
+        "text": text,
+        "blocks": [{
+            "type": "section",
+            "text": { "type": "mrkdwn", "text": text },
+        }],
+    }))
+    .into_response();
+}
+
+/// Only shown to the user who ran the command.
+fn ephemeral(text: String) -> Response {
+    return Json(json!({
+        "response_type": "ephemeral",
+        "text": text,
+    }))
+    .into_response();
+}
+
+/// Answers `/roll 2d6+1` slash commands sent by Slack or Mattermost.
+///
+/// Slack requests are checked against the app's signing secret, Mattermost requests against
+/// the slash command's token.
+#[utoipa::path(
+    post,
+    tag = "slack",
+    path = "/slack/commands",
+    request_body(content = String, content_type = "application/x-www-form-urlencoded", description = "A slash-command request, e.g. `text=2d6%2B1&user_name=alice`"),
+    responses(
+        (status = 200, description = "Slash-command response", body = Object),
+        (status = 401, description = "Request could not be authenticated", content_type = "application/problem+json", body = Problem),
+        (status = 404, description = "Slash commands are not enabled", content_type = "application/problem+json", body = Problem),
+    )
+)]
+pub async fn slash_command(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    body: Bytes,
+) -> Response {
+    if !state.slash_commands.is_enabled() {
+        return Problem::new(
+            StatusCode::NOT_FOUND,
+            "SLASH_COMMANDS_DISABLED",
+            "This server has not been configured with a Slack signing secret or Mattermost token."
+                .to_string(),
+        )
+        .into_response();
+    }
+    let command = match serde_urlencoded::from_bytes::<SlashCommand>(&body) {
+        Ok(command) => command,
+        Err(e) => {
+            return Problem::new(
+                StatusCode::BAD_REQUEST,
+                "INVALID_SLASH_COMMAND",
+                format!("Request body is not a valid slash command, {}.", e),
+            )
+            .into_response();
+        }
+    };
+    if !authenticate(&state.slash_commands, &headers, &body, &command) {
+        return Problem::new(
+            StatusCode::UNAUTHORIZED,
+            "INVALID_SIGNATURE",
+            "Request signature could not be verified.".to_string(),
+        )
+        .into_response();
+    }
+
+    let expression = command.text.trim().to_string();
+    if expression.is_empty() {
+        return ephemeral("Usage: `/roll 2d6+1`".to_string());
+    }
+    let roll_request = match parser::parse(expression.clone()) {
+        Ok(roll_request) => roll_request,
+        Err(e) => return ephemeral(e.to_string()),
+    };
+    let context = RollContext {
+        // Users relaying rolls through Slack or Mattermost have a dice budget of their own.
+        client: command
+            .user_name
+            .as_ref()
+            .map(|user_name| format!("slack:{}", user_name)),
+        player: command.user_name.clone(),
+        expression: Some(expression.clone()),
+        ..RollContext::default()
+    };
+    match roll_and_record(&state, roll_request, context).await {
+        Ok(result) => {
+            let roller = match command.user_name {
+                Some(user_name) => format!("*{}*", user_name),
+                None => "Someone".to_string(),
+            };
+            return in_channel(format!("{} rolled `{}`\n`{}`", roller, expression, result));
+        }
+        Err(problem) => return ephemeral(problem.detail),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::{
+        body::Body,
+        http::{HeaderValue, Method, header},
+    };
+
+    use super::*;
+    use crate::tests::{request, send, state};
+
+    const SIGNING_SECRET: &str = "8f742231b10e8888abcd99yyyzzz85a5";
+    const BODY: &str = "text=2d6%2B1&user_name=alice";
+
+    fn signed_headers(secret: &str, timestamp: i64, body: &str) -> HeaderMap {
+        let mut mac = HmacSha256::new_from_slice(secret.as_bytes()).unwrap();
+        mac.update(format!("v0:{}:{}", timestamp, body).as_bytes());
+        let signature = format!("v0={}", hex::encode(mac.finalize().into_bytes()));
+
+        let mut headers = HeaderMap::new();
+        headers.insert(
+            "x-slack-signature",
+            HeaderValue::from_str(&signature).unwrap(),
+        );
+        headers.insert(
+            "x-slack-request-timestamp",
+            HeaderValue::from_str(&timestamp.to_string()).unwrap(),
+        );
+        return headers;
+    }
+
+    fn slash_command(body: &str, headers: HeaderMap) -> axum::http::Request<Body> {
+        let mut request = request(Method::POST, "/slack/commands", None);
+        *request.body_mut() = Body::from(body.to_string());
+        request.headers_mut().insert(
+            header::CONTENT_TYPE,
+            HeaderValue::from_static("application/x-www-form-urlencoded"),
+        );
+        request.headers_mut().extend(headers);
+        return request;
+    }
+
+    #[test]
+    fn slack_signatures_are_checked() {
+        let now = Utc::now().timestamp();
+        let headers = signed_headers(SIGNING_SECRET, now, BODY);
+        assert!(verify_slack_signature(
+            SIGNING_SECRET,
+            &headers,
+            BODY.as_bytes()
+        ));
+
+        let tampered = BODY.replace("2d6", "9d6");
+        assert!(!verify_slack_signature(
+            SIGNING_SECRET,
+            &headers,
+            tampered.as_bytes()
+        ));
+        assert!(!verify_slack_signature(
+            "another-secret",
+            &headers,
+            BODY.as_bytes()
+        ));
+        let stale = signed_headers(SIGNING_SECRET, now - MAX_REQUEST_AGE_SECONDS - 60, BODY);
+        assert!(!verify_slack_signature(
+            SIGNING_SECRET,
+            &stale,
+            BODY.as_bytes()
+        ));
+    }
+
+    #[test]
+    fn compares_secrets() {
+        assert!(secrets_match(b"token", b"token"));
+        assert!(!secrets_match(b"token", b"tokem"));
+        assert!(!secrets_match(b"token", b"token2"));
+    }
+
+    #[tokio::test]
+    async fn answers_slack_and_mattermost_commands() {
+        let state = AppState {
+            slash_commands: SlashCommandSecrets {
+                slack_signing_secret: Some(SIGNING_SECRET.to_string()),
+                mattermost_token: Some("mattermost-token".to_string()),
+            },
+            ..state()
+        };
+        let now = Utc::now().timestamp();
+
+        let (status, body) = send(
+            &state,
+            slash_command(BODY, signed_headers(SIGNING_SECRET, now, BODY)),
+        )
+        .await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body["response_type"], "in_channel");
+        assert!(
+            body["text"]
+                .as_str()
+                .unwrap()
+                .starts_with("*alice* rolled `2d6+1`")
+        );
+
+        let (status, body) = send(
+            &state,
+            slash_command(BODY, signed_headers("another-secret", now, BODY)),
+        )
+        .await;
+        assert_eq!(status, StatusCode::UNAUTHORIZED);
+        assert_eq!(body["code"], "INVALID_SIGNATURE");
+
+        let mattermost = format!("{}&token=mattermost-token", BODY);
+        let (status, body) = send(&state, slash_command(&mattermost, HeaderMap::new())).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body["response_type"], "in_channel");
+
+        let wrong_token = format!("{}&token=guessed", BODY);
+        let (status, _) = send(&state, slash_command(&wrong_token, HeaderMap::new())).await;
+        assert_eq!(status, StatusCode::UNAUTHORIZED);
+
+        let invalid = "text=2d&token=mattermost-token";
+        let (status, body) = send(&state, slash_command(invalid, HeaderMap::new())).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body["response_type"], "ephemeral");
+    }
+
+    #[tokio::test]
+    async fn users_have_dice_budgets_of_their_own() {
+        let state = state();
+        let state = AppState {
+            slash_commands: SlashCommandSecrets {
+                slack_signing_secret: None,
+                mattermost_token: Some("mattermost-token".to_string()),
+            },
+            limits: crate::limits::Limits {
+                dice_budget: crate::limits::TokenBuckets::per_minute(3),
+                ..state.limits.clone()
+            },
+            ..state
+        };
+        let roll = |user_name: &str| {
+            let body = format!("text=2d6&user_name={}&token=mattermost-token", user_name);
+            return slash_command(&body, HeaderMap::new());
+        };
+
+        let (_, body) = send(&state, roll("alice")).await;
+        assert_eq!(body["response_type"], "in_channel");
+        let (_, body) = send(&state, roll("alice")).await;
+        assert_eq!(body["response_type"], "ephemeral");
+        let text = body["text"].as_str().unwrap();
+        assert!(text.starts_with("This roll needs 2 dice"), "{}", text);
+        let (_, body) = send(&state, roll("bob")).await;
+        assert_eq!(body["response_type"], "in_channel");
+    }
+
+    #[tokio::test]
+    async fn slash_commands_are_disabled_without_secrets() {
+        let (status, body) = send(&state(), slash_command(BODY, HeaderMap::new())).await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+        assert_eq!(body["code"], "SLASH_COMMANDS_DISABLED");
+    }
+}
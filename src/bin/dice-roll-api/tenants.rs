@@ -0,0 +1,988 @@
+use std::{
+    collections::HashMap,
+    sync::{Arc, RwLock},
+};
+
+use axum::{
+    Json,
+    extract::{FromRequestParts, MatchedPath, Path, Request, State, rejection::JsonRejection},
+    http::{Method, StatusCode, header, request::Parts},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use chrono::{DateTime, SecondsFormat, Utc};
+use dice_roll::{BoundConstraint, DEFAULT_LIMITS, RollLimits};
+use rusqlite::{Connection, OptionalExtension, Row, params};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use utoipa::ToSchema;
+
+use crate::{
+    AppState,
+    history::History,
+    limits::EXEMPT_PATHS,
+    response::{Format, Negotiable, Problem},
+    slack::secrets_match,
+    webhooks::random_hex,
+};
+
+pub const API_KEY_HEADER: &str = "x-api-key";
+const KEY_PREFIX: &str = "drk_";
+const MAX_TENANT_ID_LENGTH: usize = 64;
+
+pub const SCHEMA: &str = "
+    CREATE TABLE IF NOT EXISTS tenants (
+        id TEXT PRIMARY KEY,
+        policy TEXT NOT NULL,
+        created_at TEXT NOT NULL,
+        updated_at TEXT NOT NULL
+    );
+    CREATE TABLE IF NOT EXISTS api_keys (
+        id TEXT PRIMARY KEY,
+        tenant_id TEXT NOT NULL REFERENCES tenants (id),
+        key_hash TEXT NOT NULL UNIQUE,
+        created_at TEXT NOT NULL,
+        revoked_at TEXT
+    );
+";
+
+/// Parts of the API a tenant can be allowed to use, rolling dice is always allowed.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum Feature {
+    /// Reading back the roll history.
+    History,
+    Sessions,
+    Rooms,
+    /// Provably fair rolls.
+    Fair,
+    /// Signed roll receipts.
+    Receipts,
+    Webhooks,
+    Campaigns,
+}
+
+impl Feature {
+    pub const ALL: [Feature; 7] = [
+        Feature::History,
+        Feature::Sessions,
+        Feature::Rooms,
+        Feature::Fair,
+        Feature::Receipts,
+        Feature::Webhooks,
+        Feature::Campaigns,
+    ];
+
+    pub fn parse(name: &str) -> Option<Feature> {
+        return Feature::ALL
+            .into_iter()
+            .find(|feature| feature.as_str() == name);
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        return match self {
+            Feature::History => "history",
+            Feature::Sessions => "sessions",
+            Feature::Rooms => "rooms",
+            Feature::Fair => "fair",
+            Feature::Receipts => "receipts",
+            Feature::Webhooks => "webhooks",
+            Feature::Campaigns => "campaigns",
+        };
+    }
+
+    /// Feature a route belongs to, given its template, e.g. `/v1/rolls/{id}`.
+    fn of_route(method: &Method, route: &str) -> Option<Feature> {
+        if (route == "/v1/rolls" && method == Method::GET) || route == "/v1/rolls/{id}" {
+            return Some(Feature::History);
+        }
+        let prefixes = [
+            ("/v1/sessions/", Feature::Sessions),
+            ("/v1/rooms/", Feature::Rooms),
+            ("/v1/fair/", Feature::Fair),
+            ("/v1/receipts", Feature::Receipts),
+            ("/v1/webhooks", Feature::Webhooks),
+            ("/v1/campaigns", Feature::Campaigns),
+        ];
+        return prefixes
+            .into_iter()
+            .find(|(prefix, _)| route.starts_with(*prefix))
+            .map(|(_, feature)| feature);
+    }
+}
+
+/// Limits and features of a tenant. Limits can only be tightened from the server's defaults,
+/// anything left out falls back to them.
+#[derive(Serialize, Deserialize, Clone, Debug, Default, ToSchema)]
+#[serde(deny_unknown_fields)]
+pub struct TenantPolicy {
+    /// Most sides a die can have.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    #[schema(example = 100)]
+    pub max_sides: Option<i32>,
+    /// Most dice of a kind, e.g. the `4` in `4d6`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_count: Option<i32>,
+    /// Largest modifier, either positive or negative.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_modifier: Option<i32>,
+    /// Most dice a single roll can have in total.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_dice: Option<i32>,
+    /// Features the tenant can use, all of them when left out.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub features: Option<Vec<Feature>>,
+    /// Requests per minute shared by the tenant's keys, 0 disables the limit.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub rate_limit: Option<u32>,
+    /// Dice the tenant can roll per minute, 0 disables the limit.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub dice_budget: Option<u32>,
+}
+
+impl TenantPolicy {
+    /// Parses the policy requests without an API key are held to, given as tenant policy JSON.
+    /// Their request rate and dice budget are set with `--rate-limit` and `--dice-budget`.
+    pub fn parse_anonymous(policy: &str) -> Result<TenantPolicy, String> {
+        let policy: TenantPolicy = serde_json::from_str(policy).map_err(|e| e.to_string())?;
+        policy.validate().map_err(|problem| problem.detail)?;
+        if policy.rate_limit.is_some() || policy.dice_budget.is_some() {
+            return Err(
+                "rate_limit and dice_budget are set with --rate-limit and --dice-budget"
+                    .to_string(),
+            );
+        }
+
+        return Ok(policy);
+    }
+
+    fn limits(&self) -> RollLimits {
+        let upper = |bound: BoundConstraint, max: Option<i32>| BoundConstraint {
+            lower_bound: bound.lower_bound,
+            upper_bound: max.unwrap_or(bound.upper_bound),
+        };
+        let modifier = self
+            .max_modifier
+            .unwrap_or(DEFAULT_LIMITS.modifier.upper_bound);
+
+        return RollLimits {
+            sides: upper(DEFAULT_LIMITS.sides, self.max_sides),
+            count: upper(DEFAULT_LIMITS.count, self.max_count),
+            modifier: BoundConstraint {
+                lower_bound: -modifier,
+                upper_bound: modifier,
+            },
+            max_dice: self.max_dice.unwrap_or(DEFAULT_LIMITS.max_dice),
+        };
+    }
+
+    fn validate(&self) -> Result<(), Problem> {
+        let bounds = [
+            ("max_sides", self.max_sides, DEFAULT_LIMITS.sides),
+            ("max_count", self.max_count, DEFAULT_LIMITS.count),
+            (
+                "max_modifier",
+                self.max_modifier,
+                BoundConstraint {
+                    lower_bound: 0,
+                    upper_bound: DEFAULT_LIMITS.modifier.upper_bound,
+                },
+            ),
+            (
+                "max_dice",
+                self.max_dice,
+                BoundConstraint {
+                    lower_bound: 1,
+                    upper_bound: DEFAULT_LIMITS.max_dice,
+                },
+            ),
+        ];
+        for (field, value, bound) in bounds {
+            if let Some(value) = value
+                && (value < bound.lower_bound || value > bound.upper_bound)
+            {
+                return Err(invalid_policy(
+                    field,
+                    format!(
+                        "{} must be between {} and {}, {} provided.",
+                        field, bound.lower_bound, bound.upper_bound, value
+                    ),
+                ));
+            }
+        }
+        let max_dice = self.limits().max_dice;
+        if let Some(dice_budget) = self.dice_budget
+            && dice_budget != 0
+            && (dice_budget as i64) < max_dice as i64
+        {
+            return Err(invalid_policy(
+                "dice_budget",
+                format!(
+                    "dice_budget must be 0 or at least max_dice, {}, to allow every roll.",
+                    max_dice
+                ),
+            ));
+        }
+
+        return Ok(());
+    }
+
+    fn allows(&self, feature: Feature) -> bool {
+        return match &self.features {
+            Some(features) => features.contains(&feature),
+            None => true,
+        };
+    }
+}
+
+fn invalid_policy(field: &str, detail: String) -> Problem {
+    return Problem::new(StatusCode::BAD_REQUEST, "INVALID_TENANT_POLICY", detail)
+        .with_field(field.to_string());
+}
+
+/// A tenant resolved from the API key a request was made with.
+#[derive(Debug)]
+pub struct Tenant {
+    pub id: String,
+    pub policy: TenantPolicy,
+}
+
+impl Tenant {
+    pub fn limits(&self) -> RollLimits {
+        return self.policy.limits();
+    }
+
+    /// Checks the tenant is allowed to use `feature`.
+    pub fn require(&self, feature: Feature) -> Result<(), Problem> {
+        if self.policy.allows(feature) {
+            return Ok(());
+        }
+
+        return Err(Problem::new(
+            StatusCode::FORBIDDEN,
+            "FEATURE_NOT_ALLOWED",
+            format!(
+                "Tenant {} is not allowed to use {}.",
+                self.id,
+                feature.as_str()
+            ),
+        ));
+    }
+
+    /// Key the tenant's rate limits are tracked under.
+    pub fn bucket(&self) -> String {
+        return format!("tenant:{}", self.id);
+    }
+}
+
+pub fn hash_key(key: &str) -> String {
+    return hex::encode(Sha256::digest(key.as_bytes()));
+}
+
+fn now() -> String {
+    return Utc::now().to_rfc3339_opts(SecondsFormat::Micros, true);
+}
+
+fn timestamp(value: Option<String>) -> Option<DateTime<Utc>> {
+    return value
+        .and_then(|value| DateTime::parse_from_rfc3339(&value).ok())
+        .map(|value| value.with_timezone(&Utc));
+}
+
+fn parse_policy(policy: &str) -> Result<TenantPolicy, rusqlite::Error> {
+    return serde_json::from_str(policy).map_err(|e| {
+        rusqlite::Error::FromSqlConversionFailure(0, rusqlite::types::Type::Text, Box::new(e))
+    });
+}
+
+/// Active API keys, by the hash of the key, kept in memory so requests don't hit the database,
+/// along with the policy requests without an API key are held to and the features disabled for
+/// everyone.
+#[derive(Clone)]
+pub struct Tenants {
+    history: History,
+    keys: Arc<RwLock<HashMap<String, Arc<Tenant>>>>,
+    anonymous: Arc<TenantPolicy>,
+    disabled: Arc<Vec<Feature>>,
+}
+
+impl Tenants {
+    pub fn load(
+        history: History,
+        anonymous: TenantPolicy,
+        disabled: Vec<Feature>,
+    ) -> Result<Tenants, String> {
+        let keys = history
+            .with_connection_blocking(active_keys)
+            .map_err(|e| e.to_string())?;
+
+        return Ok(Tenants {
+            history,
+            keys: Arc::new(RwLock::new(keys)),
+            anonymous: Arc::new(anonymous),
+            disabled: Arc::new(disabled),
+        });
+    }
+
+    /// Roll limits of the tenant, or of requests without an API key when there's none.
+    pub fn limits(&self, tenant: Option<&Tenant>) -> RollLimits {
+        return match tenant {
+            Some(tenant) => tenant.limits(),
+            None => self.anonymous.limits(),
+        };
+    }
+
+    /// Checks the tenant, or requests without an API key when there's none, can use `feature`,
+    /// and that it hasn't been disabled on the server.
+    pub fn require(&self, tenant: Option<&Tenant>, feature: Feature) -> Result<(), Problem> {
+        if self.disabled.contains(&feature) {
+            return Err(Problem::new(
+                StatusCode::NOT_FOUND,
+                "FEATURE_DISABLED",
+                format!("{} is disabled on this server.", feature.as_str()),
+            ));
+        }
+        match tenant {
+            Some(tenant) => return tenant.require(feature),
+            None if self.anonymous.allows(feature) => return Ok(()),
+            None => {
+                return Err(Problem::new(
+                    StatusCode::FORBIDDEN,
+                    "FEATURE_NOT_ALLOWED",
+                    format!(
+                        "Requests without an API key are not allowed to use {}.",
+                        feature.as_str()
+                    ),
+                ));
+            }
+        }
+    }
+
+    /// Picks up keys and policies changed through the admin endpoints.
+    async fn reload(&self) -> Result<(), Problem> {
+        let keys = self.history.with_connection(active_keys).await?;
+        *self.keys.write().unwrap() = keys;
+        return Ok(());
+    }
+
+    /// Resolves an API key to its tenant, `None` for unknown or revoked keys.
+    pub fn resolve(&self, key: &str) -> Option<Arc<Tenant>> {
+        return self.keys.read().unwrap().get(&hash_key(key)).cloned();
+    }
+
+    /// Resolves an API key to its tenant, checking the tenant is allowed to use `feature`.
+    pub fn authorize(&self, key: &str, feature: Option<Feature>) -> Result<Arc<Tenant>, Problem> {
+        let tenant = match self.resolve(key) {
+            Some(tenant) => tenant,
+            None => {
+                return Err(Problem::new(
+                    StatusCode::UNAUTHORIZED,
+                    "INVALID_API_KEY",
+                    "The API key is unknown or has been revoked.".to_string(),
+                ));
+            }
+        };
+        if let Some(feature) = feature {
+            self.require(Some(&tenant), feature)?;
+        }
+
+        return Ok(tenant);
+    }
+}
+
+fn active_keys(connection: &Connection) -> Result<HashMap<String, Arc<Tenant>>, rusqlite::Error> {
+    let mut tenants = HashMap::new();
+    let mut statement = connection.prepare("SELECT id, policy FROM tenants")?;
+    let rows = statement.query_map([], |row| {
+        let policy: String = row.get(1)?;
+        return Ok(Tenant {
+            id: row.get(0)?,
+            policy: parse_policy(&policy)?,
+        });
+    })?;
+    for tenant in rows {
+        let tenant = tenant?;
+        tenants.insert(tenant.id.clone(), Arc::new(tenant));
+    }
+
+    let mut statement =
+        connection.prepare("SELECT key_hash, tenant_id FROM api_keys WHERE revoked_at IS NULL")?;
+    let rows = statement.query_map([], |row| {
+        return Ok((row.get::<_, String>(0)?, row.get::<_, String>(1)?));
+    })?;
+    let mut keys = HashMap::new();
+    for row in rows {
+        let (key_hash, tenant_id) = row?;
+        if let Some(tenant) = tenants.get(&tenant_id) {
+            keys.insert(key_hash, tenant.clone());
+        }
+    }
+
+    return Ok(keys);
+}
+
+/// Resolves the `X-API-Key` header to its tenant, which later extractors and middleware read
+/// from the request's extensions, and checks the tenant, or the anonymous policy for requests
+/// without a key, allows the route.
+pub async fn authenticate(
+    State(state): State<AppState>,
+    mut request: Request,
+    next: Next,
+) -> Response {
+    if EXEMPT_PATHS.contains(&request.uri().path()) {
+        return next.run(request).await;
+    }
+    let feature = request
+        .extensions()
+        .get::<MatchedPath>()
+        .and_then(|route| Feature::of_route(request.method(), route.as_str()));
+    let key = match request.headers().get(API_KEY_HEADER) {
+        Some(key) => key.to_str().unwrap_or_default(),
+        None => {
+            if let Some(feature) = feature
+                && let Err(problem) = state.tenants.require(None, feature)
+            {
+                return problem.into_response();
+            }
+            return next.run(request).await;
+        }
+    };
+    let tenant = match state.tenants.authorize(key, feature) {
+        Ok(tenant) => tenant,
+        Err(problem) => return problem.into_response(),
+    };
+
+    tracing::Span::current().record("tenant", &tenant.id);
+    request.extensions_mut().insert(tenant);
+    return next.run(request).await;
+}
+
+/// Checks the request carries the `--admin-token` as a bearer token.
+fn check_admin_token(parts: &Parts, state: &AppState) -> Result<(), Problem> {
+    let expected = match &state.admin_token {
+        Some(token) => token,
+        None => {
+            return Err(Problem::new(
+                StatusCode::NOT_FOUND,
+                "ADMIN_DISABLED",
+                "Admin endpoints are disabled, start the server with --admin-token to enable them."
+                    .to_string(),
+            ));
+        }
+    };
+    let provided = parts
+        .headers
+        .get(header::AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "))
+        .unwrap_or_default();
+    if !secrets_match(expected.as_bytes(), provided.as_bytes()) {
+        return Err(Problem::new(
+            StatusCode::UNAUTHORIZED,
+            "INVALID_ADMIN_TOKEN",
+            "Admin endpoints require the admin token as a bearer token.".to_string(),
+        ));
+    }
+
+    return Ok(());
+}
+
+/// Guards the admin endpoints, requests must carry the `--admin-token` as a bearer token.
+pub struct Admin;
+
+impl FromRequestParts<AppState> for Admin {
+    type Rejection = Problem;
+
+    async fn from_request_parts(
+        parts: &mut Parts,
+        state: &AppState,
+    ) -> Result<Self, Self::Rejection> {
+        check_admin_token(parts, state)?;
+        return Ok(Admin);
+    }
+}
+
+/// Whoever is managing resources that belong to a tenant: the admin, who can manage every
+/// tenant's, or a tenant through one of its API keys.
+pub enum Owner {
+    Admin,
+    Tenant(Arc<Tenant>),
+}
+
+impl Owner {
+    /// Whether the owner can manage a resource belonging to `tenant`, `None` for resources made
+    /// without an API key, which only the admin can manage.
+    pub fn owns(&self, tenant: Option<&str>) -> bool {
+        return match self {
+            Owner::Admin => true,
+            Owner::Tenant(owner) => tenant == Some(owner.id.as_str()),
+        };
+    }
+}
+
+impl FromRequestParts<AppState> for Owner {
+    type Rejection = Problem;
+
+    async fn from_request_parts(
+        parts: &mut Parts,
+        state: &AppState,
+    ) -> Result<Self, Self::Rejection> {
+        if parts.headers.contains_key(header::AUTHORIZATION) {
+            check_admin_token(parts, state)?;
+            return Ok(Owner::Admin);
+        }
+        match parts.extensions.get::<Arc<Tenant>>() {
+            Some(tenant) => return Ok(Owner::Tenant(tenant.clone())),
+            None => {
+                return Err(Problem::new(
+                    StatusCode::UNAUTHORIZED,
+                    "OWNER_REQUIRED",
+                    "This endpoint requires an API key, or the admin token as a bearer token."
+                        .to_string(),
+                ));
+            }
+        }
+    }
+}
+
+#[derive(Serialize, Debug, ToSchema)]
+pub struct StoredTenant {
+    pub id: String,
+    pub policy: TenantPolicy,
+    pub created_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
+}
+
+impl Negotiable for StoredTenant {
+    fn into_text(self) -> String {
+        return format!(
+            "{} {}",
+            self.id,
+            serde_json::to_string(&self.policy).unwrap_or_default()
+        );
+    }
+}
+
+#[derive(Serialize, Debug, ToSchema)]
+pub struct TenantList {
+    pub tenants: Vec<StoredTenant>,
+}
+
+impl Negotiable for TenantList {
+    fn into_text(self) -> String {
+        return self
+            .tenants
+            .into_iter()
+            .map(|tenant| tenant.into_text())
+            .collect::<Vec<_>>()
+            .join("\n");
+    }
+}
+
+/// An API key, the key itself is only ever returned when it's created.
+#[derive(Serialize, Debug, ToSchema)]
+pub struct ApiKey {
+    pub id: String,
+    pub tenant: String,
+    pub created_at: DateTime<Utc>,
+    pub revoked_at: Option<DateTime<Utc>>,
+}
+
+#[derive(Serialize, Debug, ToSchema)]
+pub struct CreatedApiKey {
+    #[serde(flatten)]
+    pub api_key: ApiKey,
+    /// Send as the `X-API-Key` header.
+    pub key: String,
+}
+
+impl Negotiable for CreatedApiKey {
+    fn into_text(self) -> String {
+        return format!("{} {} {}", self.api_key.id, self.api_key.tenant, self.key);
+    }
+}
+
+#[derive(Serialize, Debug, ToSchema)]
+pub struct ApiKeyList {
+    pub keys: Vec<ApiKey>,
+}
+
+impl Negotiable for ApiKeyList {
+    fn into_text(self) -> String {
+        return self
+            .keys
+            .into_iter()
+            .map(|key| match key.revoked_at {
+                Some(_) => format!("{} {} revoked", key.id, key.tenant),
+                None => format!("{} {}", key.id, key.tenant),
+            })
+            .collect::<Vec<_>>()
+            .join("\n");
+    }
+}
+
+fn stored_tenant(row: &Row) -> Result<StoredTenant, rusqlite::Error> {
+    let policy: String = row.get(1)?;
+    return Ok(StoredTenant {
+        id: row.get(0)?,
+        policy: parse_policy(&policy)?,
+        created_at: timestamp(row.get(2)?).unwrap_or_default(),
+        updated_at: timestamp(row.get(3)?).unwrap_or_default(),
+    });
+}
+
+fn tenant_not_found(id: &str) -> Problem {
+    return Problem::new(
+        StatusCode::NOT_FOUND,
+        "TENANT_NOT_FOUND",
+        format!("No tenant with id {} exists.", id),
+    );
+}
+
+/// Lists the tenants and their policies.
+#[utoipa::path(
+    get,
+    tag = "admin",
+    path = "/v1/admin/tenants",
+    security(("admin_token" = [])),
+    responses(
+        (status = 200, description = "Tenants", body = TenantList),
+        (status = 401, description = "Missing or invalid admin token", content_type = "application/problem+json", body = Problem),
+    )
+)]
+pub async fn list_tenants(State(state): State<AppState>, format: Format, _: Admin) -> Response {
+    let result = state
+        .history
+        .with_connection(|connection| {
+            let mut statement = connection
+                .prepare("SELECT id, policy, created_at, updated_at FROM tenants ORDER BY id")?;
+            return statement
+                .query_map([], stored_tenant)?
+                .collect::<Result<Vec<_>, _>>();
+        })
+        .await;
+
+    match result {
+        Ok(tenants) => return format.respond(StatusCode::OK, TenantList { tenants }),
+        Err(problem) => return format.problem(problem),
+    }
+}
+
+/// Creates a tenant, or replaces the policy of an existing one. Its keys pick up the new policy
+/// straight away.
+#[utoipa::path(
+    put,
+    tag = "admin",
+    path = "/v1/admin/tenants/{id}",
+    params(("id" = String, Path, description = "Id of the tenant, letters, digits, `-` and `_`.")),
+    request_body = TenantPolicy,
+    security(("admin_token" = [])),
+    responses(
+        (status = 200, description = "Tenant was saved", body = StoredTenant),
+        (status = 400, description = "Invalid tenant id or policy", content_type = "application/problem+json", body = Problem),
+        (status = 401, description = "Missing or invalid admin token", content_type = "application/problem+json", body = Problem),
+    )
+)]
+pub async fn put_tenant(
+    State(state): State<AppState>,
+    format: Format,
+    _: Admin,
+    Path(id): Path<String>,
+    payload: Result<Json<TenantPolicy>, JsonRejection>,
+) -> Response {
+    let policy = match payload {
+        Ok(policy) => policy.0,
+        Err(e) => return format.problem(Problem::from_json_rejection(e)),
+    };
+    let valid_id = !id.is_empty()
+        && id.len() <= MAX_TENANT_ID_LENGTH
+        && id
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_');
+    if !valid_id {
+        return format.problem(
+            Problem::new(
+                StatusCode::BAD_REQUEST,
+                "INVALID_TENANT_ID",
+                format!(
+                    "Tenant ids must be between 1 and {} letters, digits, - or _.",
+                    MAX_TENANT_ID_LENGTH
+                ),
+            )
+            .with_field("id".to_string()),
+        );
+    }
+    if let Err(problem) = policy.validate() {
+        return format.problem(problem);
+    }
+
+    let serialized = serde_json::to_string(&policy).unwrap_or_default();
+    let result = state
+        .history
+        .with_connection(move |connection| {
+            let saved_at = now();
+            connection.execute(
+                "INSERT INTO tenants (id, policy, created_at, updated_at) VALUES (?1, ?2, ?3, ?3)
+                 ON CONFLICT (id) DO UPDATE SET policy = excluded.policy, updated_at = excluded.updated_at",
+                params![id, serialized, saved_at],
+            )?;
+            return connection.query_row(
+                "SELECT id, policy, created_at, updated_at FROM tenants WHERE id = ?1",
+                params![id],
+                stored_tenant,
+            );
+        })
+        .await;
+    let tenant = match result {
+        Ok(tenant) => tenant,
+        Err(problem) => return format.problem(problem),
+    };
+    if let Err(problem) = state.tenants.reload().await {
+        return format.problem(problem);
+    }
+
+    return format.respond(StatusCode::OK, tenant);
+}
+
+/// Issues a new API key for a tenant. The key is only returned this once, only its hash is stored.
+#[utoipa::path(
+    post,
+    tag = "admin",
+    path = "/v1/admin/tenants/{id}/keys",
+    params(("id" = String, Path, description = "Id of the tenant.")),
+    security(("admin_token" = [])),
+    responses(
+        (status = 201, description = "Key was created", body = CreatedApiKey),
+        (status = 401, description = "Missing or invalid admin token", content_type = "application/problem+json", body = Problem),
+        (status = 404, description = "Unknown tenant", content_type = "application/problem+json", body = Problem),
+    )
+)]
+pub async fn create_key(
+    State(state): State<AppState>,
+    format: Format,
+    _: Admin,
+    Path(tenant): Path<String>,
+) -> Response {
+    let key = format!("{}{}", KEY_PREFIX, random_hex(32));
+    let api_key = ApiKey {
+        id: random_hex(8),
+        tenant: tenant.clone(),
+        created_at: Utc::now(),
+        revoked_at: None,
+    };
+
+    let (id, key_hash) = (api_key.id.clone(), hash_key(&key));
+    let created_at = api_key
+        .created_at
+        .to_rfc3339_opts(SecondsFormat::Micros, true);
+    let result = state
+        .history
+        .with_connection(move |connection| {
+            let exists = connection
+                .query_row(
+                    "SELECT 1 FROM tenants WHERE id = ?1",
+                    params![tenant],
+                    |_| Ok(()),
+                )
+                .optional()?;
+            if exists.is_none() {
+                return Ok(Err(tenant_not_found(&tenant)));
+            }
+            connection.execute(
+                "INSERT INTO api_keys (id, tenant_id, key_hash, created_at) VALUES (?1, ?2, ?3, ?4)",
+                params![id, tenant, key_hash, created_at],
+            )?;
+            return Ok(Ok(()));
+        })
+        .await;
+    match result {
+        Ok(Ok(())) => {}
+        Ok(Err(problem)) | Err(problem) => return format.problem(problem),
+    }
+    if let Err(problem) = state.tenants.reload().await {
+        return format.problem(problem);
+    }
+
+    return format.respond(StatusCode::CREATED, CreatedApiKey { api_key, key });
+}
+
+/// Lists every API key issued, including revoked ones. Keys themselves are never included.
+#[utoipa::path(
+    get,
+    tag = "admin",
+    path = "/v1/admin/keys",
+    security(("admin_token" = [])),
+    responses(
+        (status = 200, description = "API keys, newest first", body = ApiKeyList),
+        (status = 401, description = "Missing or invalid admin token", content_type = "application/problem+json", body = Problem),
+    )
+)]
+pub async fn list_keys(State(state): State<AppState>, format: Format, _: Admin) -> Response {
+    let result = state
+        .history
+        .with_connection(|connection| {
+            let mut statement = connection.prepare(
+                "SELECT id, tenant_id, created_at, revoked_at FROM api_keys
+                 ORDER BY created_at DESC",
+            )?;
+            return statement
+                .query_map([], |row| {
+                    return Ok(ApiKey {
+                        id: row.get(0)?,
+                        tenant: row.get(1)?,
+                        created_at: timestamp(row.get(2)?).unwrap_or_default(),
+                        revoked_at: timestamp(row.get(3)?),
+                    });
+                })?
+                .collect::<Result<Vec<_>, _>>();
+        })
+        .await;
+
+    match result {
+        Ok(keys) => return format.respond(StatusCode::OK, ApiKeyList { keys }),
+        Err(problem) => return format.problem(problem),
+    }
+}
+
+/// Revokes an API key, requests made with it are rejected from then on.
+#[utoipa::path(
+    delete,
+    tag = "admin",
+    path = "/v1/admin/keys/{id}",
+    params(("id" = String, Path, description = "Id of the API key.")),
+    security(("admin_token" = [])),
+    responses(
+        (status = 204, description = "Key was revoked"),
+        (status = 401, description = "Missing or invalid admin token", content_type = "application/problem+json", body = Problem),
+        (status = 404, description = "Unknown API key", content_type = "application/problem+json", body = Problem),
+    )
+)]
+pub async fn revoke_key(
+    State(state): State<AppState>,
+    format: Format,
+    _: Admin,
+    Path(id): Path<String>,
+) -> Response {
+    let key_id = id.clone();
+    let result = state
+        .history
+        .with_connection(move |connection| {
+            let exists = connection
+                .query_row(
+                    "SELECT 1 FROM api_keys WHERE id = ?1",
+                    params![key_id],
+                    |_| Ok(()),
+                )
+                .optional()?;
+            connection.execute(
+                "UPDATE api_keys SET revoked_at = ?2 WHERE id = ?1 AND revoked_at IS NULL",
+                params![key_id, now()],
+            )?;
+            return Ok(exists.is_some());
+        })
+        .await;
+    match result {
+        Ok(true) => {}
+        Ok(false) => {
+            return format.problem(Problem::new(
+                StatusCode::NOT_FOUND,
+                "API_KEY_NOT_FOUND",
+                format!("No API key with id {} exists.", id),
+            ));
+        }
+        Err(problem) => return format.problem(problem),
+    }
+    if let Err(problem) = state.tenants.reload().await {
+        return format.problem(problem);
+    }
+
+    return StatusCode::NO_CONTENT.into_response();
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::http::Method;
+    use serde_json::json;
+
+    use super::*;
+    use crate::tests::{api_key, request, send, state, with_header};
+
+    #[test]
+    fn anonymous_policies_leave_rates_to_the_server() {
+        let policy =
+            TenantPolicy::parse_anonymous(r#"{"max_sides": 20, "features": ["rooms"]}"#).unwrap();
+        assert_eq!(policy.limits().sides.upper_bound, 20);
+        assert!(policy.allows(Feature::Rooms));
+        assert!(!policy.allows(Feature::History));
+
+        assert!(TenantPolicy::parse_anonymous(r#"{"rate_limit": 10}"#).is_err());
+        assert!(TenantPolicy::parse_anonymous(r#"{"max_sides": 0}"#).is_err());
+        assert!(TenantPolicy::parse_anonymous(r#"{"colour": "red"}"#).is_err());
+    }
+
+    #[tokio::test]
+    async fn requests_without_a_key_are_held_to_the_anonymous_policy() {
+        let state = state();
+        let anonymous = TenantPolicy::parse_anonymous(r#"{"max_sides": 20, "features": []}"#);
+        let state = AppState {
+            tenants: Tenants::load(state.history.clone(), anonymous.unwrap(), Vec::new()).unwrap(),
+            ..state
+        };
+        let roll = |expression: &str| {
+            return request(
+                Method::POST,
+                "/v1/rolls/notation",
+                Some(json!({ "expression": expression })),
+            );
+        };
+
+        let (status, _) = send(&state, roll("1d20")).await;
+        assert_eq!(status, StatusCode::OK);
+        let (status, problem) = send(&state, roll("1d100")).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert_eq!(problem["code"], "INVALID_DICE_SIDES");
+        let (status, problem) = send(&state, request(Method::GET, "/v1/rolls", None)).await;
+        assert_eq!(status, StatusCode::FORBIDDEN);
+        assert_eq!(problem["code"], "FEATURE_NOT_ALLOWED");
+
+        let key = api_key(&state, "acme", json!({})).await;
+        let (status, _) = send(&state, with_header(roll("1d100"), API_KEY_HEADER, &key)).await;
+        assert_eq!(status, StatusCode::OK);
+        let history = with_header(
+            request(Method::GET, "/v1/rolls", None),
+            API_KEY_HEADER,
+            &key,
+        );
+        let (status, _) = send(&state, history).await;
+        assert_eq!(status, StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn disabled_features_are_off_for_everyone() {
+        let state = state();
+        let state = AppState {
+            tenants: Tenants::load(
+                state.history.clone(),
+                TenantPolicy::default(),
+                vec![Feature::Fair],
+            )
+            .unwrap(),
+            ..state
+        };
+        let key = api_key(&state, "acme", json!({})).await;
+        let seeds = || request(Method::POST, "/v1/fair/seeds", None);
+
+        let (status, problem) = send(&state, seeds()).await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+        assert_eq!(problem["code"], "FEATURE_DISABLED");
+        let (status, problem) = send(&state, with_header(seeds(), API_KEY_HEADER, &key)).await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+        assert_eq!(problem["code"], "FEATURE_DISABLED");
+        let history = with_header(
+            request(Method::GET, "/v1/rolls", None),
+            API_KEY_HEADER,
+            &key,
+        );
+        assert_eq!(send(&state, history).await.0, StatusCode::OK);
+    }
+}
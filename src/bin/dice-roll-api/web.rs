@@ -0,0 +1,168 @@
+use std::time::Duration;
+
+use axum::{
+    http::{HeaderName, HeaderValue, Method, header},
+    response::{Html, IntoResponse, Response},
+};
+use tower_http::cors::{AllowOrigin, CorsLayer};
+
+use crate::{
+    campaigns::PARTICIPANT_TOKEN_HEADER,
+    fair::SEED_TOKEN_HEADER,
+    idempotency::{IDEMPOTENCY_KEY_HEADER, REPLAYED_HEADER},
+    logging::REQUEST_ID_HEADER,
+    tenants::API_KEY_HEADER,
+};
+
+const INDEX: &str = include_str!("web/index.html");
+/// How long browsers can cache the answer to a preflight request.
+const PREFLIGHT_MAX_AGE: Duration = Duration::from_secs(3600);
+
+/// Lets browser clients on `origins` call the API, `*` allows any origin. Cookies are never
+/// used, so credentials aren't allowed.
+pub fn cors(origins: &[String]) -> Result<CorsLayer, String> {
+    let allow_origin = if origins.iter().any(|origin| origin == "*") {
+        AllowOrigin::any()
+    } else {
+        let origins = origins
+            .iter()
+            .map(|origin| {
+                return HeaderValue::from_str(origin.trim_end_matches('/'))
+                    .map_err(|_| format!("{} is not a valid origin", origin));
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        AllowOrigin::list(origins)
+    };
+
+    return Ok(CorsLayer::new()
+        .allow_origin(allow_origin)
+        .allow_methods([Method::GET, Method::POST, Method::PUT, Method::DELETE])
+        .allow_headers([
+            header::ACCEPT,
+            header::AUTHORIZATION,
+            header::CONTENT_TYPE,
+            HeaderName::from_static(API_KEY_HEADER),
+            HeaderName::from_static(IDEMPOTENCY_KEY_HEADER),
+            HeaderName::from_static(PARTICIPANT_TOKEN_HEADER),
+            HeaderName::from_static(SEED_TOKEN_HEADER),
+            REQUEST_ID_HEADER,
+        ])
+        .expose_headers([
+            REQUEST_ID_HEADER,
+            header::RETRY_AFTER,
+            HeaderName::from_static(REPLAYED_HEADER),
+        ])
+        .max_age(PREFLIGHT_MAX_AGE));
+}
+
+/// Browser dice roller, a single page calling the API.
+pub async fn index() -> Response {
+    return Html(INDEX).into_response();
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::{
+        body::Body,
+        http::{Request, StatusCode},
+    };
+    use tower::ServiceExt;
+
+    use super::*;
+    use crate::{
+        app,
+        tests::{request, respond, state},
+    };
+
+    async fn preflight(origins: &[&str], origin: &str) -> Response {
+        let origins = origins
+            .iter()
+            .map(|origin| origin.to_string())
+            .collect::<Vec<_>>();
+        let request = Request::builder()
+            .method(Method::OPTIONS)
+            .uri("/v1/rolls/notation")
+            .header(header::ORIGIN, origin)
+            .header(header::ACCESS_CONTROL_REQUEST_METHOD, "POST")
+            .header(
+                header::ACCESS_CONTROL_REQUEST_HEADERS,
+                "content-type,x-api-key",
+            )
+            .body(Body::empty())
+            .unwrap();
+
+        return app(state(), Some(cors(&origins).unwrap()))
+            .oneshot(request)
+            .await
+            .unwrap();
+    }
+
+    #[tokio::test]
+    async fn allowed_origins_pass_preflight() {
+        let origins = ["https://vtt.example.com/", "https://wiki.example.com"];
+        let response = preflight(&origins, "https://vtt.example.com").await;
+        let headers = response.headers();
+        assert_eq!(
+            headers[header::ACCESS_CONTROL_ALLOW_ORIGIN],
+            "https://vtt.example.com"
+        );
+        assert_eq!(headers[header::ACCESS_CONTROL_MAX_AGE], "3600");
+        let allowed = headers[header::ACCESS_CONTROL_ALLOW_HEADERS]
+            .to_str()
+            .unwrap();
+        assert!(allowed.contains(API_KEY_HEADER), "{}", allowed);
+        assert!(
+            headers
+                .get(header::ACCESS_CONTROL_ALLOW_CREDENTIALS)
+                .is_none()
+        );
+
+        let response = preflight(&origins, "https://evil.example.com").await;
+        assert!(
+            response
+                .headers()
+                .get(header::ACCESS_CONTROL_ALLOW_ORIGIN)
+                .is_none()
+        );
+
+        let response = preflight(&["*"], "https://anywhere.example.com").await;
+        assert_eq!(response.headers()[header::ACCESS_CONTROL_ALLOW_ORIGIN], "*");
+    }
+
+    #[tokio::test]
+    async fn responses_expose_request_ids() {
+        let mut request = request(Method::GET, "/healthz", None);
+        request.headers_mut().insert(
+            header::ORIGIN,
+            HeaderValue::from_static("https://vtt.example.com"),
+        );
+        let cors = cors(&["https://vtt.example.com".to_string()]).unwrap();
+        let response = app(state(), Some(cors)).oneshot(request).await.unwrap();
+
+        assert_eq!(response.status(), StatusCode::OK);
+        let exposed = response.headers()[header::ACCESS_CONTROL_EXPOSE_HEADERS]
+            .to_str()
+            .unwrap();
+        assert!(exposed.contains("x-request-id"), "{}", exposed);
+        assert!(exposed.contains("retry-after"), "{}", exposed);
+    }
+
+    #[test]
+    fn rejects_invalid_origins() {
+        assert!(cors(&["https://vtt.example.com\n".to_string()]).is_err());
+    }
+
+    #[tokio::test]
+    async fn serves_the_dice_roller() {
+        let (status, headers, body) = respond(&state(), request(Method::GET, "/", None)).await;
+        assert_eq!(status, StatusCode::OK);
+        assert!(
+            headers[header::CONTENT_TYPE]
+                .to_str()
+                .unwrap()
+                .starts_with("text/html")
+        );
+        let page = String::from_utf8(body).unwrap();
+        assert!(page.contains("/v1/rolls/notation"));
+    }
+}
@@ -0,0 +1,1099 @@
+use std::{
+    net::{IpAddr, Ipv4Addr},
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use axum::{
+    Json,
+    extract::{Path, State, rejection::JsonRejection},
+    http::StatusCode,
+    response::{IntoResponse, Response},
+};
+use chrono::{DateTime, SecondsFormat, Utc};
+use dice_roll::RollResponse;
+use hmac::{Hmac, Mac};
+use rand::RngCore;
+use reqwest::{
+    Url,
+    dns::{Addrs, Name, Resolve, Resolving},
+    redirect,
+};
+use rusqlite::{Row, params};
+use serde::{Deserialize, Serialize};
+use sha2::Sha256;
+use tracing::Instrument;
+use utoipa::ToSchema;
+
+use crate::{
+    AppState,
+    history::History,
+    response::{Format, Negotiable, Problem},
+    tenants::Owner,
+};
+
+type HmacSha256 = Hmac<Sha256>;
+
+/// Deliveries are attempted this many times before landing in the dead-letter log.
+const MAX_ATTEMPTS: u32 = 5;
+const FIRST_RETRY_DELAY: Duration = Duration::from_secs(1);
+const DELIVERY_TIMEOUT: Duration = Duration::from_secs(10);
+const MAX_DEAD_LETTERS: u32 = 100;
+
+const SCHEMA: &str = "
+    CREATE TABLE IF NOT EXISTS webhooks (
+        id TEXT PRIMARY KEY,
+        url TEXT NOT NULL,
+        secret TEXT NOT NULL,
+        filter TEXT NOT NULL,
+        session TEXT,
+        campaign TEXT,
+        tenant TEXT,
+        created_at TEXT NOT NULL
+    );
+    CREATE TABLE IF NOT EXISTS webhook_dead_letters (
+        id INTEGER PRIMARY KEY AUTOINCREMENT,
+        webhook_id TEXT NOT NULL,
+        url TEXT NOT NULL,
+        payload TEXT NOT NULL,
+        error TEXT NOT NULL,
+        attempts INTEGER NOT NULL,
+        tenant TEXT,
+        failed_at TEXT NOT NULL
+    );
+";
+
+/// Which rolls are delivered to a webhook.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum WebhookFilter {
+    /// Every roll.
+    #[default]
+    All,
+    /// Rolls where a d20 came up a natural 20.
+    Crits,
+    /// Rolls where a d20 came up a natural 1.
+    Fumbles,
+}
+
+impl WebhookFilter {
+    pub fn parse(value: &str) -> Option<WebhookFilter> {
+        match value {
+            "all" => Some(WebhookFilter::All),
+            "crits" => Some(WebhookFilter::Crits),
+            "fumbles" => Some(WebhookFilter::Fumbles),
+            _ => None,
+        }
+    }
+
+    fn as_str(&self) -> &'static str {
+        match self {
+            WebhookFilter::All => "all",
+            WebhookFilter::Crits => "crits",
+            WebhookFilter::Fumbles => "fumbles",
+        }
+    }
+
+    fn matches(&self, result: &RollResponse) -> bool {
+        match self {
+            WebhookFilter::All => true,
+            WebhookFilter::Crits => result.is_critical(),
+            WebhookFilter::Fumbles => result.is_fumble(),
+        }
+    }
+}
+
+/// A URL rolls are POSTed to as they happen.
+#[derive(Serialize, Clone, Debug, ToSchema)]
+pub struct Webhook {
+    pub id: String,
+    #[schema(example = "https://wiki.example.com/hooks/dice-roll")]
+    pub url: String,
+    #[serde(skip)]
+    pub secret: String,
+    pub filter: WebhookFilter,
+    /// Only deliver rolls made in this session.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub session: Option<String>,
+    /// Only deliver rolls tagged with this campaign.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub campaign: Option<String>,
+    /// Tenant whose rolls are delivered, rolls made without an API key when there's none.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tenant: Option<String>,
+    /// Webhooks passed on the command line receive every tenant's rolls, can reach private
+    /// addresses and can't be deleted through the API.
+    pub configured: bool,
+}
+
+impl Webhook {
+    fn matches(&self, event: &RollEvent) -> bool {
+        if !self.configured && self.tenant != event.tenant {
+            return false;
+        }
+        if self.session.is_some() && self.session != event.session {
+            return false;
+        }
+        if self.campaign.is_some() && self.campaign != event.campaign {
+            return false;
+        }
+        return self.filter.matches(&event.result);
+    }
+}
+
+/// Body POSTed to webhooks for every matching roll.
+#[derive(Serialize, Clone, Debug, ToSchema)]
+pub struct RollEvent {
+    #[schema(example = "roll")]
+    pub event: &'static str,
+    /// Id of the roll in the history.
+    pub roll_id: i64,
+    pub timestamp: DateTime<Utc>,
+    pub session: Option<String>,
+    pub campaign: Option<String>,
+    /// Tenant of the API key the roll was made with.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tenant: Option<String>,
+    pub player: Option<String>,
+    pub expression: Option<String>,
+    pub result: RollResponse,
+}
+
+pub fn random_hex(bytes: usize) -> String {
+    let mut buffer = vec![0u8; bytes];
+    rand::rng().fill_bytes(&mut buffer);
+    return hex::encode(buffer);
+}
+
+/// `sha256=` followed by the hex HMAC-SHA256 of `{timestamp}.{body}` keyed with the secret.
+fn signature(secret: &str, timestamp: i64, body: &str) -> String {
+    let mut mac = match HmacSha256::new_from_slice(secret.as_bytes()) {
+        Ok(mac) => mac,
+        Err(_) => return String::new(),
+    };
+    mac.update(format!("{}.{}", timestamp, body).as_bytes());
+    return format!("sha256={}", hex::encode(mac.finalize().into_bytes()));
+}
+
+/// Whether an address is reachable from the public internet, webhooks registered through the
+/// API can't be pointed at the server's own network or the cloud metadata service.
+pub fn is_public(address: IpAddr) -> bool {
+    match address {
+        IpAddr::V4(address) => {
+            let [a, b, ..] = address.octets();
+            return !(address.is_private()
+                || address.is_loopback()
+                // Including 169.254.169.254, the metadata service of most clouds.
+                || address.is_link_local()
+                || address.is_unspecified()
+                || address.is_broadcast()
+                || address.is_documentation()
+                || address.is_multicast()
+                // This network, shared address space (RFC 6598), IETF protocol assignments,
+                // benchmarking and reserved ranges.
+                || a == 0
+                || (a == 100 && (64..128).contains(&b))
+                || (a == 192 && b == 0 && address.octets()[2] == 0)
+                || (a == 198 && (18..20).contains(&b))
+                || a >= 240);
+        }
+        IpAddr::V6(address) => {
+            if let Some(mapped) = address.to_ipv4_mapped() {
+                return is_public(IpAddr::V4(mapped));
+            }
+            let segments = address.segments();
+            // NAT64 addresses embed the IPv4 address they translate to.
+            if segments[..6] == [0x64, 0xff9b, 0, 0, 0, 0] {
+                let [.., high, low] = address.octets().map(u32::from);
+                let embedded = (u32::from(segments[6]) << 16) | (high << 8) | low;
+                return is_public(IpAddr::V4(Ipv4Addr::from(embedded)));
+            }
+            return !(address.is_loopback()
+                || address.is_unspecified()
+                || address.is_multicast()
+                // Unique local addresses, including fd00:ec2::254, the AWS metadata service.
+                || (segments[0] & 0xfe00) == 0xfc00
+                || (segments[0] & 0xffc0) == 0xfe80
+                || (segments[0] == 0x2001 && segments[1] == 0xdb8));
+        }
+    }
+}
+
+/// Resolves the hosts of webhooks registered through the API, leaving out any address that
+/// isn't public. Connections only go to the addresses it returns, so a host can't be pointed
+/// at a private address between registering a webhook and its deliveries.
+struct PublicResolver;
+
+impl Resolve for PublicResolver {
+    fn resolve(&self, name: Name) -> Resolving {
+        return Box::pin(async move {
+            let addresses = tokio::net::lookup_host((name.as_str(), 0))
+                .await?
+                .filter(|address| is_public(address.ip()))
+                .collect::<Vec<_>>();
+            if addresses.is_empty() {
+                return Err(
+                    format!("{} does not resolve to a public address", name.as_str()).into(),
+                );
+            }
+            let addresses: Addrs = Box::new(addresses.into_iter());
+            return Ok(addresses);
+        });
+    }
+}
+
+/// Checks a webhook URL is an absolute http or https URL whose host only resolves to public
+/// addresses.
+async fn check_target(url: &str) -> Result<(), Problem> {
+    let invalid = |detail: String| {
+        return Problem::new(StatusCode::BAD_REQUEST, "INVALID_WEBHOOK_URL", detail)
+            .with_field("url".to_string());
+    };
+    let parsed = match Url::parse(url) {
+        Ok(parsed) if parsed.scheme() == "http" || parsed.scheme() == "https" => parsed,
+        _ => {
+            return Err(invalid(format!(
+                "Webhook URL must be an absolute http or https URL, {} provided.",
+                url
+            )));
+        }
+    };
+    // IPv6 hosts are bracketed, e.g. `[::1]`.
+    let host = parsed
+        .host_str()
+        .unwrap_or_default()
+        .trim_start_matches('[')
+        .trim_end_matches(']');
+    let addresses = match host.parse::<IpAddr>() {
+        Ok(address) => vec![address],
+        Err(_) => {
+            match tokio::net::lookup_host((host, parsed.port_or_known_default().unwrap_or(0))).await
+            {
+                Ok(addresses) => addresses.map(|address| address.ip()).collect(),
+                Err(_) => Vec::new(),
+            }
+        }
+    };
+    if addresses.is_empty() {
+        return Err(invalid(format!(
+            "Webhook URL host could not be resolved, {} provided.",
+            url
+        )));
+    }
+    if !addresses.into_iter().all(is_public) {
+        return Err(invalid(format!(
+            "Webhook URL must point at a public address, {} provided.",
+            url
+        )));
+    }
+
+    return Ok(());
+}
+
+fn stored_webhook(row: &Row) -> Result<Webhook, rusqlite::Error> {
+    let filter: String = row.get(3)?;
+    return Ok(Webhook {
+        id: row.get(0)?,
+        url: row.get(1)?,
+        secret: row.get(2)?,
+        filter: WebhookFilter::parse(&filter).unwrap_or_default(),
+        session: row.get(4)?,
+        campaign: row.get(5)?,
+        tenant: row.get(6)?,
+        configured: false,
+    });
+}
+
+/// Registered webhooks, delivering roll events in the background.
+#[derive(Clone)]
+pub struct Webhooks {
+    hooks: Arc<Mutex<Vec<Webhook>>>,
+    /// Only connects to public addresses, for webhooks registered through the API.
+    client: reqwest::Client,
+    /// For the webhooks passed on the command line.
+    configured_client: reqwest::Client,
+    history: History,
+    first_retry_delay: Duration,
+}
+
+impl Webhooks {
+    /// Loads the webhooks registered through the API alongside the `configured` ones.
+    pub fn new(history: History, configured: Vec<Webhook>) -> Result<Webhooks, String> {
+        history.migrate(SCHEMA).map_err(|e| e.to_string())?;
+        let mut hooks = configured;
+        hooks.extend(
+            history
+                .with_connection_blocking(|connection| {
+                    let mut statement = connection.prepare(
+                        "SELECT id, url, secret, filter, session, campaign, tenant FROM webhooks ORDER BY created_at",
+                    )?;
+                    return statement
+                        .query_map([], stored_webhook)?
+                        .collect::<Result<Vec<_>, _>>();
+                })
+                .map_err(|e| e.to_string())?,
+        );
+        // Redirects are never followed, they could lead anywhere.
+        let client = reqwest::Client::builder()
+            .timeout(DELIVERY_TIMEOUT)
+            .redirect(redirect::Policy::none())
+            .no_proxy()
+            .dns_resolver(Arc::new(PublicResolver))
+            .build()
+            .map_err(|e| e.to_string())?;
+        let configured_client = reqwest::Client::builder()
+            .timeout(DELIVERY_TIMEOUT)
+            .redirect(redirect::Policy::none())
+            .build()
+            .map_err(|e| e.to_string())?;
+
+        return Ok(Webhooks {
+            hooks: Arc::new(Mutex::new(hooks)),
+            client,
+            configured_client,
+            history,
+            first_retry_delay: FIRST_RETRY_DELAY,
+        });
+    }
+
+    /// Queues the event for delivery to every webhook whose filter it matches.
+    pub fn notify(&self, event: RollEvent) {
+        let hooks = self
+            .hooks
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|hook| hook.matches(&event))
+            .cloned()
+            .collect::<Vec<_>>();
+        if hooks.is_empty() {
+            return;
+        }
+        let payload = match serde_json::to_string(&event) {
+            Ok(payload) => payload,
+            Err(_) => return,
+        };
+        for hook in hooks {
+            // Deliveries stay in the span of the request that made the roll.
+            tokio::spawn(
+                self.clone()
+                    .deliver(hook, payload.clone())
+                    .in_current_span(),
+            );
+        }
+    }
+
+    /// POSTs the payload, retrying with exponential backoff before giving up on it.
+    async fn deliver(self, hook: Webhook, payload: String) {
+        let delivery = random_hex(16);
+        let client = match hook.configured {
+            true => &self.configured_client,
+            false => &self.client,
+        };
+        let mut delay = self.first_retry_delay;
+        let mut error = String::new();
+        for attempt in 1..=MAX_ATTEMPTS {
+            let timestamp = Utc::now().timestamp();
+            let response = client
+                .post(&hook.url)
+                .header("content-type", "application/json")
+                .header("x-dice-roll-delivery", &delivery)
+                .header("x-dice-roll-timestamp", timestamp.to_string())
+                .header(
+                    "x-dice-roll-signature",
+                    signature(&hook.secret, timestamp, &payload),
+                )
+                .body(payload.clone())
+                .send()
+                .await;
+            match response {
+                Ok(response) if response.status().is_success() => return,
+                Ok(response) => error = format!("responded with {}", response.status()),
+                Err(e) => error = e.to_string(),
+            }
+            tracing::warn!(
+                webhook_id = hook.id,
+                attempt,
+                error,
+                "Webhook delivery attempt failed"
+            );
+            if attempt < MAX_ATTEMPTS {
+                tokio::time::sleep(delay).await;
+                delay *= 2;
+            }
+        }
+
+        tracing::error!(
+            webhook_id = hook.id,
+            url = hook.url,
+            attempts = MAX_ATTEMPTS,
+            error,
+            "Webhook delivery failed, moved to the dead-letter log"
+        );
+        let failed_at = Utc::now().to_rfc3339_opts(SecondsFormat::Micros, true);
+        let _ = self
+            .history
+            .with_connection(move |connection| {
+                return connection.execute(
+                    "INSERT INTO webhook_dead_letters (webhook_id, url, payload, error, attempts, tenant, failed_at)
+                     VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)",
+                    params![hook.id, hook.url, payload, error, MAX_ATTEMPTS, hook.tenant, failed_at],
+                );
+            })
+            .await;
+    }
+}
+
+#[derive(Deserialize, Debug, ToSchema)]
+#[serde(deny_unknown_fields)]
+pub struct WebhookRequest {
+    #[schema(example = "https://wiki.example.com/hooks/dice-roll")]
+    pub url: String,
+    #[serde(default)]
+    pub filter: WebhookFilter,
+    #[serde(default)]
+    pub session: Option<String>,
+    #[serde(default)]
+    pub campaign: Option<String>,
+    /// Secret deliveries are signed with, generated when not provided.
+    #[serde(default)]
+    pub secret: Option<String>,
+    /// Tenant whose rolls are delivered, only the admin can register webhooks for another
+    /// tenant. Defaults to the tenant of the API key the webhook is registered with.
+    #[serde(default)]
+    pub tenant: Option<String>,
+}
+
+/// A newly registered webhook, the only time its secret is returned.
+#[derive(Serialize, Debug, ToSchema)]
+pub struct CreatedWebhook {
+    #[serde(flatten)]
+    pub webhook: Webhook,
+    pub secret: String,
+}
+
+impl Negotiable for CreatedWebhook {
+    fn into_text(self) -> String {
+        return format!("{} {} {}", self.webhook.id, self.webhook.url, self.secret);
+    }
+}
+
+#[derive(Serialize, Debug, ToSchema)]
+pub struct WebhookList {
+    pub webhooks: Vec<Webhook>,
+}
+
+impl Negotiable for WebhookList {
+    fn into_text(self) -> String {
+        return self
+            .webhooks
+            .into_iter()
+            .map(|hook| format!("{} {} {}", hook.id, hook.url, hook.filter.as_str()))
+            .collect::<Vec<_>>()
+            .join("\n");
+    }
+}
+
+/// A roll event that could not be delivered.
+#[derive(Serialize, Debug, ToSchema)]
+pub struct DeadLetter {
+    pub id: i64,
+    pub webhook_id: String,
+    pub url: String,
+    #[schema(value_type = RollEvent)]
+    pub payload: serde_json::Value,
+    pub error: String,
+    pub attempts: u32,
+    pub failed_at: DateTime<Utc>,
+}
+
+#[derive(Serialize, Debug, ToSchema)]
+pub struct DeadLetterList {
+    pub dead_letters: Vec<DeadLetter>,
+}
+
+impl Negotiable for DeadLetterList {
+    fn into_text(self) -> String {
+        return self
+            .dead_letters
+            .into_iter()
+            .map(|letter| format!("#{} {} {}", letter.id, letter.url, letter.error))
+            .collect::<Vec<_>>()
+            .join("\n");
+    }
+}
+
+fn webhook_not_found(id: &str) -> Problem {
+    return Problem::new(
+        StatusCode::NOT_FOUND,
+        "WEBHOOK_NOT_FOUND",
+        format!("No webhook with id {} exists.", id),
+    );
+}
+
+/// Registers a webhook that is POSTed every roll of its tenant matching its filter. Requires the
+/// tenant's API key, or the admin token as a bearer token.
+///
+/// Deliveries carry `X-Dice-Roll-Timestamp` and `X-Dice-Roll-Signature` headers, the latter
+/// being `sha256=` followed by the hex HMAC-SHA256 of `{timestamp}.{body}` keyed with the secret.
+#[utoipa::path(
+    post,
+    tag = "webhooks",
+    path = "/v1/webhooks",
+    request_body = WebhookRequest,
+    security(("api_key" = []), ("admin_token" = [])),
+    responses(
+        (status = 201, description = "Webhook was registered", body = CreatedWebhook),
+        (status = 400, description = "Invalid webhook", content_type = "application/problem+json", body = Problem),
+        (status = 401, description = "Missing API key or admin token", content_type = "application/problem+json", body = Problem),
+        (status = 403, description = "Webhook is for another tenant", content_type = "application/problem+json", body = Problem),
+    )
+)]
+pub async fn create_webhook(
+    State(state): State<AppState>,
+    format: Format,
+    owner: Owner,
+    payload: Result<Json<WebhookRequest>, JsonRejection>,
+) -> Response {
+    let request = match payload {
+        Ok(request) => request.0,
+        Err(e) => return format.problem(Problem::from_json_rejection(e)),
+    };
+    let tenant = match (&owner, request.tenant) {
+        (Owner::Admin, tenant) => tenant,
+        (Owner::Tenant(owner), None) => Some(owner.id.clone()),
+        (Owner::Tenant(owner), Some(tenant)) if tenant == owner.id => Some(tenant),
+        (Owner::Tenant(owner), Some(_)) => {
+            return format.problem(
+                Problem::new(
+                    StatusCode::FORBIDDEN,
+                    "WEBHOOK_TENANT_FORBIDDEN",
+                    format!(
+                        "Tenant {} can only register webhooks for its own rolls.",
+                        owner.id
+                    ),
+                )
+                .with_field("tenant".to_string()),
+            );
+        }
+    };
+    if let Err(problem) = check_target(&request.url).await {
+        return format.problem(problem);
+    }
+    let webhook = Webhook {
+        id: random_hex(8),
+        url: request.url,
+        secret: request.secret.unwrap_or_else(|| random_hex(32)),
+        filter: request.filter,
+        session: request.session,
+        campaign: request.campaign,
+        tenant,
+        configured: false,
+    };
+
+    let stored = webhook.clone();
+    let created_at = Utc::now().to_rfc3339_opts(SecondsFormat::Micros, true);
+    let result = state
+        .history
+        .with_connection(move |connection| {
+            return connection.execute(
+                "INSERT INTO webhooks (id, url, secret, filter, session, campaign, tenant, created_at)
+                 VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8)",
+                params![
+                    stored.id,
+                    stored.url,
+                    stored.secret,
+                    stored.filter.as_str(),
+                    stored.session,
+                    stored.campaign,
+                    stored.tenant,
+                    created_at,
+                ],
+            );
+        })
+        .await;
+    if let Err(problem) = result {
+        return format.problem(problem);
+    }
+    state.webhooks.hooks.lock().unwrap().push(webhook.clone());
+
+    let secret = webhook.secret.clone();
+    return format.respond(StatusCode::CREATED, CreatedWebhook { webhook, secret });
+}
+
+/// Lists the tenant's webhooks, or every webhook for the admin. Secrets are never included.
+#[utoipa::path(
+    get,
+    tag = "webhooks",
+    path = "/v1/webhooks",
+    security(("api_key" = []), ("admin_token" = [])),
+    responses(
+        (status = 200, description = "Registered webhooks", body = WebhookList),
+        (status = 401, description = "Missing API key or admin token", content_type = "application/problem+json", body = Problem),
+    )
+)]
+pub async fn list_webhooks(
+    State(state): State<AppState>,
+    format: Format,
+    owner: Owner,
+) -> Response {
+    let webhooks = state
+        .webhooks
+        .hooks
+        .lock()
+        .unwrap()
+        .iter()
+        .filter(|hook| match owner {
+            Owner::Admin => true,
+            Owner::Tenant(_) => !hook.configured && owner.owns(hook.tenant.as_deref()),
+        })
+        .cloned()
+        .collect();
+    return format.respond(StatusCode::OK, WebhookList { webhooks });
+}
+
+/// Removes one of the tenant's webhooks, or any webhook registered through the API for the admin.
+#[utoipa::path(
+    delete,
+    tag = "webhooks",
+    path = "/v1/webhooks/{id}",
+    params(("id" = String, Path, description = "Id of the webhook.")),
+    security(("api_key" = []), ("admin_token" = [])),
+    responses(
+        (status = 204, description = "Webhook was removed"),
+        (status = 401, description = "Missing API key or admin token", content_type = "application/problem+json", body = Problem),
+        (status = 404, description = "Unknown webhook", content_type = "application/problem+json", body = Problem),
+        (status = 409, description = "Webhook was passed on the command line", content_type = "application/problem+json", body = Problem),
+    )
+)]
+pub async fn delete_webhook(
+    State(state): State<AppState>,
+    format: Format,
+    owner: Owner,
+    Path(id): Path<String>,
+) -> Response {
+    let configured = state
+        .webhooks
+        .hooks
+        .lock()
+        .unwrap()
+        .iter()
+        .find(|hook| hook.id == id && (hook.configured || owner.owns(hook.tenant.as_deref())))
+        .map(|hook| hook.configured);
+    match (configured, &owner) {
+        (Some(false), _) => {}
+        (Some(true), Owner::Admin) => {
+            return format.problem(Problem::new(
+                StatusCode::CONFLICT,
+                "WEBHOOK_CONFIGURED",
+                format!(
+                    "Webhook {} was passed on the command line and can't be removed.",
+                    id
+                ),
+            ));
+        }
+        (Some(true), Owner::Tenant(_)) | (None, _) => {
+            return format.problem(webhook_not_found(&id));
+        }
+    }
+
+    let webhook_id = id.clone();
+    let result = state
+        .history
+        .with_connection(move |connection| {
+            return connection.execute("DELETE FROM webhooks WHERE id = ?1", params![webhook_id]);
+        })
+        .await;
+    if let Err(problem) = result {
+        return format.problem(problem);
+    }
+    state
+        .webhooks
+        .hooks
+        .lock()
+        .unwrap()
+        .retain(|hook| hook.id != id);
+
+    return StatusCode::NO_CONTENT.into_response();
+}
+
+/// Lists the most recent roll events that could not be delivered to the tenant's webhooks, or
+/// to any webhook for the admin.
+#[utoipa::path(
+    get,
+    tag = "webhooks",
+    path = "/v1/webhooks/dead-letters",
+    security(("api_key" = []), ("admin_token" = [])),
+    responses(
+        (status = 200, description = "Undelivered roll events, newest first", body = DeadLetterList),
+        (status = 401, description = "Missing API key or admin token", content_type = "application/problem+json", body = Problem),
+    )
+)]
+pub async fn dead_letters(State(state): State<AppState>, format: Format, owner: Owner) -> Response {
+    let tenant = match owner {
+        Owner::Admin => None,
+        Owner::Tenant(tenant) => Some(tenant.id.clone()),
+    };
+    let result = state
+        .history
+        .with_connection(move |connection| {
+            let mut statement = connection.prepare(
+                "SELECT id, webhook_id, url, payload, error, attempts, failed_at
+                 FROM webhook_dead_letters WHERE ?1 IS NULL OR tenant = ?1
+                 ORDER BY id DESC LIMIT ?2",
+            )?;
+            return statement
+                .query_map(params![tenant, MAX_DEAD_LETTERS], |row| {
+                    let payload: String = row.get(3)?;
+                    let failed_at: String = row.get(6)?;
+                    return Ok(DeadLetter {
+                        id: row.get(0)?,
+                        webhook_id: row.get(1)?,
+                        url: row.get(2)?,
+                        payload: serde_json::from_str(&payload).unwrap_or_default(),
+                        error: row.get(4)?,
+                        attempts: row.get(5)?,
+                        failed_at: DateTime::parse_from_rfc3339(&failed_at)
+                            .map(|failed_at| failed_at.with_timezone(&Utc))
+                            .unwrap_or_default(),
+                    });
+                })?
+                .collect::<Result<Vec<_>, _>>();
+        })
+        .await;
+
+    match result {
+        Ok(dead_letters) => return format.respond(StatusCode::OK, DeadLetterList { dead_letters }),
+        Err(problem) => return format.problem(problem),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::{Router, body::Bytes, http::HeaderMap, http::Method, routing::post};
+    use serde_json::json;
+
+    use super::*;
+    use crate::tests::{ADMIN_TOKEN, api_key, request, send, state, with_header};
+
+    type Received = Arc<Mutex<Vec<(String, HeaderMap, String)>>>;
+
+    /// Serves `/ok`, answering 204, and `/fail`, answering 500, on a local port, recording every
+    /// request it receives.
+    async fn stand_in() -> (String, Received) {
+        let received = Received::default();
+        let record = |status: StatusCode, path: &'static str, received: Received| {
+            return move |headers: HeaderMap, body: Bytes| async move {
+                received.lock().unwrap().push((
+                    path.to_string(),
+                    headers,
+                    String::from_utf8(body.to_vec()).unwrap(),
+                ));
+                return status;
+            };
+        };
+        let app = Router::new()
+            .route(
+                "/ok",
+                post(record(StatusCode::NO_CONTENT, "/ok", received.clone())),
+            )
+            .route(
+                "/fail",
+                post(record(
+                    StatusCode::INTERNAL_SERVER_ERROR,
+                    "/fail",
+                    received.clone(),
+                )),
+            );
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+        tokio::spawn(async move { axum::serve(listener, app).await });
+
+        return (format!("http://{}", address), received);
+    }
+
+    fn webhook(id: &str, url: String, tenant: Option<&str>, configured: bool) -> Webhook {
+        return Webhook {
+            id: id.to_string(),
+            url,
+            secret: format!("{}-secret", id),
+            filter: WebhookFilter::All,
+            session: None,
+            campaign: None,
+            tenant: tenant.map(str::to_string),
+            configured,
+        };
+    }
+
+    fn event(tenant: Option<&str>) -> RollEvent {
+        return RollEvent {
+            event: "roll",
+            roll_id: 1,
+            timestamp: Utc::now(),
+            session: None,
+            campaign: None,
+            tenant: tenant.map(str::to_string),
+            player: None,
+            expression: Some("1d20".to_string()),
+            result: dice_roll::parser::parse("1d20".to_string())
+                .unwrap()
+                .roll_dice_with(|sides| sides)
+                .unwrap(),
+        };
+    }
+
+    #[tokio::test]
+    async fn delivers_signed_events_and_dead_letters_failures() {
+        let (url, received) = stand_in().await;
+        let history = History::open(":memory:").unwrap();
+        let webhooks = Webhooks {
+            first_retry_delay: Duration::from_millis(1),
+            ..Webhooks::new(
+                history.clone(),
+                vec![
+                    webhook("ok", format!("{}/ok", url), None, true),
+                    webhook("fail", format!("{}/fail", url), None, true),
+                ],
+            )
+            .unwrap()
+        };
+
+        webhooks.notify(event(Some("acme")));
+        let mut dead_letters = Vec::new();
+        for _ in 0..500 {
+            dead_letters = history
+                .with_connection(|connection| {
+                    let mut statement = connection
+                        .prepare("SELECT webhook_id, error, attempts FROM webhook_dead_letters")?;
+                    return statement
+                        .query_map([], |row| {
+                            return Ok((
+                                row.get::<_, String>(0)?,
+                                row.get::<_, String>(1)?,
+                                row.get::<_, u32>(2)?,
+                            ));
+                        })?
+                        .collect::<Result<Vec<_>, _>>();
+                })
+                .await
+                .unwrap();
+            if !dead_letters.is_empty() {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+
+        assert_eq!(
+            dead_letters,
+            vec![(
+                "fail".to_string(),
+                "responded with 500 Internal Server Error".to_string(),
+                MAX_ATTEMPTS
+            )]
+        );
+        let received = received.lock().unwrap();
+        let attempts = |path: &str| received.iter().filter(|(p, ..)| p == path).count();
+        assert_eq!(attempts("/ok"), 1);
+        assert_eq!(attempts("/fail"), MAX_ATTEMPTS as usize);
+        for (path, headers, body) in received.iter() {
+            let secret = format!("{}-secret", path.trim_start_matches('/'));
+            let timestamp = headers["x-dice-roll-timestamp"]
+                .to_str()
+                .unwrap()
+                .parse()
+                .unwrap();
+            assert_eq!(
+                headers["x-dice-roll-signature"].to_str().unwrap(),
+                signature(&secret, timestamp, body)
+            );
+            let payload: serde_json::Value = serde_json::from_str(body).unwrap();
+            assert_eq!(payload["tenant"], "acme");
+            assert_eq!(payload["result"]["total"], 20);
+        }
+    }
+
+    #[test]
+    fn webhooks_only_match_their_tenants_rolls() {
+        let url = "https://example.com".to_string();
+        let tenanted = webhook("tenanted", url.clone(), Some("acme"), false);
+        let anonymous = webhook("anonymous", url.clone(), None, false);
+        let configured = webhook("configured", url, None, true);
+
+        assert!(tenanted.matches(&event(Some("acme"))));
+        assert!(!tenanted.matches(&event(Some("globex"))));
+        assert!(!tenanted.matches(&event(None)));
+        assert!(anonymous.matches(&event(None)));
+        assert!(!anonymous.matches(&event(Some("acme"))));
+        assert!(configured.matches(&event(Some("acme"))));
+        assert!(configured.matches(&event(None)));
+    }
+
+    #[test]
+    fn private_addresses_are_not_public() {
+        let private = [
+            "127.0.0.1",
+            "10.1.2.3",
+            "172.16.0.1",
+            "192.168.1.1",
+            "169.254.169.254",
+            "100.100.100.200",
+            "0.0.0.0",
+            "::1",
+            "::",
+            "fe80::1",
+            "fd00:ec2::254",
+            "::ffff:127.0.0.1",
+            "64:ff9b::a9fe:a9fe",
+        ];
+        for address in private {
+            assert!(!is_public(address.parse().unwrap()), "{}", address);
+        }
+        for address in ["93.184.215.14", "2606:4700:4700::1111"] {
+            assert!(is_public(address.parse().unwrap()), "{}", address);
+        }
+    }
+
+    #[tokio::test]
+    async fn rejects_private_targets() {
+        let targets = [
+            "http://127.0.0.1:3000/",
+            "http://169.254.169.254/latest/meta-data/",
+            "http://[::1]/",
+            "http://0x7f.1/",
+            "http://localhost/",
+            "ftp://example.com/",
+        ];
+        for target in targets {
+            let problem = check_target(target).await.unwrap_err();
+            assert_eq!(problem.code, "INVALID_WEBHOOK_URL", "{}", target);
+        }
+    }
+
+    #[tokio::test]
+    async fn webhooks_are_managed_by_their_tenant_or_the_admin() {
+        let state = state();
+        let acme = api_key(&state, "acme", json!({})).await;
+        let globex = api_key(&state, "globex", json!({})).await;
+        state.webhooks.hooks.lock().unwrap().extend([
+            webhook(
+                "acme-hook",
+                "https://acme.example".to_string(),
+                Some("acme"),
+                false,
+            ),
+            webhook(
+                "globex-hook",
+                "https://globex.example".to_string(),
+                Some("globex"),
+                false,
+            ),
+            webhook(
+                "configured-1",
+                "https://ops.example".to_string(),
+                None,
+                true,
+            ),
+        ]);
+        let admin = format!("Bearer {}", ADMIN_TOKEN);
+        let ids = |body: serde_json::Value| {
+            return body["webhooks"]
+                .as_array()
+                .unwrap()
+                .iter()
+                .map(|hook| hook["id"].as_str().unwrap().to_string())
+                .collect::<Vec<_>>();
+        };
+
+        for (method, uri) in [
+            (Method::GET, "/v1/webhooks"),
+            (Method::GET, "/v1/webhooks/dead-letters"),
+            (Method::DELETE, "/v1/webhooks/acme-hook"),
+        ] {
+            let (status, body) = send(&state, request(method, uri, None)).await;
+            assert_eq!(status, StatusCode::UNAUTHORIZED, "{}", uri);
+            assert_eq!(body["code"], "OWNER_REQUIRED");
+        }
+        let (status, _) = send(
+            &state,
+            request(
+                Method::POST,
+                "/v1/webhooks",
+                Some(json!({"url": "https://example.com/hook"})),
+            ),
+        )
+        .await;
+        assert_eq!(status, StatusCode::UNAUTHORIZED);
+
+        let (_, body) = send(
+            &state,
+            with_header(
+                request(Method::GET, "/v1/webhooks", None),
+                "x-api-key",
+                &acme,
+            ),
+        )
+        .await;
+        assert_eq!(ids(body), vec!["acme-hook"]);
+        let (_, body) = send(
+            &state,
+            with_header(
+                request(Method::GET, "/v1/webhooks", None),
+                "authorization",
+                &admin,
+            ),
+        )
+        .await;
+        assert_eq!(ids(body), vec!["acme-hook", "globex-hook", "configured-1"]);
+
+        let (status, body) = send(
+            &state,
+            with_header(
+                request(
+                    Method::POST,
+                    "/v1/webhooks",
+                    Some(json!({"url": "https://example.com/hook", "tenant": "globex"})),
+                ),
+                "x-api-key",
+                &acme,
+            ),
+        )
+        .await;
+        assert_eq!(status, StatusCode::FORBIDDEN);
+        assert_eq!(body["code"], "WEBHOOK_TENANT_FORBIDDEN");
+        let (status, body) = send(
+            &state,
+            with_header(
+                request(
+                    Method::POST,
+                    "/v1/webhooks",
+                    Some(json!({"url": "http://169.254.169.254/latest/meta-data/"})),
+                ),
+                "x-api-key",
+                &acme,
+            ),
+        )
+        .await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert_eq!(body["code"], "INVALID_WEBHOOK_URL");
+
+        let delete = |id: &str, key: &str| {
+            return with_header(
+                request(Method::DELETE, &format!("/v1/webhooks/{}", id), None),
+                "x-api-key",
+                key,
+            );
+        };
+        assert_eq!(
+            send(&state, delete("acme-hook", &globex)).await.0,
+            StatusCode::NOT_FOUND
+        );
+        assert_eq!(
+            send(&state, delete("configured-1", &globex)).await.0,
+            StatusCode::NOT_FOUND
+        );
+        assert_eq!(
+            send(&state, delete("acme-hook", &acme)).await.0,
+            StatusCode::NO_CONTENT
+        );
+    }
+}
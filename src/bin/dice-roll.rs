@@ -1,44 +1,146 @@
 use std::fs;
 use std::io::{self, Read};
+use std::process::ExitCode;
 
 use clap::ArgAction;
-use dice_roll::parser;
+use dice_roll::{RollResponse, fair, parser, receipt::Receipt};
+
+struct VerifyContext {
+    server_seed: String,
+    client_seed: String,
+    nonce: u64,
+    commitment: Option<String>,
+    result: String,
+}
 
 struct CommandContext {
     as_json: bool,
     input: String,
+    verify: Option<VerifyContext>,
+    receipt_public_key: Option<String>,
 }
 
 enum InitClapErrors {
-    FailedToOpenFileError,
-    FailedToReadFromStdin,
-    FailedToReadFromFile,
+    OpenFile,
+    ReadStdin,
+    ReadFile,
+}
+
+fn input_args() -> [clap::Arg; 2] {
+    return [
+        clap::Arg::new("as_json")
+            .long("as-json")
+            .action(ArgAction::SetTrue)
+            .help("Changes output to JSON."),
+        clap::Arg::new("file")
+            .default_value("-")
+            .action(ArgAction::Set)
+            .help("Reads input from the provided file or STDIN if no value is provided"),
+    ];
 }
 
 fn init_clap() -> Result<CommandContext, InitClapErrors> {
-    let matches = clap::Command::new("dice-roll")
+    let root_matches = clap::Command::new("dice-roll")
         .about("Simulates dice rolls")
-        .arg(
-            clap::Arg::new("as_json")
-                .long("as-json")
-                .action(ArgAction::SetTrue)
-                .help("Changes output to JSON."),
+        .args(input_args())
+        .subcommand(
+            clap::Command::new("verify")
+                .about("Reproduces a provably fair roll from its revealed seeds")
+                .args(input_args())
+                .arg(
+                    clap::Arg::new("server_seed")
+                        .long("server-seed")
+                        .required(true)
+                        .action(ArgAction::Set)
+                        .help("Revealed server seed, encoded as hex."),
+                )
+                .arg(
+                    clap::Arg::new("client_seed")
+                        .long("client-seed")
+                        .required(true)
+                        .action(ArgAction::Set)
+                        .help("Client seed the roll was made with."),
+                )
+                .arg(
+                    clap::Arg::new("nonce")
+                        .long("nonce")
+                        .required(true)
+                        .value_parser(clap::value_parser!(u64))
+                        .action(ArgAction::Set)
+                        .help("Nonce the roll was made with."),
+                )
+                .arg(
+                    clap::Arg::new("commitment")
+                        .long("commitment")
+                        .action(ArgAction::Set)
+                        .help("Commitment published before the roll, checked against the server seed."),
+                )
+                .arg(
+                    clap::Arg::new("result")
+                        .long("result")
+                        .required(true)
+                        .action(ArgAction::Set)
+                        .help("Result the server returned for the roll, as JSON, checked against the reproduced roll."),
+                ),
         )
-        .arg(
-            clap::Arg::new("file")
-                .default_value("-")
-                .action(ArgAction::Set)
-                .help("Reads input from the provided file or STDIN if no value is provided"),
+        .subcommand(
+            clap::Command::new("verify-receipt")
+                .about("Checks that a signed roll receipt was issued by a server and hasn't been altered")
+                .args(input_args())
+                .arg(
+                    clap::Arg::new("public_key")
+                        .long("public-key")
+                        .required(true)
+                        .action(ArgAction::Set)
+                        .help("Public key of the server that issued the receipt, encoded as hex."),
+                ),
         )
         .get_matches();
 
-    let filename = matches.get_one::<String>("file").unwrap();
+    if let Some(receipt_matches) = root_matches.subcommand_matches("verify-receipt") {
+        return Ok(CommandContext {
+            as_json: *receipt_matches.get_one::<bool>("as_json").unwrap(),
+            input: read_input(receipt_matches.get_one::<String>("file").unwrap())?,
+            verify: None,
+            receipt_public_key: receipt_matches.get_one::<String>("public_key").cloned(),
+        });
+    }
+
+    let (matches, verify) = match root_matches.subcommand_matches("verify") {
+        Some(verify_matches) => (
+            verify_matches,
+            Some(VerifyContext {
+                server_seed: verify_matches
+                    .get_one::<String>("server_seed")
+                    .unwrap()
+                    .clone(),
+                client_seed: verify_matches
+                    .get_one::<String>("client_seed")
+                    .unwrap()
+                    .clone(),
+                nonce: *verify_matches.get_one::<u64>("nonce").unwrap(),
+                commitment: verify_matches.get_one::<String>("commitment").cloned(),
+                result: verify_matches.get_one::<String>("result").unwrap().clone(),
+            }),
+        ),
+        None => (&root_matches, None),
+    };
+
+    return Ok(CommandContext {
+        as_json: *matches.get_one::<bool>("as_json").unwrap(),
+        input: read_input(matches.get_one::<String>("file").unwrap())?,
+        verify,
+        receipt_public_key: None,
+    });
+}
+
+fn read_input(filename: &str) -> Result<String, InitClapErrors> {
     let mut input_reader: Box<dyn Read> = match filename {
         _ if filename == "-" => Box::new(io::stdin()),
         _ => match fs::File::open(filename) {
             Ok(file) => Box::new(file),
             Err(_) => {
-                return Err(InitClapErrors::FailedToOpenFileError);
+                return Err(InitClapErrors::OpenFile);
             }
         },
     };
@@ -48,49 +150,134 @@ fn init_clap() -> Result<CommandContext, InitClapErrors> {
         Ok(_) => {}
         Err(_) => {
             return Err(match filename {
-                _ if filename == "-" => InitClapErrors::FailedToReadFromStdin,
-                _ => InitClapErrors::FailedToReadFromFile,
+                _ if filename == "-" => InitClapErrors::ReadStdin,
+                _ => InitClapErrors::ReadFile,
             });
         }
     };
 
-    return Ok(CommandContext {
-        as_json: *matches.get_one::<bool>("as_json").unwrap(),
-        input: input.trim().to_string(),
-    });
+    return Ok(input.trim().to_string());
+}
+
+/// Checks a receipt's signature against the public key of the server that issued it.
+fn verify_receipt(input: &str, public_key: &str, as_json: bool) -> ExitCode {
+    let receipt = match Receipt::from_json(input) {
+        Ok(receipt) => receipt,
+        Err(e) => {
+            eprintln!("{}", e);
+            return ExitCode::FAILURE;
+        }
+    };
+    if let Err(e) = receipt.verify(public_key) {
+        eprintln!("{}", e);
+        return ExitCode::FAILURE;
+    }
+
+    match as_json {
+        true => match serde_json::to_string_pretty(&receipt) {
+            Ok(serialized) => println!("{}", serialized),
+            Err(_) => {
+                eprintln!("Failed to serialize Receipt into JSON.");
+                return ExitCode::FAILURE;
+            }
+        },
+        false => {
+            println!("Receipt signature is valid.");
+            println!("{} rolled at {}", receipt.expression, receipt.timestamp);
+            println!("{}", receipt.result);
+        }
+    }
+
+    return ExitCode::SUCCESS;
 }
 
-fn main() {
+/// Reproduces a provably fair roll from its seeds and checks it against the result the server
+/// claimed, and the server seed against its commitment when one is provided.
+fn verify(input: String, context: &VerifyContext, as_json: bool) -> ExitCode {
+    let claimed: RollResponse = match serde_json::from_str(&context.result) {
+        Ok(claimed) => claimed,
+        Err(_) => {
+            eprintln!("Claimed result is not valid roll result JSON.");
+            return ExitCode::FAILURE;
+        }
+    };
+    let result = match fair::verify(
+        input,
+        &context.server_seed,
+        &context.client_seed,
+        context.nonce,
+        context.commitment.as_deref(),
+        &claimed,
+    ) {
+        Ok(result) => result,
+        Err(e) => {
+            eprintln!("{}", e);
+            return ExitCode::FAILURE;
+        }
+    };
+
+    match as_json {
+        true => match serde_json::to_string_pretty(&result.to_json()) {
+            Ok(serialized) => println!("{}", serialized),
+            Err(_) => {
+                eprintln!("Failed to serialize RollResponse into JSON.");
+                return ExitCode::FAILURE;
+            }
+        },
+        false => {
+            if context.commitment.is_some() {
+                println!("Server seed matches the commitment.");
+            }
+            println!("Claimed result matches the reproduced roll.");
+            println!("{}", result);
+        }
+    }
+
+    return ExitCode::SUCCESS;
+}
+
+fn main() -> ExitCode {
     let command_context = match init_clap() {
         Ok(context) => context,
         Err(e) => {
             match e {
-                InitClapErrors::FailedToOpenFileError => {
+                InitClapErrors::OpenFile => {
                     println!("Failed to open provided file.")
-                },
-                InitClapErrors::FailedToReadFromStdin => {
+                }
+                InitClapErrors::ReadStdin => {
                     println!("Failed to read input from STDIN.")
                 }
-                InitClapErrors::FailedToReadFromFile => {
+                InitClapErrors::ReadFile => {
                     println!("Failed to read input from file.")
                 }
             }
-            return;
+            return ExitCode::SUCCESS;
         }
     };
 
+    if let Some(public_key) = &command_context.receipt_public_key {
+        return verify_receipt(&command_context.input, public_key, command_context.as_json);
+    }
+    if let Some(verify_context) = &command_context.verify {
+        return verify(
+            command_context.input,
+            verify_context,
+            command_context.as_json,
+        );
+    }
+
     let roll_request = match parser::parse(command_context.input) {
         Ok(roll_request) => roll_request,
         Err(e) => {
-            println!("{}", e.to_string());
-            return;
+            println!("{}", e);
+            return ExitCode::SUCCESS;
         }
     };
     let result = match roll_request.roll_dice() {
         Ok(result) => result,
         Err(e) => {
-            println!("{}", e.to_string());
-            return;
+            println!("{}", e);
+            return ExitCode::SUCCESS;
         }
     };
 
@@ -102,12 +289,80 @@ fn main() {
                 }
                 Err(_) => {
                     println!("Failed to serialize RollResponse into JSON.");
-                    return;
+                    return ExitCode::SUCCESS;
                 }
             };
         }
         false => {
-            println!("{}", result.to_string());
+            println!("{}", result);
         }
     }
+
+    return ExitCode::SUCCESS;
+}
+
+#[cfg(test)]
+mod tests {
+    use dice_roll::receipt::{Signer, generate_signing_key};
+
+    use super::*;
+
+    fn receipt(signer: &Signer) -> serde_json::Value {
+        let result = parser::parse("2d6 + 1".to_string())
+            .unwrap()
+            .roll_dice()
+            .unwrap();
+        let receipt = signer.sign("2d6 + 1".to_string(), result);
+        return serde_json::to_value(receipt).unwrap();
+    }
+
+    #[test]
+    fn receipts_issued_by_the_server_are_verified() {
+        let signer = Signer::new(&generate_signing_key()).unwrap();
+        let receipt = receipt(&signer).to_string();
+
+        assert_eq!(
+            verify_receipt(&receipt, &signer.public_key(), false),
+            ExitCode::SUCCESS
+        );
+        assert_eq!(
+            verify_receipt(&receipt, &signer.public_key(), true),
+            ExitCode::SUCCESS
+        );
+    }
+
+    #[test]
+    fn altered_receipts_are_rejected() {
+        let signer = Signer::new(&generate_signing_key()).unwrap();
+        let public_key = signer.public_key();
+
+        let mut expression = receipt(&signer);
+        expression["expression"] = "2d6 + 2".into();
+        assert_eq!(
+            verify_receipt(&expression.to_string(), &public_key, false),
+            ExitCode::FAILURE
+        );
+        let mut result = receipt(&signer);
+        result["result"]["total"] = (result["result"]["total"].as_i64().unwrap() + 1).into();
+        assert_eq!(
+            verify_receipt(&result.to_string(), &public_key, false),
+            ExitCode::FAILURE
+        );
+    }
+
+    #[test]
+    fn receipts_issued_with_another_key_are_rejected() {
+        let signer = Signer::new(&generate_signing_key()).unwrap();
+        let receipt = receipt(&signer).to_string();
+        let other = Signer::new(&generate_signing_key()).unwrap();
+
+        assert_eq!(
+            verify_receipt(&receipt, &other.public_key(), false),
+            ExitCode::FAILURE
+        );
+        assert_eq!(
+            verify_receipt(&receipt, "not a key", false),
+            ExitCode::FAILURE
+        );
+    }
 }
@@ -1,7 +1,12 @@
+#[cfg(feature = "graphql")]
+use async_graphql::SimpleObject;
 use rand::prelude::*;
 use serde::{Deserialize, Serialize};
 use serde_json::json;
+#[cfg(feature = "openapi")]
+use utoipa::ToSchema;
 
+#[derive(Clone, Copy, Debug)]
 pub struct BoundConstraint {
     pub lower_bound: i32,
     pub upper_bound: i32,
@@ -25,141 +30,300 @@ pub const COUNT: BC = BC {
 
 pub const MAX_DICE: i32 = 100;
 
+/// Bounds a roll request is validated against.
+#[derive(Clone, Copy, Debug)]
+pub struct RollLimits {
+    pub sides: BC,
+    pub modifier: BC,
+    pub count: BC,
+    /// Most dice a single request may roll across all of its dice.
+    pub max_dice: i32,
+}
+
+pub const DEFAULT_LIMITS: RollLimits = RollLimits {
+    sides: SIDES,
+    modifier: MODIFIER,
+    count: COUNT,
+    max_dice: MAX_DICE,
+};
+
+#[derive(Debug)]
 pub enum RollRequestErrors {
-    InvalidDiceSides { value: i32 },
-    InvalidDiceModifier { value: i32 },
-    InvalidDiceCount { value: i32 },
-    TooManyDice,
+    InvalidDiceSides {
+        index: usize,
+        value: i32,
+        bounds: BC,
+    },
+    InvalidDiceModifier {
+        index: usize,
+        value: i32,
+        bounds: BC,
+    },
+    InvalidDiceCount {
+        index: usize,
+        value: i32,
+        bounds: BC,
+    },
+    TooManyDice {
+        limit: i32,
+    },
 }
 
-impl RollRequestErrors {
-    pub fn to_string(self) -> String {
+impl std::fmt::Display for RollRequestErrors {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
-            RollRequestErrors::InvalidDiceSides { value } => {
-                format!(
+            RollRequestErrors::InvalidDiceSides { value, bounds, .. } => {
+                return write!(
+                    f,
                     "Dice sides must be between {} and {}, {} provided",
-                    SIDES.lower_bound, SIDES.upper_bound, value
-                )
+                    bounds.lower_bound, bounds.upper_bound, value
+                );
             }
-            RollRequestErrors::InvalidDiceModifier { value } => {
-                format!(
+            RollRequestErrors::InvalidDiceModifier { value, bounds, .. } => {
+                return write!(
+                    f,
                     "Dice modifier must be between {} and {}, {} provided",
-                    MODIFIER.lower_bound, MODIFIER.upper_bound, value
-                )
+                    bounds.lower_bound, bounds.upper_bound, value
+                );
             }
-            RollRequestErrors::InvalidDiceCount { value } => {
-                format!(
+            RollRequestErrors::InvalidDiceCount { value, bounds, .. } => {
+                return write!(
+                    f,
                     "Dice count must be between {} and {}, {} provided",
-                    COUNT.lower_bound, COUNT.upper_bound, value
-                )
+                    bounds.lower_bound, bounds.upper_bound, value
+                );
             }
-            RollRequestErrors::TooManyDice => {
-                format!("Total dice to roll can not exceed {}.", MAX_DICE,)
+            RollRequestErrors::TooManyDice { limit } => {
+                return write!(f, "Total dice to roll can not exceed {}.", limit);
             }
         }
     }
+}
 
-    pub fn to_json(self) -> serde_json::Value {
+impl RollRequestErrors {
+    pub fn code(&self) -> &'static str {
         match self {
-            RollRequestErrors::InvalidDiceSides { value } => {
-                return json!({
-                    "code": "INVALID_DICE_SIDES",
-                    "message": format!(
-                        "Dice sides must be between {} and {}, {} provided",
-                        SIDES.lower_bound, SIDES.upper_bound, value
-                    ),
-                });
-            }
-            RollRequestErrors::InvalidDiceModifier { value } => {
-                return json!({
-                    "code": "INVALID_DICE_MODIFIER",
-                    "message": format!(
-                        "Dice modifier must be between {} and {}, {} provided",
-                        MODIFIER.lower_bound, MODIFIER.upper_bound, value
-                    ),
-                });
-            }
-            RollRequestErrors::InvalidDiceCount { value } => {
-                return json!({
-                    "code": "INVALID_DICE_COUNT",
-                    "message": format!(
-                        "Dice count must be between {} and {}, {} provided",
-                        COUNT.lower_bound, COUNT.upper_bound, value
-                    ),
-                });
-            }
-            RollRequestErrors::TooManyDice => {
-                return json!({
-                    "code": "TOO_MANY_DICE",
-                    "message": format!(
-                        "Total dice to roll can not exceed {}",
-                        MAX_DICE,
-                    ),
-                });
+            RollRequestErrors::InvalidDiceSides { .. } => "INVALID_DICE_SIDES",
+            RollRequestErrors::InvalidDiceModifier { .. } => "INVALID_DICE_MODIFIER",
+            RollRequestErrors::InvalidDiceCount { .. } => "INVALID_DICE_COUNT",
+            RollRequestErrors::TooManyDice { .. } => "TOO_MANY_DICE",
+        }
+    }
+
+    /// Path of the request field that failed validation, e.g. `dice[2].sides`.
+    pub fn field(&self) -> String {
+        match self {
+            RollRequestErrors::InvalidDiceSides { index, .. } => format!("dice[{}].sides", index),
+            RollRequestErrors::InvalidDiceModifier { index, .. } => {
+                format!("dice[{}].modifier", index)
             }
+            RollRequestErrors::InvalidDiceCount { index, .. } => format!("dice[{}].count", index),
+            RollRequestErrors::TooManyDice { .. } => "dice".to_string(),
         }
     }
+
+    pub fn to_json(self) -> serde_json::Value {
+        return json!({
+            "code": self.code(),
+            "message": self.to_string(),
+        });
+    }
 }
 
-#[derive(Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug)]
+#[cfg_attr(feature = "openapi", derive(ToSchema))]
+#[serde(deny_unknown_fields)]
 pub struct Dice {
+    #[serde(default = "default_count")]
+    #[cfg_attr(feature = "openapi", schema(default = 1))]
     pub count: i32,
     pub sides: i32,
+    #[serde(default)]
+    #[cfg_attr(feature = "openapi", schema(default = 0))]
     pub modifier: i32,
 }
 
-#[derive(Deserialize, Debug)]
+fn default_count() -> i32 {
+    return 1;
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+#[cfg_attr(feature = "openapi", derive(ToSchema))]
+#[serde(deny_unknown_fields)]
 pub struct RollRequest {
     pub dice: Vec<Dice>,
 }
 
-#[derive(Serialize, Debug)]
-struct Rolls {
-    count: i32,
-    sides: i32,
-    modifier: i32,
-    rolls: Vec<i32>,
-    total: i32,
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "graphql", derive(SimpleObject))]
+#[cfg_attr(feature = "openapi", derive(ToSchema))]
+pub struct Rolls {
+    pub count: i32,
+    pub sides: i32,
+    pub modifier: i32,
+    pub rolls: Vec<i32>,
+    pub total: i32,
 }
 
-#[derive(Serialize, Debug)]
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "graphql", derive(SimpleObject))]
+#[cfg_attr(feature = "openapi", derive(ToSchema))]
 pub struct RollResponse {
-    rolls: Vec<Rolls>,
-    total: i32,
+    pub rolls: Vec<Rolls>,
+    pub total: i32,
+}
+
+/// Lowest, highest and average totals a roll request can roll.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[cfg_attr(feature = "graphql", derive(SimpleObject))]
+#[cfg_attr(feature = "openapi", derive(ToSchema))]
+pub struct RollStats {
+    pub minimum: i32,
+    pub maximum: i32,
+    pub mean: f64,
+    pub standard_deviation: f64,
+}
+
+/// Chance of a roll request rolling a particular total.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[cfg_attr(feature = "graphql", derive(SimpleObject))]
+#[cfg_attr(feature = "openapi", derive(ToSchema))]
+pub struct Outcome {
+    pub total: i32,
+    pub probability: f64,
+}
+
+/// Every total a roll request can roll, from lowest to highest, and how likely each is.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[cfg_attr(feature = "graphql", derive(SimpleObject))]
+#[cfg_attr(feature = "openapi", derive(ToSchema))]
+pub struct Distribution {
+    #[serde(flatten)]
+    #[cfg_attr(feature = "graphql", graphql(flatten))]
+    pub stats: RollStats,
+    pub outcomes: Vec<Outcome>,
+}
+
+/// Probabilities of each total after adding a die with `sides` to a roll whose totals have
+/// `probabilities`, both starting from their lowest total.
+fn add_die(probabilities: &[f64], sides: usize) -> Vec<f64> {
+    let mut added = Vec::with_capacity(probabilities.len() + sides - 1);
+    // Each total is reached from any of the previous `sides` totals, kept as a running sum.
+    let mut window = 0.0;
+    for total in 0..probabilities.len() + sides - 1 {
+        if total < probabilities.len() {
+            window += probabilities[total];
+        }
+        if total >= sides {
+            window -= probabilities[total - sides];
+        }
+        added.push(window.max(0.0) / sides as f64);
+    }
+
+    return added;
 }
 
 impl RollRequest {
-    fn validate_roll_request(&self) -> Result<&RollRequest, RollRequestErrors> {
+    /// Checks the request against `limits`, rolling checks it against [`DEFAULT_LIMITS`].
+    pub fn validate(&self, limits: &RollLimits) -> Result<(), RollRequestErrors> {
         let mut total_dice_count = 0;
-        for dice in self.dice.iter() {
-            if dice.sides < SIDES.lower_bound || dice.sides > SIDES.upper_bound {
-                return Err(RollRequestErrors::InvalidDiceSides { value: dice.sides });
+        for (index, dice) in self.dice.iter().enumerate() {
+            if dice.sides < limits.sides.lower_bound || dice.sides > limits.sides.upper_bound {
+                return Err(RollRequestErrors::InvalidDiceSides {
+                    index,
+                    value: dice.sides,
+                    bounds: limits.sides,
+                });
             }
-            if dice.modifier < MODIFIER.lower_bound || dice.modifier > MODIFIER.upper_bound {
+            if dice.modifier < limits.modifier.lower_bound
+                || dice.modifier > limits.modifier.upper_bound
+            {
                 return Err(RollRequestErrors::InvalidDiceModifier {
+                    index,
                     value: dice.modifier,
+                    bounds: limits.modifier,
                 });
             }
-            if dice.count < COUNT.lower_bound || dice.count > COUNT.upper_bound {
-                return Err(RollRequestErrors::InvalidDiceCount { value: dice.count });
+            if dice.count < limits.count.lower_bound || dice.count > limits.count.upper_bound {
+                return Err(RollRequestErrors::InvalidDiceCount {
+                    index,
+                    value: dice.count,
+                    bounds: limits.count,
+                });
             }
 
             total_dice_count += dice.count;
-            if total_dice_count > MAX_DICE {
-                return Err(RollRequestErrors::TooManyDice);
+            if total_dice_count > limits.max_dice {
+                return Err(RollRequestErrors::TooManyDice {
+                    limit: limits.max_dice,
+                });
             }
         }
 
+        Ok(())
+    }
+
+    fn validate_roll_request(&self) -> Result<&RollRequest, RollRequestErrors> {
+        self.validate(&DEFAULT_LIMITS)?;
         Ok(self)
     }
 
-    pub fn roll_dice(&self) -> Result<RollResponse, RollRequestErrors> {
-        let roll_request = match RollRequest::validate_roll_request(self) {
-            Ok(ok) => ok,
-            Err(err) => return Err(err),
+    pub fn stats(&self) -> Result<RollStats, RollRequestErrors> {
+        let roll_request = RollRequest::validate_roll_request(self)?;
+
+        let mut stats = RollStats {
+            minimum: 0,
+            maximum: 0,
+            mean: 0.0,
+            standard_deviation: 0.0,
         };
+        let mut variance = 0.0;
+        for dice in roll_request.dice.iter() {
+            let (count, sides) = (dice.count as f64, dice.sides as f64);
+            stats.minimum += dice.count + dice.modifier;
+            stats.maximum += dice.count * dice.sides + dice.modifier;
+            stats.mean += count * (sides + 1.0) / 2.0 + dice.modifier as f64;
+            // Each die is uniform over 1 to sides, the dice are independent so variances add up.
+            variance += count * (sides * sides - 1.0) / 12.0;
+        }
+        stats.standard_deviation = variance.sqrt();
 
+        Ok(stats)
+    }
+
+    pub fn distribution(&self) -> Result<Distribution, RollRequestErrors> {
+        let stats = self.stats()?;
+
+        let mut probabilities = vec![1.0];
+        for dice in self.dice.iter() {
+            for _ in 0..dice.count {
+                probabilities = add_die(&probabilities, dice.sides as usize);
+            }
+        }
+        let outcomes = probabilities
+            .into_iter()
+            .enumerate()
+            .map(|(offset, probability)| Outcome {
+                total: stats.minimum + offset as i32,
+                probability,
+            })
+            .collect();
+
+        Ok(Distribution { stats, outcomes })
+    }
+
+    pub fn roll_dice(&self) -> Result<RollResponse, RollRequestErrors> {
         let mut rng = rand::rng();
+        return self.roll_dice_with(|sides| rng.random_range(1..=sides));
+    }
+
+    /// Rolls the request's dice using `roll` to produce each result, given the dice's sides.
+    pub fn roll_dice_with<F: FnMut(i32) -> i32>(
+        &self,
+        mut roll: F,
+    ) -> Result<RollResponse, RollRequestErrors> {
+        let roll_request = RollRequest::validate_roll_request(self)?;
+
         let mut roll_response = RollResponse {
             rolls: Vec::new(),
             total: 0,
@@ -170,7 +334,7 @@ impl RollRequest {
             let mut rolls_total = dice.modifier;
 
             for _ in 0..dice.count {
-                let roll = rng.random_range(1..=dice.sides);
+                let roll = roll(dice.sides);
                 rolls.push(roll);
                 rolls_total += roll
             }
@@ -188,13 +352,36 @@ impl RollRequest {
     }
 }
 
-impl RollResponse {
-    pub fn to_string(self) -> String {
+/// Writes the request as dice roll notation, e.g. `2d6 + 1d8 - 3`.
+impl std::fmt::Display for RollRequest {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let mut notation = Vec::new();
+
+        for dice in &self.dice {
+            if !notation.is_empty() {
+                notation.push("+".to_string());
+            }
+            notation.push(format!("{}d{}", dice.count, dice.sides));
+            if dice.modifier > 0 {
+                notation.push("+".to_string());
+                notation.push(dice.modifier.to_string());
+            } else if dice.modifier < 0 {
+                notation.push("-".to_string());
+                notation.push(dice.modifier.abs().to_string());
+            }
+        }
+
+        return f.write_str(&notation.join(" "));
+    }
+}
+
+impl std::fmt::Display for RollResponse {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         let mut result = Vec::new();
 
-        for rolls in self.rolls {
-            for roll in rolls.rolls {
-                if result.len() > 0 {
+        for rolls in &self.rolls {
+            for roll in &rolls.rolls {
+                if !result.is_empty() {
                     result.push("+".to_string())
                 }
                 result.push(format!("({} of {})", roll, rolls.sides));
@@ -212,12 +399,37 @@ impl RollResponse {
         result.push("=".to_string());
         result.push(self.total.to_string());
 
-        return result.join(" ");
+        return f.write_str(&result.join(" "));
     }
+}
 
+impl RollResponse {
     pub fn to_json(self) -> serde_json::Value {
         return json!(self);
     }
+
+    /// Number of dice rolled in each group, as `(sides, count)` pairs.
+    pub fn dice_rolled(&self) -> impl Iterator<Item = (i32, i32)> + '_ {
+        return self.rolls.iter().map(|rolls| (rolls.sides, rolls.count));
+    }
+
+    fn any_d20_rolled(&self, value: i32) -> bool {
+        return self
+            .rolls
+            .iter()
+            .filter(|rolls| rolls.sides == 20)
+            .any(|rolls| rolls.rolls.contains(&value));
+    }
+
+    /// Whether any twenty sided die came up a natural 20.
+    pub fn is_critical(&self) -> bool {
+        return self.any_d20_rolled(20);
+    }
+
+    /// Whether any twenty sided die came up a natural 1.
+    pub fn is_fumble(&self) -> bool {
+        return self.any_d20_rolled(1);
+    }
 }
 
 pub mod parser {
@@ -231,6 +443,7 @@ pub mod parser {
 
     #[derive(Debug)]
     pub enum ParserErrors {
+        EmptyInputError,
         RollParserError(RollTokenParserErrors),
         EmptyDiceForModifierError,
         NoZeroModifiersError,
@@ -247,6 +460,9 @@ pub mod parser {
 
     pub fn parse(input: String) -> Result<crate::RollRequest, ParserErrors> {
         let input = input.as_bytes();
+        if input.iter().all(|byte| *byte == b' ') {
+            return Err(ParserErrors::EmptyInputError);
+        }
         let mut result = crate::RollRequest { dice: Vec::new() };
         let mut current_token_state = Tokens::Roll;
         let mut cursor = 0;
@@ -287,7 +503,7 @@ pub mod parser {
                                 token = String::new();
                             }
                             Tokens::PlusRollOrModifier | Tokens::MinusModifier => {
-                                if token == "" {
+                                if token.is_empty() {
                                     return Err(ParserErrors::EmptyModifierError);
                                 }
                                 let modifier: i32 = match token.parse() {
@@ -398,46 +614,915 @@ pub mod parser {
         };
 
         let result = crate::Dice {
-            count: count,
-            sides: sides,
+            count,
+            sides,
             modifier: 0,
         };
 
         return Ok(result);
     }
 
-    impl ParserErrors {
-        pub fn to_string(self) -> String {
+    impl std::fmt::Display for ParserErrors {
+        fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
             match self {
+                Self::EmptyInputError => {
+                    return f.write_str("No input provided. Dice roll is in the form \"1d4\"");
+                }
                 Self::RollParserError(roll_token_parser_errors) => match roll_token_parser_errors {
                     RollTokenParserErrors::DiceCountParserError { token } => {
-                        return format!(
+                        return write!(
+                            f,
                             "Invalid roll provided, {token}. Failed to parse dice count."
                         );
                     }
                     RollTokenParserErrors::DiceSidesParserError { token } => {
-                        return format!(
+                        return write!(
+                            f,
                             "Invalid roll provided, {token}. Failed to parse dice sides."
                         );
                     }
                 },
                 Self::EmptyDiceForModifierError => {
-                    return "No dice roll provided. Dice roll is in the form \"1d4\"".to_string();
+                    return f.write_str("No dice roll provided. Dice roll is in the form \"1d4\"");
                 }
                 Self::EmptyModifierError => {
-                    return "Dice roll includes empty modifier.".to_string();
+                    return f.write_str("Dice roll includes empty modifier.");
                 }
                 Self::ModifierParserError { token } => {
-                    return format!("Invalid modifier provided, {token}.");
+                    return write!(f, "Invalid modifier provided, {token}.");
                 }
                 Self::TooManyModifiersError => {
-                    return "Multiple non-zero modifiers are being applied to the same dice roll."
-                        .to_string();
+                    return f.write_str(
+                        "Multiple non-zero modifiers are being applied to the same dice roll.",
+                    );
                 }
                 Self::NoZeroModifiersError => {
-                    return "No modifiers with a value of 0.".to_string();
+                    return f.write_str("No modifiers with a value of 0.");
                 }
             }
         }
     }
+
+    impl ParserErrors {
+        pub fn code(&self) -> &'static str {
+            match self {
+                Self::EmptyInputError => "EMPTY_INPUT",
+                Self::RollParserError(RollTokenParserErrors::DiceCountParserError { .. }) => {
+                    "INVALID_DICE_COUNT_TOKEN"
+                }
+                Self::RollParserError(RollTokenParserErrors::DiceSidesParserError { .. }) => {
+                    "INVALID_DICE_SIDES_TOKEN"
+                }
+                Self::EmptyDiceForModifierError => "EMPTY_DICE_FOR_MODIFIER",
+                Self::EmptyModifierError => "EMPTY_MODIFIER",
+                Self::ModifierParserError { .. } => "INVALID_MODIFIER_TOKEN",
+                Self::TooManyModifiersError => "TOO_MANY_MODIFIERS",
+                Self::NoZeroModifiersError => "ZERO_MODIFIER",
+            }
+        }
+
+        pub fn to_json(self) -> serde_json::Value {
+            return serde_json::json!({
+                "code": self.code(),
+                "message": self.to_string(),
+            });
+        }
+    }
+}
+
+pub mod fair {
+    //! Provably fair rolls using a commit-reveal scheme.
+    //!
+    //! The server commits to a secret seed by publishing its SHA-256 hash. Each roll is then
+    //! derived from HMAC-SHA256 keyed with the server seed over `"{client_seed}:{nonce}:{round}"`,
+    //! for rounds 0, 1, 2, ... as more random bytes are needed. The HMAC output is read as
+    //! big-endian `u32`s. Values at or above `u32::MAX - u32::MAX % sides` are skipped and the
+    //! rest become `value % sides + 1`, so every side is equally likely. Dice are rolled in the
+    //! order they appear in the request. Once the server seed is revealed anyone can check it
+    //! against the commitment and reproduce the rolls.
+
+    use hmac::{Hmac, Mac};
+    use rand::RngCore;
+    use sha2::{Digest, Sha256};
+
+    type HmacSha256 = Hmac<Sha256>;
+
+    pub const SERVER_SEED_BYTES: usize = 32;
+
+    #[derive(Debug)]
+    pub enum FairErrors {
+        InvalidServerSeedError,
+        EmptyClientSeedError,
+    }
+
+    impl std::fmt::Display for FairErrors {
+        fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+            match self {
+                Self::InvalidServerSeedError => {
+                    return write!(
+                        f,
+                        "Server seed must be {} bytes encoded as hex.",
+                        SERVER_SEED_BYTES
+                    );
+                }
+                Self::EmptyClientSeedError => {
+                    return f.write_str("Client seed can not be empty.");
+                }
+            }
+        }
+    }
+
+    impl FairErrors {
+        pub fn code(&self) -> &'static str {
+            match self {
+                Self::InvalidServerSeedError => "INVALID_SERVER_SEED",
+                Self::EmptyClientSeedError => "EMPTY_CLIENT_SEED",
+            }
+        }
+    }
+
+    fn decode_server_seed(server_seed: &str) -> Result<Vec<u8>, FairErrors> {
+        match hex::decode(server_seed.trim()) {
+            Ok(bytes) if bytes.len() == SERVER_SEED_BYTES => return Ok(bytes),
+            _ => return Err(FairErrors::InvalidServerSeedError),
+        }
+    }
+
+    /// Generates a new secret server seed, encoded as hex.
+    pub fn generate_server_seed() -> String {
+        let mut bytes = [0u8; SERVER_SEED_BYTES];
+        rand::rng().fill_bytes(&mut bytes);
+        return hex::encode(bytes);
+    }
+
+    /// Hex encoded SHA-256 hash of the server seed's bytes, published before any rolls are made.
+    pub fn commitment(server_seed: &str) -> Result<String, FairErrors> {
+        let bytes = decode_server_seed(server_seed)?;
+        return Ok(hex::encode(Sha256::digest(bytes)));
+    }
+
+    /// Deterministic source of dice rolls derived from a server seed, client seed and nonce.
+    pub struct FairRng {
+        mac: HmacSha256,
+        client_seed: String,
+        nonce: u64,
+        round: u64,
+        block: Vec<u8>,
+        offset: usize,
+    }
+
+    impl FairRng {
+        pub fn new(
+            server_seed: &str,
+            client_seed: &str,
+            nonce: u64,
+        ) -> Result<FairRng, FairErrors> {
+            let key = decode_server_seed(server_seed)?;
+            if client_seed.is_empty() {
+                return Err(FairErrors::EmptyClientSeedError);
+            }
+            let mac = match HmacSha256::new_from_slice(&key) {
+                Ok(mac) => mac,
+                Err(_) => return Err(FairErrors::InvalidServerSeedError),
+            };
+
+            return Ok(FairRng {
+                mac,
+                client_seed: client_seed.to_string(),
+                nonce,
+                round: 0,
+                block: Vec::new(),
+                offset: 0,
+            });
+        }
+
+        fn next_u32(&mut self) -> u32 {
+            if self.offset + 4 > self.block.len() {
+                let mut mac = self.mac.clone();
+                mac.update(
+                    format!("{}:{}:{}", self.client_seed, self.nonce, self.round).as_bytes(),
+                );
+                self.block = mac.finalize().into_bytes().to_vec();
+                self.offset = 0;
+                self.round += 1;
+            }
+            let bytes = [
+                self.block[self.offset],
+                self.block[self.offset + 1],
+                self.block[self.offset + 2],
+                self.block[self.offset + 3],
+            ];
+            self.offset += 4;
+
+            return u32::from_be_bytes(bytes);
+        }
+
+        /// Rolls a single die with the provided number of sides, returning a value in `1..=sides`.
+        pub fn roll(&mut self, sides: i32) -> i32 {
+            let sides = sides.max(1) as u32;
+            // Values at or above the largest multiple of `sides` are discarded to avoid bias.
+            let zone = u32::MAX - (u32::MAX % sides);
+            loop {
+                let value = self.next_u32();
+                if value < zone {
+                    return (value % sides) as i32 + 1;
+                }
+            }
+        }
+    }
+
+    impl crate::RollRequest {
+        pub fn roll_dice_fair(
+            &self,
+            rng: &mut FairRng,
+        ) -> Result<crate::RollResponse, crate::RollRequestErrors> {
+            return self.roll_dice_with(|sides| rng.roll(sides));
+        }
+    }
+
+    #[derive(Debug)]
+    pub enum VerifyErrors {
+        CommitmentMismatchError,
+        ResultMismatchError { reproduced: crate::RollResponse },
+        FairError(FairErrors),
+        ParserError(crate::parser::ParserErrors),
+        RollError(crate::RollRequestErrors),
+    }
+
+    impl std::fmt::Display for VerifyErrors {
+        fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+            match self {
+                Self::CommitmentMismatchError => {
+                    return f.write_str("Server seed does not match the commitment.");
+                }
+                Self::ResultMismatchError { reproduced } => {
+                    return write!(
+                        f,
+                        "Claimed result does not match the roll reproduced from the seeds, {}.",
+                        reproduced
+                    );
+                }
+                Self::FairError(e) => return e.fmt(f),
+                Self::ParserError(e) => return e.fmt(f),
+                Self::RollError(e) => return e.fmt(f),
+            }
+        }
+    }
+
+    /// Reproduces a roll of `expression` from its seeds and nonce, checking it against the result
+    /// the server claimed, and the server seed against its commitment when one is provided.
+    /// Returns the reproduced roll.
+    pub fn verify(
+        expression: String,
+        server_seed: &str,
+        client_seed: &str,
+        nonce: u64,
+        commitment: Option<&str>,
+        claimed: &crate::RollResponse,
+    ) -> Result<crate::RollResponse, VerifyErrors> {
+        if let Some(commitment) = commitment
+            && self::commitment(server_seed).map_err(VerifyErrors::FairError)?
+                != commitment.trim().to_lowercase()
+        {
+            return Err(VerifyErrors::CommitmentMismatchError);
+        }
+        let roll_request = crate::parser::parse(expression).map_err(VerifyErrors::ParserError)?;
+        let mut rng =
+            FairRng::new(server_seed, client_seed, nonce).map_err(VerifyErrors::FairError)?;
+        let reproduced = roll_request
+            .roll_dice_fair(&mut rng)
+            .map_err(VerifyErrors::RollError)?;
+        if &reproduced != claimed {
+            return Err(VerifyErrors::ResultMismatchError { reproduced });
+        }
+
+        return Ok(reproduced);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    mod requests {
+        use crate::RollRequest;
+
+        #[test]
+        fn dice_count_and_modifier_are_optional() {
+            let request: RollRequest = serde_json::from_str(
+                r#"{"dice": [{"sides": 20}, {"count": 2, "sides": 6, "modifier": -1}]}"#,
+            )
+            .unwrap();
+
+            let dice = request
+                .dice
+                .iter()
+                .map(|dice| (dice.count, dice.sides, dice.modifier))
+                .collect::<Vec<_>>();
+            assert_eq!(dice, [(1, 20, 0), (2, 6, -1)]);
+        }
+
+        #[test]
+        fn unknown_fields_are_rejected() {
+            let error =
+                serde_json::from_str::<RollRequest>(r#"{"dice": [{"sides": 20, "side": 6}]}"#)
+                    .unwrap_err();
+            assert!(
+                error.to_string().contains("unknown field `side`"),
+                "{}",
+                error
+            );
+            assert!(serde_json::from_str::<RollRequest>(r#"{"dice": [], "seed": 1}"#).is_err());
+        }
+    }
+
+    mod parser {
+        use crate::parser::{ParserErrors, RollTokenParserErrors, parse};
+
+        #[test]
+        fn empty_input_is_rejected() {
+            assert!(matches!(
+                parse(String::new()),
+                Err(ParserErrors::EmptyInputError)
+            ));
+            assert!(matches!(
+                parse("   ".to_string()),
+                Err(ParserErrors::EmptyInputError)
+            ));
+        }
+
+        #[test]
+        fn parses_dice_and_modifiers() {
+            let request = parse("2d6 + 1d8 - 3".to_string()).unwrap();
+
+            assert_eq!(request.dice.len(), 2);
+            assert_eq!(
+                (
+                    request.dice[0].count,
+                    request.dice[0].sides,
+                    request.dice[0].modifier
+                ),
+                (2, 6, 0)
+            );
+            assert_eq!(
+                (
+                    request.dice[1].count,
+                    request.dice[1].sides,
+                    request.dice[1].modifier
+                ),
+                (1, 8, -3)
+            );
+        }
+
+        #[test]
+        fn rejects_malformed_notation() {
+            assert!(matches!(
+                parse("xd6".to_string()),
+                Err(ParserErrors::RollParserError(
+                    RollTokenParserErrors::DiceCountParserError { .. }
+                ))
+            ));
+            assert!(matches!(
+                parse("1d6+".to_string()),
+                Err(ParserErrors::EmptyModifierError)
+            ));
+            assert!(matches!(
+                parse("1d6+0".to_string()),
+                Err(ParserErrors::NoZeroModifiersError)
+            ));
+            assert!(matches!(
+                parse("1d6+1+2".to_string()),
+                Err(ParserErrors::TooManyModifiersError)
+            ));
+        }
+
+        #[test]
+        fn requests_are_written_back_as_notation() {
+            for notation in ["2d6 + 1d8 - 3", "1d20 + 5", "4d6"] {
+                assert_eq!(parse(notation.to_string()).unwrap().to_string(), notation);
+            }
+        }
+
+        #[test]
+        fn errors_have_codes_and_messages() {
+            let error = parse(String::new()).unwrap_err();
+
+            assert_eq!(error.code(), "EMPTY_INPUT");
+            assert_eq!(
+                error.to_string(),
+                "No input provided. Dice roll is in the form \"1d4\""
+            );
+        }
+    }
+
+    mod fair {
+        use crate::fair::{
+            FairErrors, FairRng, VerifyErrors, commitment, generate_server_seed, verify,
+        };
+
+        const SERVER_SEED: &str =
+            "0000000000000000000000000000000000000000000000000000000000000000";
+
+        fn rolls(client_seed: &str, nonce: u64, sides: i32) -> Vec<i32> {
+            let mut rng = FairRng::new(SERVER_SEED, client_seed, nonce).unwrap();
+            return (0..20).map(|_| rng.roll(sides)).collect();
+        }
+
+        #[test]
+        fn rolls_are_reproducible_from_the_seeds_and_nonce() {
+            assert_eq!(rolls("lucky", 1, 20), rolls("lucky", 1, 20));
+            assert_ne!(rolls("lucky", 1, 20), rolls("lucky", 2, 20));
+            assert_ne!(rolls("lucky", 1, 20), rolls("unlucky", 1, 20));
+        }
+
+        #[test]
+        fn rolls_stay_within_the_dice_sides() {
+            // 1000 sides doesn't divide 2^32 evenly, so some values are rejected and redrawn.
+            for sides in [1, 2, 6, 7, 20, 1000] {
+                let mut rng = FairRng::new(&generate_server_seed(), "bounds", 1).unwrap();
+                for _ in 0..1000 {
+                    let roll = rng.roll(sides);
+                    assert!((1..=sides).contains(&roll), "{} out of 1..={}", roll, sides);
+                }
+            }
+        }
+
+        #[test]
+        fn every_side_comes_up() {
+            let mut rng = FairRng::new(SERVER_SEED, "coverage", 1).unwrap();
+            let mut seen = [false; 6];
+            for _ in 0..600 {
+                seen[rng.roll(6) as usize - 1] = true;
+            }
+            assert!(seen.iter().all(|seen| *seen));
+        }
+
+        #[test]
+        fn commitment_is_the_hash_of_the_seed_bytes() {
+            assert_eq!(
+                commitment(SERVER_SEED).unwrap(),
+                "66687aadf862bd776c8fc18b8e9f8e20089714856ee233b3902a591d0d5f2925"
+            );
+            let server_seed = generate_server_seed();
+            assert_eq!(
+                commitment(&server_seed).unwrap(),
+                commitment(&server_seed.to_uppercase()).unwrap()
+            );
+            assert_ne!(
+                commitment(&server_seed).unwrap(),
+                commitment(&generate_server_seed()).unwrap()
+            );
+        }
+
+        #[test]
+        fn invalid_seeds_are_rejected() {
+            assert!(matches!(
+                commitment("abcd"),
+                Err(FairErrors::InvalidServerSeedError)
+            ));
+            assert!(matches!(
+                FairRng::new("not hex", "lucky", 1),
+                Err(FairErrors::InvalidServerSeedError)
+            ));
+            assert!(matches!(
+                FairRng::new(SERVER_SEED, "", 1),
+                Err(FairErrors::EmptyClientSeedError)
+            ));
+        }
+
+        fn claimed(expression: &str) -> crate::RollResponse {
+            let roll_request = crate::parser::parse(expression.to_string()).unwrap();
+            let mut rng = FairRng::new(SERVER_SEED, "lucky", 1).unwrap();
+            return roll_request.roll_dice_fair(&mut rng).unwrap();
+        }
+
+        #[test]
+        fn rolls_matching_their_seeds_are_verified() {
+            let claimed = claimed("3d20 + 2");
+            let commitment = commitment(SERVER_SEED).unwrap();
+
+            let reproduced = verify(
+                "3d20 + 2".to_string(),
+                SERVER_SEED,
+                "lucky",
+                1,
+                Some(&commitment.to_uppercase()),
+                &claimed,
+            )
+            .unwrap();
+            assert_eq!(reproduced, claimed);
+            assert!(
+                verify(
+                    "3d20 + 2".to_string(),
+                    SERVER_SEED,
+                    "lucky",
+                    1,
+                    None,
+                    &claimed
+                )
+                .is_ok()
+            );
+        }
+
+        #[test]
+        fn seeds_not_matching_their_commitment_are_rejected() {
+            let claimed = claimed("3d20 + 2");
+            let commitment = commitment(&generate_server_seed()).unwrap();
+
+            assert!(matches!(
+                verify(
+                    "3d20 + 2".to_string(),
+                    SERVER_SEED,
+                    "lucky",
+                    1,
+                    Some(&commitment),
+                    &claimed
+                ),
+                Err(VerifyErrors::CommitmentMismatchError)
+            ));
+        }
+
+        #[test]
+        fn tampered_results_are_rejected() {
+            let mut tampered = claimed("3d20 + 2");
+            tampered.total += 1;
+            assert!(matches!(
+                verify(
+                    "3d20 + 2".to_string(),
+                    SERVER_SEED,
+                    "lucky",
+                    1,
+                    None,
+                    &tampered
+                ),
+                Err(VerifyErrors::ResultMismatchError { .. })
+            ));
+
+            let claimed = claimed("3d20 + 2");
+            for (expression, client_seed, nonce) in [
+                ("3d20 + 3", "lucky", 1),
+                ("3d20 + 2", "unlucky", 1),
+                ("3d20 + 2", "lucky", 2),
+            ] {
+                assert!(matches!(
+                    verify(
+                        expression.to_string(),
+                        SERVER_SEED,
+                        client_seed,
+                        nonce,
+                        None,
+                        &claimed
+                    ),
+                    Err(VerifyErrors::ResultMismatchError { .. })
+                ));
+            }
+        }
+
+        #[test]
+        fn fair_rolls_match_the_rng() {
+            let roll_request = crate::parser::parse("3d20 + 2".to_string()).unwrap();
+            let mut rng = FairRng::new(SERVER_SEED, "lucky", 1).unwrap();
+            let result = roll_request.roll_dice_fair(&mut rng).unwrap();
+
+            let expected = rolls("lucky", 1, 20)[..3].to_vec();
+            assert_eq!(result.rolls[0].rolls, expected);
+            assert_eq!(result.total, expected.iter().sum::<i32>() + 2);
+        }
+    }
+
+    mod receipt {
+        use crate::receipt::{Receipt, ReceiptErrors, Signer, generate_signing_key};
+
+        fn signed() -> (Signer, Receipt) {
+            let signer = Signer::new(&generate_signing_key()).unwrap();
+            let result = crate::parser::parse("1d20 + 5".to_string())
+                .unwrap()
+                .roll_dice()
+                .unwrap();
+            let receipt = signer.sign("1d20 + 5".to_string(), result);
+
+            return (signer, receipt);
+        }
+
+        #[test]
+        fn receipts_verify_against_the_signers_key() {
+            let (signer, receipt) = signed();
+            assert!(receipt.verify(&signer.public_key()).is_ok());
+
+            let json = serde_json::to_string(&receipt).unwrap();
+            let parsed = Receipt::from_json(&json).unwrap();
+            assert!(parsed.verify(&signer.public_key()).is_ok());
+        }
+
+        #[test]
+        fn altered_receipts_are_rejected() {
+            let (signer, receipt) = signed();
+            let public_key = signer.public_key();
+
+            let mut altered = receipt.clone();
+            altered.result.total += 1;
+            assert!(matches!(
+                altered.verify(&public_key),
+                Err(ReceiptErrors::InvalidSignatureError)
+            ));
+            let mut altered = receipt.clone();
+            altered.expression = "1d20 + 6".to_string();
+            assert!(matches!(
+                altered.verify(&public_key),
+                Err(ReceiptErrors::InvalidSignatureError)
+            ));
+            let mut altered = receipt.clone();
+            altered.nonce = "00".repeat(16);
+            assert!(matches!(
+                altered.verify(&public_key),
+                Err(ReceiptErrors::InvalidSignatureError)
+            ));
+        }
+
+        #[test]
+        fn receipts_are_rejected_with_another_key() {
+            let (_, receipt) = signed();
+            let (other, _) = signed();
+            assert!(matches!(
+                receipt.verify(&other.public_key()),
+                Err(ReceiptErrors::InvalidSignatureError)
+            ));
+            assert!(matches!(
+                receipt.verify("not hex"),
+                Err(ReceiptErrors::InvalidPublicKeyError)
+            ));
+        }
+
+        #[test]
+        fn malformed_receipts_and_keys_are_rejected() {
+            assert!(matches!(
+                Receipt::from_json("{\"expression\": \"1d20\"}"),
+                Err(ReceiptErrors::MalformedReceiptError)
+            ));
+            assert!(matches!(
+                Signer::new("abcd"),
+                Err(ReceiptErrors::InvalidSigningKeyError)
+            ));
+        }
+    }
+
+    mod stats {
+        use crate::{Dice, RollRequest};
+
+        fn request(dice: &[(i32, i32, i32)]) -> RollRequest {
+            let dice = dice
+                .iter()
+                .map(|&(count, sides, modifier)| Dice {
+                    count,
+                    sides,
+                    modifier,
+                })
+                .collect();
+            RollRequest { dice }
+        }
+
+        fn assert_close(actual: f64, expected: f64) {
+            assert!(
+                (actual - expected).abs() < 1e-9,
+                "{} != {}",
+                actual,
+                expected
+            );
+        }
+
+        #[test]
+        fn stats_add_up_across_dice() {
+            let stats = request(&[(2, 6, 1), (1, 20, -2)]).stats().unwrap();
+            assert_eq!(stats.minimum, 2 + 1 + 1 - 2);
+            assert_eq!(stats.maximum, 12 + 1 + 20 - 2);
+            assert_close(stats.mean, 8.0 + 8.5);
+            // 2d6 has a variance of 35/6 and 1d20 one of 399/12.
+            assert_close(
+                stats.standard_deviation,
+                (35.0 / 6.0 + 399.0 / 12.0_f64).sqrt(),
+            );
+        }
+
+        #[test]
+        fn distributions_cover_every_total() {
+            let distribution = request(&[(2, 6, 1)]).distribution().unwrap();
+            let outcomes = &distribution.outcomes;
+            assert_eq!(outcomes.len(), 11);
+            assert_eq!(outcomes[0].total, 3);
+            assert_eq!(outcomes[10].total, 13);
+            assert_close(outcomes[0].probability, 1.0 / 36.0);
+            assert_close(outcomes[5].probability, 6.0 / 36.0);
+            assert_close(
+                outcomes.iter().map(|outcome| outcome.probability).sum(),
+                1.0,
+            );
+
+            let mean = outcomes
+                .iter()
+                .map(|outcome| outcome.total as f64 * outcome.probability)
+                .sum();
+            assert_close(mean, distribution.stats.mean);
+        }
+
+        #[test]
+        fn invalid_requests_have_no_stats() {
+            assert!(request(&[(1, 0, 0)]).stats().is_err());
+            assert!(request(&[(1000, 6, 0)]).distribution().is_err());
+        }
+    }
+
+    #[test]
+    fn roll_response_displays_each_die_and_the_total() {
+        let response = RollResponse {
+            rolls: vec![Rolls {
+                count: 2,
+                sides: 6,
+                modifier: -1,
+                rolls: vec![3, 5],
+                total: 7,
+            }],
+            total: 7,
+        };
+
+        assert_eq!(response.to_string(), "(3 of 6) + (5 of 6) - 1 = 7");
+    }
+}
+
+pub mod receipt {
+    //! Tamper evident roll receipts signed with Ed25519.
+    //!
+    //! A receipt carries the expression, the roll, the time it was made and a random nonce.
+    //! The signature covers `"dice-roll-receipt-v1\n"` followed by those fields serialized as
+    //! compact JSON, in that order. Anyone with the server's public key can check that a
+    //! receipt was issued by the server and hasn't been altered since.
+
+    use chrono::{DateTime, Utc};
+    use ed25519_dalek::{Signature, Signer as _, SigningKey, VerifyingKey};
+    use rand::RngCore;
+    use serde::{Deserialize, Serialize};
+    #[cfg(feature = "openapi")]
+    use utoipa::ToSchema;
+
+    use crate::RollResponse;
+
+    const CONTEXT: &[u8] = b"dice-roll-receipt-v1\n";
+    const NONCE_BYTES: usize = 16;
+
+    #[derive(Debug)]
+    pub enum ReceiptErrors {
+        InvalidSigningKeyError,
+        InvalidPublicKeyError,
+        MalformedReceiptError,
+        InvalidSignatureError,
+    }
+
+    impl std::fmt::Display for ReceiptErrors {
+        fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+            match self {
+                Self::InvalidSigningKeyError => {
+                    return f.write_str("Signing key must be 32 bytes encoded as hex.");
+                }
+                Self::InvalidPublicKeyError => {
+                    return f.write_str("Public key must be a 32 byte Ed25519 key encoded as hex.");
+                }
+                Self::MalformedReceiptError => {
+                    return f.write_str("Receipt is not valid receipt JSON.");
+                }
+                Self::InvalidSignatureError => {
+                    return f.write_str("Receipt signature is not valid, it was not issued with this key or has been altered.");
+                }
+            }
+        }
+    }
+
+    impl ReceiptErrors {
+        pub fn code(&self) -> &'static str {
+            match self {
+                Self::InvalidSigningKeyError => "INVALID_SIGNING_KEY",
+                Self::InvalidPublicKeyError => "INVALID_PUBLIC_KEY",
+                Self::MalformedReceiptError => "MALFORMED_RECEIPT",
+                Self::InvalidSignatureError => "INVALID_SIGNATURE",
+            }
+        }
+    }
+
+    /// A signed roll.
+    #[derive(Serialize, Deserialize, Clone, Debug)]
+    #[cfg_attr(feature = "openapi", derive(ToSchema))]
+    #[serde(deny_unknown_fields)]
+    pub struct Receipt {
+        #[cfg_attr(feature = "openapi", schema(example = "1d20 + 5"))]
+        pub expression: String,
+        pub result: RollResponse,
+        pub timestamp: DateTime<Utc>,
+        /// Random hex value making every receipt unique.
+        pub nonce: String,
+        /// Hex encoded Ed25519 signature.
+        pub signature: String,
+    }
+
+    #[derive(Serialize)]
+    struct SignedFields<'a> {
+        expression: &'a str,
+        result: &'a RollResponse,
+        timestamp: &'a DateTime<Utc>,
+        nonce: &'a str,
+    }
+
+    fn signed_message(
+        expression: &str,
+        result: &RollResponse,
+        timestamp: &DateTime<Utc>,
+        nonce: &str,
+    ) -> Vec<u8> {
+        let mut message = CONTEXT.to_vec();
+        let fields = SignedFields {
+            expression,
+            result,
+            timestamp,
+            nonce,
+        };
+        // Serializing plain structs and strings to JSON can not fail.
+        message.extend(serde_json::to_vec(&fields).unwrap_or_default());
+        return message;
+    }
+
+    impl Receipt {
+        pub fn from_json(json: &str) -> Result<Receipt, ReceiptErrors> {
+            return serde_json::from_str(json).map_err(|_| ReceiptErrors::MalformedReceiptError);
+        }
+
+        /// Checks the receipt's signature against a hex encoded Ed25519 public key.
+        pub fn verify(&self, public_key: &str) -> Result<(), ReceiptErrors> {
+            let public_key: [u8; 32] = match hex::decode(public_key.trim()) {
+                Ok(bytes) => bytes
+                    .try_into()
+                    .map_err(|_| ReceiptErrors::InvalidPublicKeyError)?,
+                Err(_) => return Err(ReceiptErrors::InvalidPublicKeyError),
+            };
+            let public_key = VerifyingKey::from_bytes(&public_key)
+                .map_err(|_| ReceiptErrors::InvalidPublicKeyError)?;
+            let signature: [u8; 64] = match hex::decode(&self.signature) {
+                Ok(bytes) => bytes
+                    .try_into()
+                    .map_err(|_| ReceiptErrors::InvalidSignatureError)?,
+                Err(_) => return Err(ReceiptErrors::InvalidSignatureError),
+            };
+            let message =
+                signed_message(&self.expression, &self.result, &self.timestamp, &self.nonce);
+
+            return public_key
+                .verify_strict(&message, &Signature::from_bytes(&signature))
+                .map_err(|_| ReceiptErrors::InvalidSignatureError);
+        }
+    }
+
+    /// Generates a new secret signing key, encoded as hex.
+    pub fn generate_signing_key() -> String {
+        let mut bytes = [0u8; 32];
+        rand::rng().fill_bytes(&mut bytes);
+        return hex::encode(bytes);
+    }
+
+    /// Issues receipts signed with the server's Ed25519 key.
+    pub struct Signer {
+        key: SigningKey,
+    }
+
+    impl Signer {
+        pub fn new(signing_key: &str) -> Result<Signer, ReceiptErrors> {
+            let bytes: [u8; 32] = match hex::decode(signing_key.trim()) {
+                Ok(bytes) => bytes
+                    .try_into()
+                    .map_err(|_| ReceiptErrors::InvalidSigningKeyError)?,
+                Err(_) => return Err(ReceiptErrors::InvalidSigningKeyError),
+            };
+
+            return Ok(Signer {
+                key: SigningKey::from_bytes(&bytes),
+            });
+        }
+
+        /// Hex encoded public key receipts can be verified with.
+        pub fn public_key(&self) -> String {
+            return hex::encode(self.key.verifying_key().as_bytes());
+        }
+
+        pub fn sign(&self, expression: String, result: RollResponse) -> Receipt {
+            let mut nonce = [0u8; NONCE_BYTES];
+            rand::rng().fill_bytes(&mut nonce);
+            let nonce = hex::encode(nonce);
+            let timestamp = Utc::now();
+            let message = signed_message(&expression, &result, &timestamp, &nonce);
+            let signature = hex::encode(self.key.sign(&message).to_bytes());
+
+            return Receipt {
+                expression,
+                result,
+                timestamp,
+                nonce,
+                signature,
+            };
+        }
+    }
 }